@@ -0,0 +1,990 @@
+//! # Lsp
+//!
+//! `folio lsp`, a language server for folio source over stdio.
+//! Open documents are loaded with their imports and assembled on every change to publish diagnostics.
+//! Completion and hover are driven by the [`spec`] table, and macros support go-to-definition and rename.
+//! [`run`] takes any [`Connection`], so the server can be driven in memory by a test client.
+
+use std::{collections::HashMap, error::Error as StdError};
+
+use compiler::{
+    assembler::Assembler,
+    error::{Error, Warning},
+    lint::{self, Level, Lint},
+    loader::Loader,
+    semantic,
+    spec::{self, Kind},
+    token::{Span, Token, TokenType},
+};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as LspNotification, PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, HoverRequest, Rename, Request as LspRequest},
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind,
+    OneOf, Position, PublishDiagnosticsParams, Range, RenameParams, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::manifest::Project;
+
+/// Run the server over stdio for the project in the working directory, until the client shuts it down.
+pub fn serve(target: Option<&str>) -> Result<(), Box<dyn StdError + Sync + Send>> {
+    let project = Project::discover(target)?;
+    let (connection, threads) = Connection::stdio();
+
+    run(connection, project)?;
+    threads.join()?;
+
+    Ok(())
+}
+
+/// Initialize the server on `connection`, and handle messages until the client shuts it down.
+pub fn run(
+    connection: Connection,
+    project: Project,
+) -> Result<(), Box<dyn StdError + Sync + Send>> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![":".to_string()]),
+            ..CompletionOptions::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server {
+        project,
+        documents: HashMap::new(),
+    };
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+
+                connection
+                    .sender
+                    .send(Message::Response(server.request(request)))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(notification) = server.notify(notification) {
+                    connection
+                        .sender
+                        .send(Message::Notification(notification))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Handle a request of type `R`.
+fn handle<R: LspRequest>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> Result<R::Result, String>,
+) -> Response {
+    let id = request.id.clone();
+
+    match request.extract::<R::Params>(R::METHOD) {
+        Ok((id, params)) => match handler(params) {
+            Ok(result) => Response::new_ok(id, result),
+            Err(message) => Response::new_err(id, ErrorCode::RequestFailed as i32, message),
+        },
+        Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+    }
+}
+
+/// A document loaded with its imports.
+struct Analysis {
+    loader: Loader,
+    /// Length of the document, whose tokens come first in the token stream.
+    length: usize,
+    /// The first error raised while loading or assembling the document.
+    error: Option<Error>,
+    /// Warnings about the entry points of the document, if it assembled.
+    warnings: Vec<Warning>,
+    /// Lints of the document, if it assembled.
+    lints: Vec<Lint>,
+}
+
+impl Analysis {
+    /// Where a span of the token stream lies, as a location in the document at `uri` or one of its imports.
+    fn location(&self, uri: &Url, span: Span) -> Option<Location> {
+        let (path, source, local) = self.loader.locate(span)?;
+
+        let uri = if span.start <= self.length {
+            uri.clone()
+        } else {
+            Url::from_file_path(path?).ok()?
+        };
+
+        Some(Location::new(uri, range(source, local)))
+    }
+}
+
+struct Server {
+    project: Project,
+    /// The contents of every open document.
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    fn request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            HoverRequest::METHOD => {
+                handle::<HoverRequest>(request, |params| Ok(self.hover(params)))
+            }
+            Completion::METHOD => {
+                handle::<Completion>(request, |params| Ok(self.completion(params)))
+            }
+            GotoDefinition::METHOD => {
+                handle::<GotoDefinition>(request, |params| Ok(self.definition(params)))
+            }
+            Rename::METHOD => handle::<Rename>(request, |params| self.rename(params)),
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{method}`"),
+            ),
+        }
+    }
+
+    /// Track the open documents, returning the diagnostics to publish.
+    fn notify(&mut self, notification: Notification) -> Option<Notification> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = notification
+                    .extract::<<DidOpenTextDocument as LspNotification>::Params>(
+                        DidOpenTextDocument::METHOD,
+                    )
+                    .ok()?;
+
+                let document = params.text_document;
+                self.documents.insert(document.uri.clone(), document.text);
+                document.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let params = notification
+                    .extract::<<DidChangeTextDocument as LspNotification>::Params>(
+                        DidChangeTextDocument::METHOD,
+                    )
+                    .ok()?;
+
+                // Documents are synchronized in full, so the last change holds the whole text.
+                let text = params.content_changes.into_iter().last()?.text;
+                self.documents
+                    .insert(params.text_document.uri.clone(), text);
+                params.text_document.uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = notification
+                    .extract::<<DidCloseTextDocument as LspNotification>::Params>(
+                        DidCloseTextDocument::METHOD,
+                    )
+                    .ok()?;
+
+                self.documents.remove(&params.text_document.uri);
+                return Some(publish(params.text_document.uri, Vec::new()));
+            }
+            _ => return None,
+        };
+
+        let diagnostics = self.diagnostics(&uri);
+        Some(publish(uri, diagnostics))
+    }
+
+    /// Load the document at `uri` with its imports, and assemble every macro.
+    fn analyze(&self, uri: &Url) -> Option<Analysis> {
+        let text = self.documents.get(uri)?;
+        let mut loader = Loader::new(self.project.imports.clone());
+        let path = uri.to_file_path().ok();
+
+        let result = loader
+            .load(uri.as_str(), path.as_deref(), text.clone())
+            .and_then(|_| {
+                let assembler =
+                    Assembler::new(loader.tokens()).with_environment(&self.project.environment);
+                let macros = assembler.parse_macros()?;
+
+                // Every macro is expanded, since a file need not have a `main` to be valid.
+                for name in macros.keys() {
+                    Assembler::expand(&macros, name)?;
+                }
+
+                let externs = assembler.externs();
+                let entries = Assembler::entries(&macros)
+                    .into_iter()
+                    .filter(|name| macros.contains_key(name))
+                    .map(|name| Ok((name, Assembler::expand(&macros, name)?)))
+                    .collect::<Result<Vec<_>, Error>>()?;
+
+                let lints = lint::lint(&macros, &entries, &loader, &self.project.lints)?;
+                let warnings = entries
+                    .iter()
+                    .flat_map(|(_, body)| semantic::analyze(body, &externs))
+                    .collect::<Vec<_>>();
+
+                Ok((warnings, lints))
+            });
+
+        let (error, warnings, lints) = match result {
+            Ok((warnings, lints)) => (None, warnings, lints),
+            Err(error) => (Some(error), Vec::new(), Vec::new()),
+        };
+
+        Some(Analysis {
+            loader,
+            length: text.len(),
+            error,
+            warnings,
+            lints,
+        })
+    }
+
+    fn diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        let Some(analysis) = self.analyze(uri) else {
+            return Vec::new();
+        };
+
+        let text = &self.documents[uri];
+        let mut diagnostics = Vec::new();
+
+        if let Some(error) = &analysis.error {
+            // Errors in imported files are reported at the start of the document.
+            let (range, message) = if error.span.start <= analysis.length {
+                (range(text, error.span), error.message.clone())
+            } else {
+                let file = analysis
+                    .loader
+                    .locate(error.span)
+                    .and_then(|(path, _, _)| path)
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default();
+
+                (Range::default(), format!("{} (in `{file}`)", error.message))
+            };
+
+            diagnostics.push(diagnostic(range, DiagnosticSeverity::ERROR, message));
+        }
+
+        // Warnings and lints in imported files are left for when they are opened, since they do not stop the document building.
+        let warnings = analysis
+            .warnings
+            .iter()
+            .map(|warning| {
+                (
+                    DiagnosticSeverity::WARNING,
+                    warning.span,
+                    warning.message.clone(),
+                )
+            })
+            .chain(analysis.lints.iter().map(|lint| {
+                let severity = match lint.level {
+                    Level::Deny => DiagnosticSeverity::ERROR,
+                    _ => DiagnosticSeverity::WARNING,
+                };
+
+                (severity, lint.span, lint.to_string())
+            }));
+
+        for (severity, span, message) in warnings {
+            if span.end <= analysis.length {
+                diagnostics.push(diagnostic(range(text, span), severity, message));
+            }
+        }
+
+        diagnostics
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let document = params.text_document_position_params;
+        let analysis = self.analyze(&document.text_document.uri)?;
+        let text = &self.documents[&document.text_document.uri];
+        let tokens = analysis.loader.tokens();
+        let index = token_at(&tokens, offset(text, document.position), analysis.length)?;
+        let token = tokens[index];
+
+        let contents = if let Some(instruction) = spec::instruction(token.slice) {
+            let mut contents = format!(
+                "**{}** `{:#04x}`\n\n{}\n",
+                instruction.name, instruction.opcode, instruction.description
+            );
+
+            for parameter in instruction.parameters {
+                contents.push_str(&format!("\n- {}", describe(parameter)));
+            }
+
+            contents
+        } else if token.ttype.is_parameter() {
+            describe(spec::parameter(token.slice)?)
+        } else if token.ttype != TokenType::Identifier {
+            return None;
+        } else if is_value(&tokens, index) {
+            let environment = &self.project.environment;
+
+            match (
+                environment.addresses.get(token.slice),
+                environment.decimals.get(token.slice),
+            ) {
+                (Some(address), _) => format!("`{}`: `{address:?}`", token.slice),
+                (_, Some(decimals)) => format!("`{}`: {decimals} decimals", token.slice),
+                _ => return None,
+            }
+        } else {
+            let span = definitions(&tokens).get(token.slice).copied()?;
+            let (_, source, local) = analysis.loader.locate(span)?;
+
+            format!("```\n{}\n```", definition(source, local))
+        };
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: contents,
+            }),
+            range: Some(range(text, token.span)),
+        })
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let document = params.text_document_position;
+        let analysis = self.analyze(&document.text_document.uri)?;
+        let text = &self.documents[&document.text_document.uri];
+        let tokens = analysis.loader.tokens();
+        let offset = offset(text, document.position);
+
+        // The tokens before the cursor, without the word being typed.
+        let mut before = tokens
+            .iter()
+            .take_while(|token| token.span.end <= offset)
+            .copied()
+            .collect::<Vec<_>>();
+
+        if before.last().is_some_and(|token| {
+            token.span.end == offset
+                && !matches!(token.ttype, TokenType::Colon | TokenType::Literal)
+        }) {
+            before.pop();
+        }
+
+        let environment = &self.project.environment;
+        let mut items = Vec::new();
+
+        match before.as_slice() {
+            // A value: names from the address book.
+            [.., key, colon] if colon.ttype == TokenType::Colon && key.ttype.is_parameter() => {
+                if spec::parameter(key.slice)
+                    .is_some_and(|parameter| parameter.kind == Kind::Address)
+                {
+                    items.extend(environment.addresses.iter().map(|(name, address)| {
+                        item(name, CompletionItemKind::CONSTANT, format!("{address:?}"))
+                    }));
+                }
+
+                return Some(CompletionResponse::Array(items));
+            }
+            // The unit of a literal.
+            [.., colon, literal]
+                if colon.ttype == TokenType::Colon && literal.ttype == TokenType::Literal =>
+            {
+                items.extend(environment.decimals.iter().map(|(symbol, decimals)| {
+                    item(
+                        symbol,
+                        CompletionItemKind::UNIT,
+                        format!("{decimals} decimals"),
+                    )
+                }));
+            }
+            _ => {}
+        }
+
+        // The keys of the instruction the cursor is in, which have not been given yet.
+        if let Some((start, instruction)) = current_instruction(&before) {
+            let given = before[start..]
+                .iter()
+                .filter(|token| token.ttype.is_parameter())
+                .map(|token| token.slice)
+                .collect::<Vec<_>>();
+
+            items.extend(
+                instruction
+                    .parameters
+                    .iter()
+                    .filter(|parameter| !given.contains(&parameter.name))
+                    .map(|parameter| CompletionItem {
+                        insert_text: Some(format!("{}: ", parameter.name)),
+                        sort_text: Some(format!("0{}", parameter.name)),
+                        ..item(
+                            parameter.name,
+                            CompletionItemKind::FIELD,
+                            describe(parameter),
+                        )
+                    }),
+            );
+        }
+
+        items.extend(spec::INSTRUCTIONS.iter().map(|instruction| {
+            item(
+                instruction.name,
+                CompletionItemKind::KEYWORD,
+                instruction.description.to_string(),
+            )
+        }));
+
+        items.extend(
+            definitions(&tokens)
+                .into_keys()
+                .map(|name| item(name, CompletionItemKind::FUNCTION, format!("macro {name}"))),
+        );
+
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let document = params.text_document_position_params;
+        let uri = &document.text_document.uri;
+        let analysis = self.analyze(uri)?;
+        let tokens = analysis.loader.tokens();
+        let index = token_at(
+            &tokens,
+            offset(&self.documents[uri], document.position),
+            analysis.length,
+        )?;
+
+        let macros = definitions(&tokens);
+
+        if !is_macro(&tokens, index, &macros) {
+            return None;
+        }
+
+        let span = macros[tokens[index].slice];
+
+        analysis
+            .location(uri, span)
+            .map(GotoDefinitionResponse::Scalar)
+    }
+
+    fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+        let document = params.text_document_position;
+        let uri = &document.text_document.uri;
+
+        let renamed = Token::lex(&params.new_name);
+        if !matches!(renamed.as_slice(), [token] if token.ttype == TokenType::Identifier && token.slice == params.new_name)
+        {
+            return Err(format!("`{}` is not a valid macro name", params.new_name));
+        }
+
+        let Some(analysis) = self.analyze(uri) else {
+            return Ok(None);
+        };
+
+        let tokens = analysis.loader.tokens();
+        let Some(index) = token_at(
+            &tokens,
+            offset(&self.documents[uri], document.position),
+            analysis.length,
+        ) else {
+            return Ok(None);
+        };
+
+        let name = tokens[index].slice;
+        let macros = definitions(&tokens);
+
+        if !is_macro(&tokens, index, &macros) {
+            return Err("only macros can be renamed".to_string());
+        }
+
+        if name != params.new_name && macros.contains_key(params.new_name.as_str()) {
+            return Err(format!(
+                "a macro named `{}` already exists",
+                params.new_name
+            ));
+        }
+
+        let mut changes = HashMap::<Url, Vec<TextEdit>>::new();
+
+        for (index, token) in tokens.iter().enumerate() {
+            if token.slice != name || !is_macro(&tokens, index, &macros) {
+                continue;
+            }
+
+            if let Some(location) = analysis.location(uri, token.span) {
+                changes
+                    .entry(location.uri)
+                    .or_default()
+                    .push(TextEdit::new(location.range, params.new_name.clone()));
+            }
+        }
+
+        Ok(Some(WorkspaceEdit::new(changes)))
+    }
+}
+
+fn publish(uri: Url, diagnostics: Vec<Diagnostic>) -> Notification {
+    Notification::new(
+        PublishDiagnostics::METHOD.to_string(),
+        PublishDiagnosticsParams::new(uri, diagnostics, None),
+    )
+}
+
+fn diagnostic(range: Range, severity: DiagnosticSeverity, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some("folio".to_string()),
+        message,
+        ..Diagnostic::default()
+    }
+}
+
+fn item(label: &str, kind: CompletionItemKind, detail: String) -> CompletionItem {
+    CompletionItem {
+        label: label.to_string(),
+        kind: Some(kind),
+        detail: Some(detail),
+        ..CompletionItem::default()
+    }
+}
+
+/// A one line description of a parameter.
+fn describe(parameter: &spec::Parameter) -> String {
+    let kind = format!("{:?}", parameter.kind).to_lowercase();
+    let default = parameter
+        .default
+        .map(|default| format!(", default {default}"))
+        .unwrap_or_default();
+
+    format!(
+        "`{}`: {kind}, {} bits{default}",
+        parameter.name, parameter.bits
+    )
+}
+
+/// The index of the token of the document under `offset`.
+fn token_at(tokens: &[Token], offset: usize, length: usize) -> Option<usize> {
+    tokens.iter().position(|token| {
+        token.span.start <= offset && offset <= token.span.end && token.span.end <= length
+    })
+}
+
+/// Whether the identifier at `index` is a parameter value, e.g. an address name or a token symbol,
+/// rather than a macro name.
+fn is_value(tokens: &[Token], index: usize) -> bool {
+    index > 0
+        && matches!(
+            tokens[index - 1].ttype,
+            TokenType::Colon | TokenType::Literal
+        )
+}
+
+/// Whether the token at `index` names a macro, in its definition or an invocation,
+/// rather than e.g. a parameter value or the kind of an `extern`.
+fn is_macro(tokens: &[Token], index: usize, macros: &HashMap<&str, Span>) -> bool {
+    tokens[index].ttype == TokenType::Identifier
+        && macros.contains_key(tokens[index].slice)
+        && !is_value(tokens, index)
+        && !(index > 0 && tokens[index - 1].ttype == TokenType::Extern)
+}
+
+/// The span of the name of every macro definition.
+fn definitions<'a>(tokens: &[Token<'a>]) -> HashMap<&'a str, Span> {
+    tokens
+        .windows(2)
+        .filter(|pair| pair[0].ttype == TokenType::Macro && pair[1].ttype == TokenType::Identifier)
+        .map(|pair| (pair[1].slice, pair[1].span))
+        .collect()
+}
+
+/// The source of a macro definition, from its name to its closing brace.
+fn definition(source: &str, name: Span) -> &str {
+    let end = source[name.start..]
+        .find('}')
+        .map(|end| name.start + end + 1)
+        .unwrap_or(source.len());
+
+    let start = source[..name.start].rfind("macro").unwrap_or(name.start);
+
+    &source[start..end]
+}
+
+/// The instruction whose parameters the end of `tokens` is within, and the index of its keyword.
+fn current_instruction(tokens: &[Token]) -> Option<(usize, &'static spec::Instruction)> {
+    for (index, token) in tokens.iter().enumerate().rev() {
+        if let Some(instruction) = spec::instruction(token.slice) {
+            return Some((index, instruction));
+        }
+
+        match token.ttype {
+            TokenType::Identifier if !is_value(tokens, index) => return None,
+            TokenType::OpenBrace | TokenType::CloseBrace | TokenType::Macro => return None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The LSP position of a byte offset, whose character is counted in UTF-16 code units.
+fn position(source: &str, offset: usize) -> Position {
+    let before = &source[..offset];
+    let start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[start..].encode_utf16().count() as u32,
+    )
+}
+
+/// The byte offset of an LSP position.
+fn offset(source: &str, position: Position) -> usize {
+    let mut start = 0;
+
+    for _ in 0..position.line {
+        match source[start..].find('\n') {
+            Some(newline) => start += newline + 1,
+            None => return source.len(),
+        }
+    }
+
+    let mut units = 0;
+
+    for (index, character) in source[start..].char_indices() {
+        if units >= position.character as usize || character == '\n' {
+            return start + index;
+        }
+
+        units += character.len_utf16();
+    }
+
+    source.len()
+}
+
+fn range(source: &str, span: Span) -> Range {
+    Range::new(position(source, span.start), position(source, span.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::{self, JoinHandle};
+
+    use lsp_server::RequestId;
+    use lsp_types::{
+        notification::Initialized, request::Initialize, DidChangeTextDocumentParams,
+        DidOpenTextDocumentParams, InitializeParams, InitializedParams,
+        TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+        TextDocumentPositionParams, VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+
+    const SOURCE: &str = "\
+extern pool 34
+
+macro main {
+\tprovide
+\tswap: useMax: 0 poolId: 34 amount0: 1 amount1: 2 sellAsset: 1
+}
+
+macro provide {
+\tallocate: useMax: 0 poolId: 34 deltaLiquidity: 10
+}
+";
+
+    /// A client talking to a server running on another thread.
+    struct Client {
+        connection: Connection,
+        server: Option<JoinHandle<()>>,
+        id: i32,
+    }
+
+    impl Client {
+        fn new() -> Self {
+            let (server, connection) = Connection::memory();
+            let server = thread::spawn(move || run(server, Project::default()).unwrap());
+            let mut client = Self {
+                connection,
+                server: Some(server),
+                id: 0,
+            };
+
+            let result = client.request::<Initialize>(InitializeParams::default());
+            assert_eq!(result.capabilities.rename_provider, Some(OneOf::Left(true)));
+            client.notify::<Initialized>(InitializedParams {});
+
+            client
+        }
+
+        fn notify<N: LspNotification>(&self, params: N::Params) {
+            self.connection
+                .sender
+                .send(Message::Notification(Notification::new(
+                    N::METHOD.to_string(),
+                    params,
+                )))
+                .unwrap();
+        }
+
+        fn send<R: LspRequest>(&mut self, params: R::Params) -> Response {
+            self.id += 1;
+            let id = RequestId::from(self.id);
+
+            self.connection
+                .sender
+                .send(Message::Request(Request::new(
+                    id.clone(),
+                    R::METHOD.to_string(),
+                    params,
+                )))
+                .unwrap();
+
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) if response.id == id => response,
+                message => panic!("expected a response to {id}, got {message:?}"),
+            }
+        }
+
+        fn request<R: LspRequest>(&mut self, params: R::Params) -> R::Result {
+            let response = self.send::<R>(params);
+            assert!(response.error.is_none(), "{:?}", response.error);
+            serde_json::from_value(response.result.unwrap()).unwrap()
+        }
+
+        /// The diagnostics published after a notification.
+        fn diagnostics(&self) -> PublishDiagnosticsParams {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Notification(notification)
+                    if notification.method == PublishDiagnostics::METHOD =>
+                {
+                    serde_json::from_value(notification.params).unwrap()
+                }
+                message => panic!("expected diagnostics, got {message:?}"),
+            }
+        }
+
+        fn open(&self, text: &str) -> PublishDiagnosticsParams {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri(),
+                    "folio".to_string(),
+                    1,
+                    text.to_string(),
+                ),
+            });
+
+            self.diagnostics()
+        }
+
+        fn change(&self, text: &str) -> PublishDiagnosticsParams {
+            self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri(), 2),
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: text.to_string(),
+                }],
+            });
+
+            self.diagnostics()
+        }
+    }
+
+    impl Drop for Client {
+        fn drop(&mut self) {
+            if thread::panicking() {
+                return;
+            }
+
+            let response = self.send::<lsp_types::request::Shutdown>(());
+            assert!(response.error.is_none());
+            self.notify::<lsp_types::notification::Exit>(());
+            self.server.take().unwrap().join().unwrap();
+        }
+    }
+
+    fn uri() -> Url {
+        Url::parse("file:///project/main.fvm").unwrap()
+    }
+
+    /// A position within the `occurrence`th match of `needle` in `source`.
+    fn at(source: &str, needle: &str, occurrence: usize) -> Position {
+        let offset = source.match_indices(needle).nth(occurrence).unwrap().0;
+        position(source, offset + 1)
+    }
+
+    fn document(position: Position) -> TextDocumentPositionParams {
+        TextDocumentPositionParams::new(TextDocumentIdentifier::new(uri()), position)
+    }
+
+    fn rename(client: &mut Client, position: Position, name: &str) -> Response {
+        client.send::<Rename>(RenameParams {
+            text_document_position: document(position),
+            new_name: name.to_string(),
+            work_done_progress_params: Default::default(),
+        })
+    }
+
+    #[test]
+    fn diagnostics() {
+        let client = Client::new();
+
+        let published = client.open(SOURCE);
+        assert_eq!(published.uri, uri());
+        assert!(
+            published.diagnostics.is_empty(),
+            "{:?}",
+            published.diagnostics
+        );
+
+        let broken = SOURCE.replace("\tprovide\n", "\tmissing\n");
+        let published = client.change(&broken);
+        let [diagnostic] = published.diagnostics.as_slice() else {
+            panic!("expected one diagnostic, got {:?}", published.diagnostics);
+        };
+
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert!(
+            diagnostic.message.contains("missing"),
+            "{}",
+            diagnostic.message
+        );
+        let offset = broken.find("missing").unwrap();
+        assert_eq!(
+            diagnostic.range,
+            range(&broken, Span::new(offset, offset + 7))
+        );
+
+        assert!(client.change(SOURCE).diagnostics.is_empty());
+    }
+
+    #[test]
+    fn hover() {
+        let mut client = Client::new();
+        client.open(SOURCE);
+
+        let hover = client
+            .request::<HoverRequest>(HoverParams {
+                text_document_position_params: document(at(SOURCE, "swap", 0)),
+                work_done_progress_params: Default::default(),
+            })
+            .unwrap();
+
+        let HoverContents::Markup(contents) = hover.contents else {
+            panic!("expected markup");
+        };
+
+        assert!(contents.value.starts_with("**swap**"));
+        assert!(contents.value.contains("`sellAsset`"));
+
+        let hover = client
+            .request::<HoverRequest>(HoverParams {
+                text_document_position_params: document(at(SOURCE, "provide", 0)),
+                work_done_progress_params: Default::default(),
+            })
+            .unwrap();
+
+        let HoverContents::Markup(contents) = hover.contents else {
+            panic!("expected markup");
+        };
+
+        assert!(contents.value.contains("macro provide {"));
+    }
+
+    #[test]
+    fn definition() {
+        let mut client = Client::new();
+        client.open(SOURCE);
+
+        let response = client
+            .request::<GotoDefinition>(GotoDefinitionParams {
+                text_document_position_params: document(at(SOURCE, "provide", 0)),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .unwrap();
+
+        let GotoDefinitionResponse::Scalar(location) = response else {
+            panic!("expected a single location");
+        };
+
+        let offset = SOURCE.match_indices("provide").nth(1).unwrap().0;
+        assert_eq!(location.uri, uri());
+        assert_eq!(location.range, range(SOURCE, Span::new(offset, offset + 7)));
+    }
+
+    #[test]
+    fn rename_macro() {
+        let mut client = Client::new();
+        client.open(SOURCE);
+
+        let response = rename(&mut client, at(SOURCE, "provide", 0), "supply");
+        let edit: WorkspaceEdit = serde_json::from_value(response.result.unwrap()).unwrap();
+        let edits = &edit.changes.unwrap()[&uri()];
+
+        let mut renamed = SOURCE.to_string();
+        let mut edits = edits.clone();
+        edits.sort_by_key(|edit| std::cmp::Reverse(offset(SOURCE, edit.range.start)));
+
+        for edit in edits {
+            let span = offset(SOURCE, edit.range.start)..offset(SOURCE, edit.range.end);
+            renamed.replace_range(span, &edit.new_text);
+        }
+
+        assert_eq!(renamed, SOURCE.replace("provide", "supply"));
+    }
+
+    #[test]
+    fn rename_conflict() {
+        let mut client = Client::new();
+        client.open(SOURCE);
+
+        let response = rename(&mut client, at(SOURCE, "provide", 0), "main");
+        assert_eq!(
+            response.error.unwrap().message,
+            "a macro named `main` already exists"
+        );
+
+        let response = rename(&mut client, at(SOURCE, "provide", 0), "swap");
+        assert_eq!(
+            response.error.unwrap().message,
+            "`swap` is not a valid macro name"
+        );
+    }
+
+    #[test]
+    fn rename_only_macros() {
+        let mut client = Client::new();
+
+        // `pool` is both the kind of an extern and the name of a macro.
+        let source = "extern pool 34\n\nmacro main {\n\tpool\n}\n\nmacro pool {}\n";
+        client.open(source);
+
+        let response = rename(&mut client, at(source, "pool", 1), "shared");
+        let edit: WorkspaceEdit = serde_json::from_value(response.result.unwrap()).unwrap();
+        let edits = &edit.changes.unwrap()[&uri()];
+
+        assert_eq!(edits.len(), 2);
+        assert!(edits
+            .iter()
+            .all(|edit| edit.range.start.line != 0 && edit.new_text == "shared"));
+
+        let response = rename(&mut client, at(source, "pool", 0), "shared");
+        assert_eq!(
+            response.error.unwrap().message,
+            "only macros can be renamed"
+        );
+    }
+}
@@ -1,7 +1,35 @@
-use std::fs;
+mod lsp;
+mod manifest;
+mod repl;
+mod sign;
+mod simulate;
+mod stats;
+mod watch;
 
-use clap::{Parser, Subcommand};
-use compiler::{assembler::Assembler, codegen::Codegen, token::Token};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use colored::Colorize;
+use compiler::{
+    assembler::{Assembler, Body, Expression, Stack},
+    codegen::Codegen,
+    disassembler, formatter, json, lint,
+    loader::Loader,
+    opcode::Address,
+    optimizer, semantic,
+    simulator::State,
+    sourcemap::{Frame, Origin, SourceMap},
+    spec,
+    token::{Span, Spanned},
+    transaction::{self, Entrypoint},
+};
+use manifest::Project;
+use sign::SignArgs;
 
 #[derive(Parser)]
 #[clap(about, version, author)]
@@ -12,32 +40,1426 @@ struct Value {
 
 #[derive(Subcommand)]
 enum Commands {
-    Build { path: String },
+    /// Compile source files into FVM payloads.
+    Build(Box<BuildArgs>),
+    /// Validate source files without generating any output.
+    Check {
+        /// Source files or glob patterns, or `-` to read from stdin.
+        /// Defaults to every source file of the project.
+        paths: Vec<String>,
+        /// Build target from `folio.toml` whose definitions override the defaults.
+        #[clap(long)]
+        target: Option<String>,
+        /// Macro to build, instead of every `pub` macro, or `main` if there are none.
+        #[clap(long)]
+        entry: Option<String>,
+        /// Syntax of the source files, inferred from their extensions by default.
+        #[clap(long, value_enum)]
+        input_format: Option<InputFormat>,
+    },
+    /// Decode a payload into its instructions and their fields.
+    Disassemble {
+        /// Payload as hex, or a file holding it as hex or raw bytes.
+        payload: String,
+        /// Source map written by `folio build --source-map`, to show where each instruction came from.
+        #[clap(long)]
+        source_map: Option<PathBuf>,
+    },
+    /// Format source files in the canonical layout.
+    Fmt {
+        /// Source files or glob patterns, or `-` to format stdin to stdout.
+        /// Defaults to every source file of the project.
+        paths: Vec<String>,
+        /// Report files that are not formatted, instead of formatting them.
+        #[clap(long)]
+        check: bool,
+    },
+    /// Create a new project with a manifest and an example strategy.
+    Init {
+        /// Directory to create the project in.
+        #[clap(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Run a language server over stdio.
+    Lsp {
+        /// Build target from `folio.toml` whose definitions override the defaults.
+        #[clap(long)]
+        target: Option<String>,
+    },
+    /// Compose a program interactively, seeing each instruction encoded as it is entered.
+    Repl {
+        /// Build target from `folio.toml` whose definitions override the defaults.
+        #[clap(long)]
+        target: Option<String>,
+    },
+    /// Run a program against a model of the FVM, printing the effect of each instruction.
+    Simulate {
+        /// Source file, or `-` to read from stdin.
+        path: String,
+        /// JSON file of the pairs, pools and balances the program starts from.
+        /// Defaults to an empty state.
+        #[clap(long)]
+        state: Option<PathBuf>,
+        /// Build target from `folio.toml` whose definitions override the defaults.
+        #[clap(long)]
+        target: Option<String>,
+        /// Macro to simulate, instead of every `pub` macro, or `main` if there are none.
+        #[clap(long)]
+        entry: Option<String>,
+        /// Syntax of the source file, inferred from its extension by default.
+        #[clap(long, value_enum)]
+        input_format: Option<InputFormat>,
+        /// Simulate the program as written, without optimizing it.
+        #[clap(long)]
+        no_opt: bool,
+        /// Merge each `allocate` with a `deallocate` from the same pool right after it,
+        /// which may keep the program from reverting where the `allocate` could not be paid for.
+        #[clap(long, conflicts_with = "no_opt")]
+        net_liquidity: bool,
+    },
+    /// Print the specification of every instruction.
+    Spec {
+        /// Print the specification as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    /// Source files or glob patterns, or `-` to read from stdin.
+    /// Defaults to every source file of the project.
+    paths: Vec<String>,
+    /// Build target from `folio.toml` whose definitions override the defaults.
+    #[clap(long)]
+    target: Option<String>,
+    /// Macro to build, instead of every `pub` macro, or `main` if there are none.
+    #[clap(long)]
+    entry: Option<String>,
+    /// Syntax of the source files, inferred from their extensions by default.
+    #[clap(long, value_enum)]
+    input_format: Option<InputFormat>,
+    /// Write the output to a file instead of stdout.
+    #[clap(short, long, conflicts_with = "out_dir")]
+    output: Option<String>,
+    /// Write one artifact per source file and entry point into a directory.
+    #[clap(long)]
+    out_dir: Option<String>,
+    /// Format of the output.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Hex)]
+    format: OutputFormat,
+    /// Only print errors, without warnings or progress.
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print the encoded instructions and compilation time to stderr.
+    #[clap(short, long)]
+    verbose: bool,
+    /// Encode the program as written, without merging or removing instructions.
+    #[clap(long)]
+    no_opt: bool,
+    /// Merge each `allocate` with a `deallocate` from the same pool right after it,
+    /// which may keep the program from reverting where the `allocate` could not be paid for.
+    #[clap(long, conflicts_with = "no_opt")]
+    net_liquidity: bool,
+    /// Print the calldata size and gas of each instruction and macro to stderr.
+    #[clap(long)]
+    stats: bool,
+    /// Write a source map next to each artifact, mapping the byte range of each instruction
+    /// to its location in source and the macros it was expanded from.
+    #[clap(long)]
+    source_map: bool,
+    /// Function of the FVM contract the `calldata` and `transaction` formats call:
+    /// `fallback`, with the bare payload, or `multiprocess`, with the payload abi-encoded.
+    #[clap(long, default_value_t = Entrypoint::Fallback)]
+    entrypoint: Entrypoint,
+    /// Address of the FVM contract, or its name in the address book, for the `transaction` format.
+    #[clap(long)]
+    to: Option<String>,
+    #[clap(flatten)]
+    sign: SignArgs,
+    /// Rebuild whenever a source file, an import or `folio.toml` changes,
+    /// printing the instructions that changed.
+    #[clap(short, long, conflicts_with = "sign")]
+    watch: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    Fvm,
+    Json,
+    Yaml,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// The payload as hex.
+    Hex,
+    /// The payload as raw bytes.
+    Bin,
+    /// The encoded instructions and payload as JSON.
+    Json,
+    /// The calldata of a call to the FVM contract, as `0x` prefixed hex.
+    Calldata,
+    /// An unsigned transaction to the FVM contract at `--to`, as JSON.
+    Transaction,
+}
+
+impl OutputFormat {
+    /// File extension of artifacts in this format.
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Hex => "hex",
+            OutputFormat::Bin => "bin",
+            OutputFormat::Json => "json",
+            OutputFormat::Calldata => "calldata",
+            OutputFormat::Transaction => "tx.json",
+        }
+    }
+
+    /// Render a compiled program, its encoded instructions and their payload,
+    /// called through `entrypoint` of the FVM contract at `to`.
+    fn render(
+        &self,
+        instructions: &[String],
+        bytes: &[u8],
+        entrypoint: Entrypoint,
+        to: Option<Address>,
+    ) -> Vec<u8> {
+        let payload = hex::encode(bytes);
+
+        match self {
+            OutputFormat::Hex => format!("{payload}\n").into_bytes(),
+            OutputFormat::Bin => bytes.to_vec(),
+            OutputFormat::Json => {
+                let json = serde_json::json!({
+                    "instructions": instructions,
+                    "payload": payload,
+                });
+
+                format!("{json:#}\n").into_bytes()
+            }
+            OutputFormat::Calldata => {
+                let calldata = transaction::calldata(bytes, entrypoint);
+                format!("0x{}\n", hex::encode(calldata)).into_bytes()
+            }
+            OutputFormat::Transaction => {
+                let request = transaction::request(bytes, entrypoint, to.unwrap());
+                format!("{}\n", serde_json::to_string_pretty(&request).unwrap()).into_bytes()
+            }
+        }
+    }
+}
+
+impl InputFormat {
+    fn infer(path: &str) -> Self {
+        match Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("json") => InputFormat::Json,
+            Some("yaml" | "yml") => InputFormat::Yaml,
+            _ => InputFormat::Fvm,
+        }
+    }
+}
+
+/// A source file, read from disk or stdin.
+struct Source {
+    /// Path of the file, or `-` for stdin.
+    path: String,
+    contents: String,
+}
+
+impl Source {
+    /// Name of artifacts built from this source.
+    fn stem(&self) -> &str {
+        match self.path.as_str() {
+            "-" => "stdin",
+            path => Path::new(path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(path),
+        }
+    }
+
+    /// Name of the artifact built from the entry point `entry` of this source.
+    fn artifact(&self, entry: &str) -> String {
+        match entry {
+            "main" => self.stem().to_string(),
+            entry => format!("{}.{entry}", self.stem()),
+        }
+    }
+
+    /// Path of the artifact built from the entry point `entry` of this source into `out_dir`.
+    fn output(&self, out_dir: &str, entry: &str, extension: &str) -> String {
+        Path::new(out_dir)
+            .join(format!("{}.{extension}", self.artifact(entry)))
+            .display()
+            .to_string()
+    }
+
+    /// Name of the source in diagnostics.
+    fn name(&self) -> &str {
+        match self.path.as_str() {
+            "-" => "<stdin>",
+            path => path,
+        }
+    }
+}
+
+/// Read every source, expanding glob patterns, and reading `-` from `stdin`.
+/// Each source that cannot be read is an error of its own, so that the others are still built.
+fn read_sources(paths: &[String], mut stdin: impl Read) -> Vec<Result<Source, String>> {
+    let mut sources = Vec::new();
+
+    for pattern in paths {
+        if pattern == "-" {
+            let mut contents = String::new();
+
+            sources.push(
+                stdin
+                    .read_to_string(&mut contents)
+                    .map(|_| Source {
+                        path: pattern.clone(),
+                        contents,
+                    })
+                    .map_err(|error| format!("could not read stdin: {error}")),
+            );
+
+            continue;
+        }
+
+        let matches = match glob::glob(pattern) {
+            Ok(matches) => matches.flatten().collect::<Vec<_>>(),
+            Err(error) => {
+                sources.push(Err(format!("invalid pattern `{pattern}`: {error}")));
+                continue;
+            }
+        };
+
+        // A path that is not a pattern and does not exist is reported when it is read.
+        let paths = if matches.is_empty() && !pattern.contains(['*', '?', '[']) {
+            vec![pattern.into()]
+        } else if matches.is_empty() {
+            sources.push(Err(format!("no files match `{pattern}`")));
+            continue;
+        } else {
+            matches
+        };
+
+        for path in paths {
+            let path = path.display().to_string();
+
+            sources.push(
+                fs::read_to_string(&path)
+                    .map(|contents| Source {
+                        path: path.clone(),
+                        contents,
+                    })
+                    .map_err(|error| format!("could not read `{path}`: {error}")),
+            );
+        }
+    }
+
+    sources
+}
+
+/// Print an error that has no source location.
+fn report(message: &str) {
+    eprintln!("{}: {message}", "error".red().bold());
+}
+
+/// An entry point built from a source file.
+struct Program {
+    name: String,
+    codegen: Codegen,
+    /// Bytes the optimizer removed from the payload.
+    saved: usize,
+    /// Where each opcode came from in source.
+    origins: Vec<Origin>,
+}
+
+impl Program {
+    /// Generate the code of an expanded entry point, optimizing it first with `optimize`, if any.
+    /// `stacks` holds the macros each expression was expanded from, and `loader` the source it was parsed from, if any.
+    fn new(
+        name: &str,
+        body: Body,
+        stacks: Vec<Stack>,
+        optimize: Option<optimizer::Options>,
+        loader: Option<&Loader>,
+    ) -> Self {
+        let unoptimized = Codegen::new(
+            body.iter()
+                .map(|expression| expression.node.clone())
+                .collect(),
+        );
+
+        let expressions = match optimize {
+            Some(options) => optimizer::optimize_indexed(&body, options),
+            None => body
+                .iter()
+                .map(|expression| expression.node.clone())
+                .enumerate()
+                .collect(),
+        };
+
+        let location = |span| loader.and_then(|loader| loader.location(span));
+
+        let origins = expressions
+            .iter()
+            .map(|(index, _)| Origin {
+                location: location(body[*index].span),
+                stack: stacks[*index]
+                    .iter()
+                    .map(|frame| Frame {
+                        name: frame.node.to_string(),
+                        location: location(frame.span),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let codegen = Codegen::new(
+            expressions
+                .into_iter()
+                .map(|(_, expression)| expression)
+                .collect(),
+        );
+
+        Self {
+            name: name.to_string(),
+            saved: unoptimized.size() - codegen.size(),
+            codegen,
+            origins,
+        }
+    }
+}
+
+/// Lex, parse, expand and validate a source file, printing any error, and any warning unless `quiet` is set.
+/// Returns each entry point built from the source, optimized with `optimize`, if any.
+fn parse(
+    source: &Source,
+    input_format: InputFormat,
+    project: &Project,
+    entry: Option<&str>,
+    optimize: Option<optimizer::Options>,
+    quiet: bool,
+    dependencies: &mut BTreeSet<PathBuf>,
+) -> Option<Vec<Program>> {
+    if let Ok(path) = Path::new(&source.path).canonicalize() {
+        dependencies.insert(path);
+    }
+
+    let format = match input_format {
+        InputFormat::Fvm => {
+            let mut loader = Loader::new(project.imports.clone());
+            let path = (source.path != "-").then(|| Path::new(&source.path));
+
+            let result = loader
+                .load(source.name(), path, source.contents.clone())
+                .and_then(|_| {
+                    let assembler =
+                        Assembler::new(loader.tokens()).with_environment(&project.environment);
+
+                    let macros = assembler.parse_macros()?;
+                    let names = match entry {
+                        Some(entry) => vec![entry],
+                        None => Assembler::entries(&macros),
+                    };
+
+                    let mut entries = Vec::new();
+                    let mut stacks = Vec::new();
+
+                    for name in names {
+                        let (body, stack): (Body, Vec<Stack>) =
+                            Assembler::trace(&macros, name)?.into_iter().unzip();
+                        entries.push((name, body));
+                        stacks.push(stack);
+                    }
+
+                    let lints = lint::lint(&macros, &entries, &loader, &project.lints)?;
+
+                    Ok((entries, stacks, assembler.externs(), lints))
+                });
+
+            dependencies.extend(loader.paths().map(Path::to_path_buf));
+
+            return match result {
+                Ok((entries, stacks, externs, lints)) => {
+                    // Entry points often share macros, so each warning is only reported once.
+                    let mut warnings = entries
+                        .iter()
+                        .flat_map(|(_, body)| semantic::analyze(body, &externs))
+                        .collect::<Vec<_>>();
+                    warnings.sort_by(|a, b| {
+                        (a.span.start, &a.message).cmp(&(b.span.start, &b.message))
+                    });
+                    warnings.dedup();
+
+                    if !quiet {
+                        for warning in &warnings {
+                            loader.warn(warning);
+                        }
+                    }
+
+                    let mut denied = false;
+
+                    for lint in &lints {
+                        match lint.diagnostic() {
+                            Ok(_) if quiet => {}
+                            Ok(warning) => loader.warn(&warning),
+                            Err(error) => {
+                                loader.emit(&error);
+                                denied = true;
+                            }
+                        }
+                    }
+
+                    if denied {
+                        return None;
+                    }
+
+                    Some(
+                        entries
+                            .into_iter()
+                            .zip(stacks)
+                            .map(|((name, body), stacks)| {
+                                Program::new(name, body, stacks, optimize, Some(&loader))
+                            })
+                            .collect(),
+                    )
+                }
+                Err(error) => {
+                    loader.emit(&error);
+                    None
+                }
+            };
+        }
+        InputFormat::Json => json::Format::Json,
+        InputFormat::Yaml => json::Format::Yaml,
+    };
+
+    if entry.is_some() {
+        report(&format!(
+            "`--entry` does not apply to `{}`, which has a single program",
+            source.name()
+        ));
+        return None;
+    }
+
+    match json::parse(&source.contents, format) {
+        Ok(opcodes) => {
+            let body: Body = opcodes
+                .into_iter()
+                .map(|opcode| Spanned::new(Expression::Opcode(opcode), Span::default()))
+                .collect();
+
+            let stacks = vec![Vec::new(); body.len()];
+
+            Some(vec![Program::new("main", body, stacks, optimize, None)])
+        }
+        Err(error) => {
+            error.emit(source.name(), &source.contents);
+            None
+        }
+    }
+}
+
+/// Check a source file as `folio build` builds it by default, printing any error.
+/// Returns whether every entry point parses, and encodes into a payload.
+fn check(
+    source: &Source,
+    input_format: InputFormat,
+    project: &Project,
+    entry: Option<&str>,
+) -> bool {
+    let Some(programs) = parse(
+        source,
+        input_format,
+        project,
+        entry,
+        Some(optimizer::Options::default()),
+        false,
+        &mut BTreeSet::new(),
+    ) else {
+        return false;
+    };
+
+    let mut valid = true;
+
+    for Program { name, codegen, .. } in &programs {
+        if let Err(message) = codegen.payload() {
+            report(&format!(
+                "could not encode `{name}` in {}: {message}",
+                source.name()
+            ));
+            valid = false;
+        }
+    }
+
+    valid
+}
+
+/// Load the project the working directory belongs to, and the paths to compile.
+fn project(paths: &[String], target: Option<&str>) -> Result<(Project, Vec<String>), String> {
+    let project = Project::discover(target)?;
+
+    let paths = match paths {
+        [] if project.sources.is_empty() => {
+            return Err(format!(
+                "no paths given, and no `{}` found",
+                manifest::FILE_NAME
+            ))
+        }
+        [] => project.source_patterns(),
+        paths => paths.to_vec(),
+    };
+
+    Ok((project, paths))
+}
+
+/// Simulate each entry point of a source file from the same initial state.
+/// Returns whether every entry point ran without reverting.
+fn simulate(
+    path: &str,
+    state: Option<&Path>,
+    target: Option<&str>,
+    entry: Option<&str>,
+    input_format: Option<InputFormat>,
+    optimize: Option<optimizer::Options>,
+) -> bool {
+    let (project, mut sources) = match project(&[path.to_string()], target) {
+        Ok((project, paths)) => (project, read_sources(&paths, io::stdin())),
+        Err(message) => {
+            report(&message);
+            return false;
+        }
+    };
+
+    let source = match sources.remove(0) {
+        Ok(source) => source,
+        Err(message) => {
+            report(&message);
+            return false;
+        }
+    };
+
+    let state = match state {
+        Some(path) => match simulate::load(path, &project.environment) {
+            Ok(state) => state,
+            Err(message) => {
+                report(&message);
+                return false;
+            }
+        },
+        None => State::new(),
+    };
+
+    let input_format = input_format.unwrap_or_else(|| InputFormat::infer(&source.path));
+    let Some(programs) = parse(
+        &source,
+        input_format,
+        &project,
+        entry,
+        optimize,
+        false,
+        &mut BTreeSet::new(),
+    ) else {
+        return false;
+    };
+
+    let mut reverted = false;
+
+    for Program { name, codegen, .. } in &programs {
+        if programs.len() > 1 {
+            println!("{}", format!("{} ({name})", source.name()).bold());
+        }
+
+        // The encoded program is simulated, so that what runs is exactly what would be sent.
+        let payload = match codegen.payload() {
+            Ok(payload) => payload,
+            Err(message) => {
+                report(&format!("could not encode `{name}`: {message}"));
+                reverted = true;
+                continue;
+            }
+        };
+
+        match state.simulate_bytecode(&payload) {
+            Ok(simulation) => {
+                simulate::print(&simulation, &project.environment);
+                reverted |= simulation.revert.is_some();
+            }
+            Err(message) => {
+                report(&format!("could not decode `{name}`: {message}"));
+                reverted = true;
+            }
+        }
+    }
+
+    !reverted
+}
+
+/// The outcome of building every source once.
+#[derive(Default)]
+struct Build {
+    /// Whether any source failed to build.
+    failed: bool,
+    /// Every file read by the build, including imports and the manifest.
+    dependencies: BTreeSet<PathBuf>,
+    /// Encoded instructions of each artifact, by source and entry point.
+    artifacts: BTreeMap<(String, String), Vec<String>>,
+}
+
+/// Build every source, printing diagnostics and writing the artifacts.
+fn build(args: &BuildArgs) -> Build {
+    let mut build = Build::default();
+
+    let (project, patterns) = match project(&args.paths, args.target.as_deref()) {
+        Ok(project) => project,
+        Err(message) => {
+            report(&message);
+            build.failed = true;
+            return build;
+        }
+    };
+
+    build.dependencies.extend(project.manifest.clone());
+
+    let sources = read_sources(&patterns, io::stdin());
+
+    // Building a whole project writes into its output directory.
+    let out_dir = &match (&args.out_dir, &args.output) {
+        (None, None) if args.paths.is_empty() => project
+            .out_dir
+            .as_ref()
+            .map(|out_dir| out_dir.display().to_string()),
+        (out_dir, _) => out_dir.clone(),
+    };
+
+    if sources.len() > 1 && out_dir.is_none() {
+        report("building more than one source requires `--out-dir`");
+        build.failed = true;
+        return build;
+    }
+
+    let to = match (&args.to, args.format) {
+        (Some(to), _) => match project.environment.address(to) {
+            Some(address) => Some(address),
+            None => {
+                report(&format!("unknown address `{to}`"));
+                build.failed = true;
+                return build;
+            }
+        },
+        (None, OutputFormat::Transaction) => {
+            report("`--format transaction` requires `--to`");
+            build.failed = true;
+            return build;
+        }
+        (None, _) => None,
+    };
+
+    let signer = match args.sign.signer(args.paths.iter().any(|path| path == "-")) {
+        Ok(signer) => signer,
+        Err(message) => {
+            report(&message);
+            build.failed = true;
+            return build;
+        }
+    };
+
+    if args.source_map && args.output.is_none() && out_dir.is_none() {
+        report("`--source-map` requires `--output` or `--out-dir`");
+        build.failed = true;
+        return build;
+    }
+
+    if let Some(out_dir) = out_dir {
+        if let Err(error) = fs::create_dir_all(out_dir) {
+            report(&format!("could not create `{out_dir}`: {error}"));
+            build.failed = true;
+            return build;
+        }
+    }
+
+    for source in sources {
+        let start = std::time::Instant::now();
+
+        let source = match source {
+            Ok(source) => source,
+            Err(message) => {
+                report(&message);
+                build.failed = true;
+                continue;
+            }
+        };
+
+        let input_format = args
+            .input_format
+            .unwrap_or_else(|| InputFormat::infer(&source.path));
+
+        let Some(programs) = parse(
+            &source,
+            input_format,
+            &project,
+            args.entry.as_deref(),
+            (!args.no_opt).then_some(optimizer::Options {
+                net_liquidity: args.net_liquidity,
+            }),
+            args.quiet,
+            &mut build.dependencies,
+        ) else {
+            build.failed = true;
+            continue;
+        };
+
+        if programs.len() > 1 && out_dir.is_none() {
+            report(&format!(
+                "`{}` has more than one entry point, which requires `--entry` or `--out-dir`",
+                source.name()
+            ));
+            build.failed = true;
+            continue;
+        }
+
+        for Program {
+            name: entry,
+            codegen,
+            saved,
+            origins,
+        } in programs
+        {
+            let encoded = codegen.encode();
+
+            if args.verbose {
+                eprintln!("{encoded:#?}");
+            }
+
+            let payload = match codegen.payload() {
+                Ok(payload) => payload,
+                Err(message) => {
+                    report(&format!(
+                        "could not encode `{entry}` in {}: {message}",
+                        source.name()
+                    ));
+                    build.failed = true;
+                    continue;
+                }
+            };
+
+            let rendered = match &signer {
+                Some((wallet, parameters)) => {
+                    // Signing several programs with the same nonce would make all but one invalid.
+                    if !build.artifacts.is_empty() {
+                        report("`--sign` signs a single program, which `--entry` selects");
+                        build.failed = true;
+                        break;
+                    }
+
+                    match transaction::sign(
+                        &payload,
+                        args.entrypoint,
+                        to.unwrap(),
+                        parameters,
+                        wallet,
+                    ) {
+                        Ok(signed) => format!("{signed}\n").into_bytes(),
+                        Err(message) => {
+                            report(&message);
+                            build.failed = true;
+                            continue;
+                        }
+                    }
+                }
+                None => args.format.render(&encoded, &payload, args.entrypoint, to),
+            };
+
+            let duration = start.elapsed();
+
+            if args.verbose {
+                eprintln!(
+                    "Compilation of `{entry}` in {} finished in: {duration:?}",
+                    source.name()
+                );
+            }
+
+            if saved > 0 && !args.quiet {
+                eprintln!(
+                    "Optimized `{entry}` in {}, saving {saved} bytes",
+                    source.name()
+                );
+            }
+
+            if args.stats {
+                stats::print(
+                    &format!("{} ({entry})", source.name()),
+                    &codegen,
+                    &origins,
+                    &payload,
+                    args.entrypoint,
+                );
+            }
+
+            build
+                .artifacts
+                .insert((source.name().to_string(), entry.clone()), encoded);
+
+            let output = match (&args.output, out_dir) {
+                (Some(output), _) => output.clone(),
+                (None, Some(out_dir)) => source.output(
+                    out_dir,
+                    &entry,
+                    match signer {
+                        Some(_) => "tx",
+                        None => args.format.extension(),
+                    },
+                ),
+                (None, None) => {
+                    io::stdout().write_all(&rendered).unwrap();
+                    continue;
+                }
+            };
+
+            if let Err(error) = fs::write(&output, &rendered) {
+                report(&format!("could not write `{output}`: {error}"));
+                build.failed = true;
+                continue;
+            }
+
+            if !args.quiet {
+                eprintln!("Wrote {} bytes to {output}", rendered.len());
+            }
+
+            if args.source_map {
+                let path = format!("{output}.map");
+                let source_map = SourceMap::new(&codegen, origins);
+                let json = serde_json::to_string_pretty(&source_map).unwrap();
+
+                if let Err(error) = fs::write(&path, json + "\n") {
+                    report(&format!("could not write `{path}`: {error}"));
+                    build.failed = true;
+                }
+            }
+        }
+    }
+
+    build
+}
+
+/// Decode a payload given as hex or read from a file, annotating each instruction from a source map.
+fn disassemble(payload: &str, source_map: Option<&Path>) -> Result<(), String> {
+    let bytes = match fs::read(payload) {
+        Ok(contents) => match std::str::from_utf8(&contents) {
+            Ok(text) => hex::decode(text.trim().trim_start_matches("0x"))
+                .map_err(|error| format!("`{payload}` does not hold valid hex: {error}"))?,
+            Err(_) => contents,
+        },
+        Err(_) => hex::decode(payload.trim_start_matches("0x"))
+            .map_err(|error| format!("`{payload}` is neither a file nor valid hex: {error}"))?,
+    };
+
+    let source_map = match source_map {
+        Some(path) => {
+            let contents = fs::read_to_string(path)
+                .map_err(|error| format!("could not read `{}`: {error}", path.display()))?;
+
+            serde_json::from_str(&contents)
+                .map_err(|error| format!("invalid source map `{}`: {error}", path.display()))?
+        }
+        None => SourceMap::default(),
+    };
+
+    for (index, (decoded, mapping)) in disassembler::annotate(&bytes, &source_map)?
+        .iter()
+        .enumerate()
+    {
+        println!(
+            "{}",
+            format!("instruction {index}, at byte {}", decoded.offset).dimmed()
+        );
+        repl::print(decoded);
+
+        let Some(mapping) = mapping else {
+            continue;
+        };
+
+        if let Some(location) = &mapping.origin.location {
+            println!("    at {location}");
+        }
+
+        // Innermost first, like a backtrace.
+        for (depth, frame) in mapping.origin.stack.iter().enumerate().rev() {
+            let location = frame
+                .location
+                .as_ref()
+                .map(|location| match depth {
+                    0 => format!(", defined at {location}"),
+                    _ => format!(", invoked at {location}"),
+                })
+                .unwrap_or_default();
+
+            println!("    in `{}`{location}", frame.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Scaffold a project in `path`.
+fn init(path: &Path) -> Result<(), String> {
+    let manifest = path.join(manifest::FILE_NAME);
+
+    if manifest.exists() {
+        return Err(format!("`{}` already exists", manifest.display()));
+    }
+
+    let files = [
+        (manifest, manifest::TEMPLATE),
+        (
+            path.join("src").join("main.fvm"),
+            include_str!("../../../examples/project.fvm"),
+        ),
+        (path.join(".gitignore"), "/out\n"),
+    ];
+
+    for (file, contents) in files {
+        if file.exists() {
+            continue;
+        }
+
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|error| format!("could not create `{}`: {error}", parent.display()))?;
+        }
+
+        fs::write(&file, contents)
+            .map_err(|error| format!("could not write `{}`: {error}", file.display()))?;
+    }
+
+    let lib = path.join("lib");
+
+    fs::create_dir_all(&lib)
+        .map_err(|error| format!("could not create `{}`: {error}", lib.display()))?;
+
+    Ok(())
 }
 
 fn main() {
     let value = Value::parse();
 
     match &value.command {
-        Commands::Build { path } => {
-            let start = std::time::Instant::now();
+        Commands::Build(args) if args.watch => watch::watch(args),
+        Commands::Build(args) => {
+            if build(args).failed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Check {
+            paths,
+            target,
+            entry,
+            input_format,
+        } => {
+            let (project, paths) = match project(paths, target.as_deref()) {
+                Ok(project) => project,
+                Err(message) => {
+                    report(&message);
+                    std::process::exit(1);
+                }
+            };
 
-            let contents = fs::read_to_string(path).unwrap();
-            let tokens = Token::lex(&contents);
-            let expressions = Assembler::parse(tokens);
+            let mut failed = false;
 
-            let codegen = Codegen::new(expressions);
-            let encoded = codegen.encode();
+            for source in read_sources(&paths, io::stdin()) {
+                let source = match source {
+                    Ok(source) => source,
+                    Err(message) => {
+                        report(&message);
+                        failed = true;
+                        continue;
+                    }
+                };
 
-            println!("{encoded:#?}");
+                let input_format = input_format.unwrap_or_else(|| InputFormat::infer(&source.path));
 
-            let encoded = Codegen::generate(codegen.encode());
+                if !check(&source, input_format, &project, entry.as_deref()) {
+                    failed = true;
+                }
+            }
 
-            let duration = start.elapsed();
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Fmt { paths, check } => {
+            let (_, paths) = match project(paths, None) {
+                Ok(project) => project,
+                Err(message) => {
+                    report(&message);
+                    std::process::exit(1);
+                }
+            };
+
+            let mut failed = false;
+
+            for source in read_sources(&paths, io::stdin()) {
+                let source = match source {
+                    Ok(source) => source,
+                    Err(message) => {
+                        report(&message);
+                        failed = true;
+                        continue;
+                    }
+                };
+
+                let formatted = match formatter::format(&source.contents) {
+                    Ok(formatted) => formatted,
+                    Err(error) => {
+                        error.emit(source.name(), &source.contents);
+                        failed = true;
+                        continue;
+                    }
+                };
+
+                if source.path == "-" {
+                    print!("{formatted}");
+                } else if formatted == source.contents {
+                    continue;
+                } else if *check {
+                    eprintln!("{} is not formatted", source.name());
+                    failed = true;
+                } else if let Err(error) = fs::write(&source.path, formatted) {
+                    report(&format!("could not write `{}`: {error}", source.path));
+                    failed = true;
+                }
+            }
+
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Init { path } => {
+            if let Err(message) = init(path) {
+                report(&message);
+                std::process::exit(1);
+            }
+
+            println!("Created project in {}", path.display());
+        }
+        Commands::Lsp { target } => {
+            if let Err(error) = lsp::serve(target.as_deref()) {
+                report(&error.to_string());
+                std::process::exit(1);
+            }
+        }
+        Commands::Repl { target } => match Project::discover(target.as_deref()) {
+            Ok(project) => repl::repl(project.environment),
+            Err(message) => {
+                report(&message);
+                std::process::exit(1);
+            }
+        },
+        Commands::Simulate {
+            path,
+            state,
+            target,
+            entry,
+            input_format,
+            no_opt,
+            net_liquidity,
+        } => {
+            if !simulate(
+                path,
+                state.as_deref(),
+                target.as_deref(),
+                entry.as_deref(),
+                *input_format,
+                (!no_opt).then_some(optimizer::Options {
+                    net_liquidity: *net_liquidity,
+                }),
+            ) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Disassemble {
+            payload,
+            source_map,
+        } => {
+            if let Err(message) = disassemble(payload, source_map.as_deref()) {
+                report(&message);
+                std::process::exit(1);
+            }
+        }
+        Commands::Spec { json } => {
+            if *json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&spec::INSTRUCTIONS).unwrap()
+                );
+                return;
+            }
+
+            for instruction in &spec::INSTRUCTIONS {
+                println!("{} ({:#04x})", instruction.name, instruction.opcode);
+
+                for parameter in instruction.parameters {
+                    let default = parameter
+                        .default
+                        .map(|default| format!("default {default}"))
+                        .unwrap_or_default();
+
+                    let line = format!(
+                        "    {:<16}{:<10}{:>4} bits  {default}",
+                        parameter.name,
+                        format!("{:?}", parameter.kind).to_lowercase(),
+                        parameter.bits,
+                    );
+
+                    println!("{}", line.trim_end());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for the test `name`.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("folio-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    /// Write `contents` to `path` in `directory`, returning the path.
+    fn write(directory: &Path, path: &str, contents: &str) -> String {
+        let path = directory.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+
+        path.display().to_string()
+    }
+
+    fn paths(sources: &[Result<Source, String>]) -> Vec<String> {
+        sources
+            .iter()
+            .map(|source| match source {
+                Ok(source) => source.path.clone(),
+                Err(message) => message.clone(),
+            })
+            .collect()
+    }
+
+    fn source(contents: &str) -> Source {
+        Source {
+            path: "-".to_string(),
+            contents: contents.to_string(),
+        }
+    }
+
+    /// A program of `count` swaps, which the optimizer leaves as they are.
+    fn swaps(count: usize) -> Source {
+        let swap = "swap: poolId: 34 amount0: 10 amount1: 20 sellAsset: 1\n";
+
+        source(&format!(
+            "extern pool 34\nmacro main {{\n{}}}",
+            swap.repeat(count)
+        ))
+    }
+
+    #[test]
+    fn stdin() {
+        let sources = read_sources(&["-".to_string()], "macro main {}".as_bytes());
+        let source = sources[0].as_ref().unwrap();
+
+        assert_eq!(source.contents, "macro main {}");
+        assert_eq!(source.name(), "<stdin>");
+        assert_eq!(source.artifact("main"), "stdin");
+    }
+
+    #[test]
+    fn multiple_paths_and_globs() {
+        let directory = directory("globs");
+        let a = write(&directory, "a.fvm", "");
+        let b = write(&directory, "b.fvm", "");
+        let c = write(&directory, "c.json", "");
+        let d = write(&directory, "nested/d.fvm", "");
+        let pattern = |pattern: &str| directory.join(pattern).display().to_string();
+
+        assert_eq!(
+            paths(&read_sources(&[pattern("*.fvm"), c.clone()], io::empty())),
+            [a.clone(), b.clone(), c]
+        );
+        assert_eq!(
+            paths(&read_sources(&[pattern("**/*.fvm")], io::empty())),
+            [a, b, d]
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn errors_per_source() {
+        let directory = directory("errors");
+        let a = write(&directory, "a.fvm", "");
+        let missing = directory.join("missing.fvm").display().to_string();
+        let unmatched = directory.join("*.yaml").display().to_string();
+
+        let sources = read_sources(
+            &[
+                a.clone(),
+                missing.clone(),
+                unmatched.clone(),
+                "[".to_string(),
+            ],
+            io::empty(),
+        );
+
+        assert_eq!(paths(&sources[..1]), [a]);
+        assert!(sources[1]
+            .as_ref()
+            .is_err_and(|message| message.starts_with(&format!("could not read `{missing}`"))));
+        assert_eq!(
+            sources[2].as_ref().err(),
+            Some(&format!("no files match `{unmatched}`"))
+        );
+        assert!(sources[3]
+            .as_ref()
+            .is_err_and(|message| message.starts_with("invalid pattern `[`")));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn artifact_names() {
+        let pools = Source {
+            path: Path::new("src").join("pools.fvm").display().to_string(),
+            contents: String::new(),
+        };
+        let out = |entry, extension| {
+            let path = Path::new("out").join(format!("{entry}.{extension}"));
+            path.display().to_string()
+        };
+
+        assert_eq!(pools.output("out", "main", "hex"), out("pools", "hex"));
+        assert_eq!(
+            pools.output("out", "rebalance", "tx.json"),
+            out("pools.rebalance", "tx.json")
+        );
+        assert_eq!(source("").output("out", "main", "bin"), out("stdin", "bin"));
+    }
+
+    #[test]
+    fn builds_each_source_into_the_output_directory() {
+        let directory = directory("build");
+        let out_dir = directory.join("out");
+        let valid = write(
+            &directory,
+            "valid.fvm",
+            "extern pool 34\npub macro provide { allocate: poolId: 34 deltaLiquidity: 10 }\npub macro withdraw { deallocate: poolId: 34 deltaLiquidity: 10 }",
+        );
+        write(&directory, "invalid.fvm", "macro main { swap: poolId: 34 }");
+
+        let Value {
+            command: Commands::Build(args),
+        } = Value::try_parse_from([
+            "folio",
+            "build",
+            &directory.join("*.fvm").display().to_string(),
+            "--out-dir",
+            &out_dir.display().to_string(),
+            "--quiet",
+        ])
+        .unwrap()
+        else {
+            unreachable!("the command is `build`");
+        };
+
+        // The invalid source fails the build, without keeping the valid one from being written.
+        let build = build(&args);
+        assert!(build.failed);
+        assert_eq!(
+            build.artifacts.keys().cloned().collect::<Vec<_>>(),
+            [
+                (valid.clone(), "provide".to_string()),
+                (valid, "withdraw".to_string())
+            ]
+        );
+
+        let mut written = fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        written.sort();
+        assert_eq!(written, ["valid.provide.hex", "valid.withdraw.hex"]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn check_encodes_payloads() {
+        let project = Project::default();
+
+        assert!(check(&swaps(255), InputFormat::Fvm, &project, None));
+        assert!(!check(&swaps(256), InputFormat::Fvm, &project, None));
+    }
+
+    #[test]
+    fn check_reports_errors() {
+        let project = Project::default();
+
+        assert!(!check(
+            &source("macro main { swap: poolId: 34 }"),
+            InputFormat::Fvm,
+            &project,
+            None
+        ));
+        assert!(!check(
+            &swaps(1),
+            InputFormat::Fvm,
+            &project,
+            Some("missing")
+        ));
+    }
+
+    #[test]
+    fn initialized_projects_build() {
+        let directory = directory("init");
+        init(&directory).unwrap();
+
+        let mut project = Project::load(&directory.join(manifest::FILE_NAME), None).unwrap();
+
+        // Every lint is denied, so that one firing fails the build.
+        for rule in &lint::RULES {
+            project.lints.insert(rule.id.to_string(), lint::Level::Deny);
+        }
+
+        let sources = read_sources(&project.source_patterns(), io::empty());
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(sources.len(), 1);
 
-            println!("Compilation finished in: {duration:?}");
+        for source in sources {
+            let source = source.unwrap();
+            let programs = parse(
+                &source,
+                InputFormat::Fvm,
+                &project,
+                None,
+                Some(optimizer::Options::default()),
+                false,
+                &mut BTreeSet::new(),
+            )
+            .unwrap();
 
-            println!("{encoded}");
+            assert_eq!(programs.len(), 1);
+            assert!(check(&source, InputFormat::Fvm, &project, None));
         }
     }
 }
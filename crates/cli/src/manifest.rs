@@ -0,0 +1,411 @@
+//! # Manifest
+//!
+//! The `folio.toml` project manifest.
+//! A manifest declares where sources and imports live, and the environment programs are assembled in:
+//! an address book, token decimals and default parameter values, each of which a named target can override.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use compiler::{
+    environment::Environment,
+    lint::{self, Level},
+};
+use serde::Deserialize;
+
+/// File name of the manifest.
+pub const FILE_NAME: &str = "folio.toml";
+
+/// Manifest written by `folio init`.
+pub const TEMPLATE: &str = r#"[project]
+sources = ["src"]
+imports = ["lib"]
+out-dir = "out"
+
+[addresses]
+treasury = "0xdAB0A5d15ff6B75E69256c29609d2Fc3eBbFEb04"
+
+[decimals]
+USDC = 6
+WETH = 18
+
+[defaults.createPool]
+controller = "treasury"
+priorityFee = 0
+
+[lints]
+identical_tokens = "deny"
+
+[targets.mainnet.addresses]
+treasury = "0xdAB0A5d15ff6B75E69256c29609d2Fc3eBbFEb04"
+
+[targets.testnet.addresses]
+treasury = "0x0000000000000000000000000000000000000001"
+"#;
+
+/// The contents of a `folio.toml` file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    project: ProjectTable,
+    #[serde(default)]
+    addresses: BTreeMap<String, String>,
+    #[serde(default)]
+    decimals: BTreeMap<String, u8>,
+    #[serde(default)]
+    defaults: BTreeMap<String, BTreeMap<String, toml::Value>>,
+    #[serde(default)]
+    targets: BTreeMap<String, EnvironmentTable>,
+    /// Level of lint rules, by id.
+    #[serde(default)]
+    lints: BTreeMap<String, String>,
+}
+
+/// The `[project]` table.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ProjectTable {
+    #[serde(default = "ProjectTable::default_sources")]
+    sources: Vec<PathBuf>,
+    #[serde(default)]
+    imports: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+}
+
+impl ProjectTable {
+    fn default_sources() -> Vec<PathBuf> {
+        vec!["src".into()]
+    }
+}
+
+impl Default for ProjectTable {
+    fn default() -> Self {
+        Self {
+            sources: ProjectTable::default_sources(),
+            imports: Vec::new(),
+            out_dir: None,
+        }
+    }
+}
+
+/// Definitions made at the top level of the manifest, or overridden by a target.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentTable {
+    #[serde(default)]
+    addresses: BTreeMap<String, String>,
+    #[serde(default)]
+    decimals: BTreeMap<String, u8>,
+    #[serde(default)]
+    defaults: BTreeMap<String, BTreeMap<String, toml::Value>>,
+}
+
+impl EnvironmentTable {
+    /// Add these definitions to `environment`, replacing any with the same name.
+    fn apply(self, environment: &mut Environment) -> Result<(), String> {
+        for (name, value) in self.addresses {
+            let address = value
+                .parse()
+                .map_err(|_| format!("`{value}` is not a valid address for `{name}`"))?;
+
+            environment.addresses.insert(name, address);
+        }
+
+        environment.decimals.extend(self.decimals);
+
+        for (instruction, parameters) in self.defaults {
+            let defaults = environment.defaults.entry(instruction.clone()).or_default();
+
+            for (parameter, value) in parameters {
+                let value = match value {
+                    toml::Value::Integer(value) if value >= 0 => value.to_string(),
+                    toml::Value::String(value) => value,
+                    toml::Value::Boolean(value) => u8::from(value).to_string(),
+                    _ => {
+                        return Err(format!(
+                            "default `{instruction}.{parameter}` must be a non-negative integer, a boolean or a string"
+                        ))
+                    }
+                };
+
+                defaults.insert(parameter, value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A project, as described by its manifest.
+#[derive(Debug, Default)]
+pub struct Project {
+    /// Path of the manifest, or `None` outside of a project.
+    pub manifest: Option<PathBuf>,
+    /// Directories holding the source files built when no paths are given.
+    pub sources: Vec<PathBuf>,
+    /// Directories searched for imports.
+    pub imports: Vec<PathBuf>,
+    /// Directory artifacts are written to when no paths are given.
+    pub out_dir: Option<PathBuf>,
+    /// Definitions programs are assembled with.
+    pub environment: Environment,
+    /// Level of lint rules, by id, overriding their defaults.
+    pub lints: BTreeMap<String, Level>,
+}
+
+impl Project {
+    /// Find the manifest in the working directory or its closest ancestor, and load it for `target`.
+    /// Outside of a project, returns an empty project unless a target was requested.
+    pub fn discover(target: Option<&str>) -> Result<Self, String> {
+        let directory = std::env::current_dir()
+            .map_err(|error| format!("could not read the working directory: {error}"))?;
+
+        match directory
+            .ancestors()
+            .map(|directory| directory.join(FILE_NAME))
+            .find(|path| path.is_file())
+        {
+            Some(path) => Project::load(&path, target),
+            None if target.is_some() => Err(format!(
+                "`--target` requires a `{FILE_NAME}`, but none was found"
+            )),
+            None => Ok(Project::default()),
+        }
+    }
+
+    /// Load the manifest at `path`, applying the overrides of `target`.
+    pub fn load(path: &Path, target: Option<&str>) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| format!("could not read `{}`: {error}", path.display()))?;
+
+        let mut manifest: Manifest = toml::from_str(&contents)
+            .map_err(|error| format!("invalid `{}`: {error}", path.display()))?;
+
+        let mut environment = Environment::new();
+
+        EnvironmentTable {
+            addresses: manifest.addresses,
+            decimals: manifest.decimals,
+            defaults: manifest.defaults,
+        }
+        .apply(&mut environment)
+        .map_err(|error| format!("invalid `{}`: {error}", path.display()))?;
+
+        if let Some(target) = target {
+            manifest
+                .targets
+                .remove(target)
+                .ok_or_else(|| format!("no target `{target}` in `{}`", path.display()))?
+                .apply(&mut environment)
+                .map_err(|error| {
+                    format!("invalid target `{target}` in `{}`: {error}", path.display())
+                })?;
+        }
+
+        let lints = manifest
+            .lints
+            .into_iter()
+            .map(|(id, level)| {
+                lint::rule(&id).ok_or_else(|| format!("unknown lint rule `{id}`"))?;
+                Ok((id, level.parse()?))
+            })
+            .collect::<Result<_, String>>()
+            .map_err(|error| format!("invalid `{}`: {error}", path.display()))?;
+
+        let root = path.parent().unwrap_or(Path::new("."));
+
+        Ok(Self {
+            manifest: path.canonicalize().ok(),
+            sources: manifest
+                .project
+                .sources
+                .iter()
+                .map(|source| root.join(source))
+                .collect(),
+            imports: manifest
+                .project
+                .imports
+                .iter()
+                .map(|import| root.join(import))
+                .collect(),
+            out_dir: manifest.project.out_dir.map(|out_dir| root.join(out_dir)),
+            environment,
+            lints,
+        })
+    }
+
+    /// Glob patterns matching every source file of the project.
+    pub fn source_patterns(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .map(|source| source.join("**").join("*.fvm").display().to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compiler::opcode::Address;
+
+    use super::*;
+
+    /// Write `contents` as the manifest of a new project for the test `name`, returning its path.
+    fn manifest(name: &str, contents: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("folio-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let path = directory.join(FILE_NAME);
+        fs::write(&path, contents).unwrap();
+
+        path
+    }
+
+    /// Load `contents` as a manifest for `target`.
+    fn load(name: &str, contents: &str, target: Option<&str>) -> Result<Project, String> {
+        let path = manifest(name, contents);
+        let project = Project::load(&path, target);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        project
+    }
+
+    #[test]
+    fn template() {
+        let path = manifest("template", TEMPLATE);
+        let root = path.parent().unwrap().to_path_buf();
+        let project = Project::load(&path, None).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(project.sources, [root.join("src")]);
+        assert_eq!(project.imports, [root.join("lib")]);
+        assert_eq!(project.out_dir, Some(root.join("out")));
+        assert_eq!(
+            project.source_patterns(),
+            [root
+                .join("src")
+                .join("**")
+                .join("*.fvm")
+                .display()
+                .to_string()]
+        );
+        assert_eq!(project.environment.decimals["USDC"], 6);
+        assert_eq!(
+            project.environment.default("createPool", "controller"),
+            Some("treasury")
+        );
+    }
+
+    #[test]
+    fn defaults() {
+        let project = load("defaults", "", None).unwrap();
+
+        assert_eq!(project.sources.len(), 1);
+        assert!(project.sources[0].ends_with("src"));
+        assert!(project.imports.is_empty());
+        assert_eq!(project.out_dir, None);
+        assert_eq!(project.environment, Environment::new());
+        assert!(project.lints.is_empty());
+    }
+
+    #[test]
+    fn targets() {
+        let contents = r#"
+            [addresses]
+            treasury = "0x0101010101010101010101010101010101010101"
+            vault = "0x0202020202020202020202020202020202020202"
+
+            [decimals]
+            USDC = 6
+
+            [defaults.swap]
+            useMax = true
+
+            [targets.testnet.addresses]
+            treasury = "0x0303030303030303030303030303030303030303"
+
+            [targets.testnet.decimals]
+            USDC = 18
+
+            [targets.testnet.defaults.swap]
+            useMax = 0
+        "#;
+
+        let project = load("targets", contents, None).unwrap();
+        assert_eq!(
+            project.environment.address("treasury"),
+            Some(Address::repeat_byte(1))
+        );
+        assert_eq!(project.environment.decimals["USDC"], 6);
+        assert_eq!(project.environment.default("swap", "useMax"), Some("1"));
+
+        // A target replaces the definitions it makes, and keeps the others.
+        let testnet = load("targets", contents, Some("testnet")).unwrap();
+        assert_eq!(
+            testnet.environment.address("treasury"),
+            Some(Address::repeat_byte(3))
+        );
+        assert_eq!(
+            testnet.environment.address("vault"),
+            Some(Address::repeat_byte(2))
+        );
+        assert_eq!(testnet.environment.decimals["USDC"], 18);
+        assert_eq!(testnet.environment.default("swap", "useMax"), Some("0"));
+
+        assert!(load("targets", contents, Some("mainnet"))
+            .unwrap_err()
+            .starts_with("no target `mainnet`"));
+    }
+
+    #[test]
+    fn lints() {
+        let project = load(
+            "lints",
+            "[lints]\nidentical_tokens = \"deny\"\nunused_macro = \"allow\"",
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            project.lints,
+            [
+                ("identical_tokens".to_string(), Level::Deny),
+                ("unused_macro".to_string(), Level::Allow)
+            ]
+            .into()
+        );
+
+        assert!(load("lints", "[lints]\nunknown = \"deny\"", None)
+            .unwrap_err()
+            .ends_with("unknown lint rule `unknown`"));
+        assert!(load("lints", "[lints]\nunused_macro = \"error\"", None)
+            .unwrap_err()
+            .ends_with("unknown lint level `error`, expected `allow`, `warn` or `deny`"));
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |contents| load("invalid", contents, None).unwrap_err();
+
+        assert!(error("[addresses]\ntreasury = \"0x12\"")
+            .ends_with("`0x12` is not a valid address for `treasury`"));
+        assert!(error("[defaults.swap]\nuseMax = -1").ends_with(
+            "default `swap.useMax` must be a non-negative integer, a boolean or a string"
+        ));
+        assert!(error("[project]\nsource = [\"src\"]").contains("unknown field `source`"));
+        assert!(load(
+            "invalid",
+            "[targets.testnet.addresses]\ntreasury = \"vault\"",
+            Some("testnet")
+        )
+        .unwrap_err()
+        .contains("invalid target `testnet`"));
+        assert!(Project::load(Path::new("missing.toml"), None)
+            .unwrap_err()
+            .starts_with("could not read `missing.toml`"));
+    }
+}
@@ -0,0 +1,562 @@
+//! # Repl
+//!
+//! `folio repl`, an interactive session for composing programs.
+//! Each entry is lexed and assembled on its own against the macros defined so far, and its instructions are
+//! encoded and decoded immediately. Instructions accumulate into a program that can be saved as a source file.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use colored::Colorize;
+use compiler::{
+    assembler::{Assembler, Expression, Macro},
+    codegen::Codegen,
+    disassembler::{self, Decoded},
+    environment::Environment,
+    opcode::Opcode,
+    token::{Span, Spanned, Token, TokenType},
+};
+
+use crate::report;
+
+const HELP: &str = "\
+Enter an instruction, a macro invocation or a macro definition.
+Instructions are encoded as they are entered, and added to the program.
+
+:program         print the program and its payload
+:undo            remove the last entry from the program
+:clear           remove every entry from the program
+:macros          list the defined macros
+:decode <hex>    decode a payload or a single instruction
+:save <path>     save the program and its macros as a source file
+:help            print this message
+:quit            exit";
+
+/// An expression of a macro body, owning the name of the macro it invokes.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+enum Item {
+    Opcode(Opcode),
+    Invocation(String),
+}
+
+/// A macro definition, parsed once when it is entered.
+#[derive(Debug, Clone, PartialEq)]
+struct Definition {
+    name: String,
+    /// Source of the definition, from `macro` or `pub` to its closing brace.
+    source: String,
+    /// Whether the macro is marked `pub` or `entry`.
+    entry: bool,
+    /// Body of the macro, with spans into `source`.
+    body: Vec<Spanned<Item>>,
+}
+
+impl Definition {
+    /// Take ownership of `mac`, parsed from `source`.
+    fn new(source: &str, mac: &Macro) -> Self {
+        let span = Definition::span(source, mac);
+
+        Self {
+            name: mac.name.to_string(),
+            source: source[span.start..span.end].to_string(),
+            entry: mac.entry,
+            body: mac
+                .body
+                .iter()
+                .map(|expression| {
+                    let item = match &expression.node {
+                        Expression::Opcode(opcode) => Item::Opcode(opcode.clone()),
+                        Expression::Invocation(name) => Item::Invocation(name.to_string()),
+                    };
+
+                    Spanned::new(
+                        item,
+                        Span::new(
+                            expression.span.start - span.start,
+                            expression.span.end - span.start,
+                        ),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// The span of the definition of `mac` in `source`, from `macro` or `pub` to its closing brace.
+    fn span(source: &str, mac: &Macro) -> Span {
+        let tokens = Token::lex(source);
+        let name = tokens
+            .iter()
+            .position(|token| token.span == mac.span)
+            .unwrap_or_default();
+
+        let start = tokens[..name]
+            .iter()
+            .rev()
+            .take(2)
+            .take_while(|token| matches!(token.ttype, TokenType::Macro | TokenType::Pub))
+            .last()
+            .map(|token| token.span.start)
+            .unwrap_or(mac.span.start);
+
+        let end = tokens[name..]
+            .iter()
+            .find(|token| token.ttype == TokenType::CloseBrace)
+            .map(|token| token.span.end)
+            .unwrap_or(source.len());
+
+        Span::new(start, end)
+    }
+
+    /// The macro, borrowing its names from this definition.
+    fn to_macro(&self) -> Macro<'_> {
+        Macro {
+            name: &self.name,
+            span: Span::default(),
+            entry: self.entry,
+            body: self
+                .body
+                .iter()
+                .map(|item| {
+                    let expression = match &item.node {
+                        Item::Opcode(opcode) => Expression::Opcode(opcode.clone()),
+                        Item::Invocation(name) => Expression::Invocation(name),
+                    };
+
+                    Spanned::new(expression, item.span)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The state of a session.
+///
+/// Each entry is lexed and parsed on its own, and expanded against the definitions parsed from earlier entries.
+/// Definitions own their names, so that nothing borrows from an entry once it has been assembled.
+struct Session {
+    environment: Environment,
+    /// Each macro definition, in order of definition.
+    definitions: Vec<Definition>,
+    /// Source of each program entry, with the opcodes it expanded to.
+    program: Vec<(String, Vec<Opcode>)>,
+}
+
+impl Session {
+    fn new(environment: Environment) -> Self {
+        Self {
+            environment,
+            definitions: Vec::new(),
+            program: Vec::new(),
+        }
+    }
+
+    /// Assemble an entry, which is either a set of macro definitions or a list of instructions and invocations.
+    fn enter(&mut self, source: String) {
+        let tokens = Token::lex(&source);
+        let definition = matches!(
+            tokens.first().map(|token| token.ttype),
+            Some(TokenType::Macro | TokenType::Pub)
+        );
+        let assembler = Assembler::new(tokens).with_environment(&self.environment);
+
+        if definition {
+            let mut macros = match assembler.parse_macros() {
+                Ok(macros) => macros.into_values().collect::<Vec<_>>(),
+                Err(error) => return error.emit("<repl>", &source),
+            };
+
+            macros.sort_by_key(|mac| mac.span.start);
+
+            for mac in macros {
+                let verb = if self
+                    .definitions
+                    .iter()
+                    .any(|definition| definition.name == mac.name)
+                {
+                    "redefined"
+                } else {
+                    "defined"
+                };
+
+                self.definitions
+                    .retain(|definition| definition.name != mac.name);
+                self.definitions.push(Definition::new(&source, &mac));
+                println!("{verb} `{}`", mac.name);
+            }
+
+            return;
+        }
+
+        let body = match assembler.parse_body() {
+            Ok(body) => body,
+            Err(error) => return error.emit("<repl>", &source),
+        };
+
+        let macros = self
+            .definitions
+            .iter()
+            .map(|definition| (definition.name.as_str(), definition.to_macro()))
+            .collect::<HashMap<_, _>>();
+
+        let opcodes = match Assembler::expand_body(&macros, &body) {
+            Ok(body) => body
+                .into_iter()
+                .filter_map(|expression| match expression.node {
+                    Expression::Opcode(opcode) => Some(opcode),
+                    Expression::Invocation(_) => None,
+                })
+                .collect::<Vec<_>>(),
+            // Errors in the body of a definition point into its source, rather than the entry's.
+            Err(error) if body.iter().any(|expression| expression.span == error.span) => {
+                return error.emit("<repl>", &source)
+            }
+            Err(error) => return report(&error.message),
+        };
+
+        for opcode in &opcodes {
+            let Some(encoded) = Codegen::from(vec![opcode.clone()]).encode().pop() else {
+                println!("{} is not encoded", opcode.instruction().name);
+                continue;
+            };
+
+            match disassembler::decode(&hex::decode(&encoded).unwrap()) {
+                Ok(decoded) => {
+                    println!("{}", encoded.dimmed());
+                    print(&decoded);
+                }
+                Err(message) => report(&format!("could not decode `{encoded}`: {message}")),
+            }
+        }
+
+        self.program.push((source, opcodes));
+    }
+
+    /// Run a `:` command, returning whether the session should end.
+    fn command(&mut self, line: &str) -> bool {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        match command {
+            ":help" => println!("{HELP}"),
+            ":quit" | ":q" => return true,
+            ":program" => {
+                for (source, _) in &self.program {
+                    println!("{source}");
+                }
+
+                let opcodes = self
+                    .program
+                    .iter()
+                    .flat_map(|(_, opcodes)| opcodes.clone())
+                    .collect::<Vec<_>>();
+
+                match Codegen::generate(Codegen::from(opcodes).encode()) {
+                    Ok(payload) => println!("{}", payload.bold()),
+                    Err(message) => report(&message),
+                }
+            }
+            ":undo" => match self.program.pop() {
+                Some((source, _)) => println!("removed `{source}`"),
+                None => println!("the program is empty"),
+            },
+            ":clear" => self.program.clear(),
+            ":macros" => {
+                for definition in &self.definitions {
+                    println!("{}", definition.name);
+                }
+            }
+            ":decode" => decode(argument),
+            ":save" if argument.is_empty() => report("`:save` requires a path"),
+            ":save" => match fs::write(argument, self.source()) {
+                Ok(()) => println!("saved to {argument}"),
+                Err(error) => report(&format!("could not write `{argument}`: {error}")),
+            },
+            _ => report(&format!("unknown command `{command}`, see `:help`")),
+        }
+
+        false
+    }
+
+    /// The session as a source file: every macro definition, followed by the program as `main`.
+    fn source(&self) -> String {
+        let mut source = String::new();
+
+        for definition in &self.definitions {
+            if definition.name != "main" {
+                source.push_str(&definition.source);
+                source.push_str("\n\n");
+            }
+        }
+
+        // When other macros are entry points, `main` has to be marked to be built as well.
+        if self.definitions.iter().any(|definition| definition.entry) {
+            source.push_str("pub ");
+        }
+
+        source.push_str("macro main {\n");
+
+        for (entry, _) in &self.program {
+            for line in entry.lines() {
+                source.push_str(&format!("\t{}\n", line.trim()));
+            }
+        }
+
+        source.push_str("}\n");
+        source
+    }
+}
+
+/// Print a decoded instruction, and each of its fields.
+pub fn print(decoded: &Decoded) {
+    let length = decoded
+        .fields
+        .last()
+        .map(|field| field.range.end)
+        .unwrap_or_default();
+
+    println!(
+        "{} ({length} bytes)",
+        decoded.opcode.instruction().name.bold()
+    );
+
+    for field in &decoded.fields {
+        println!(
+            "    {:<16}{:<10}{}",
+            field.name,
+            format!("{}..{}", field.range.start, field.range.end),
+            field.value
+        );
+    }
+}
+
+/// Decode and print a payload, or a single instruction.
+fn decode(argument: &str) {
+    let bytes = match hex::decode(argument.trim_start_matches("0x")) {
+        Ok(bytes) => bytes,
+        Err(error) => return report(&format!("`{argument}` is not valid hex: {error}")),
+    };
+
+    let decoded = match bytes.first() {
+        Some(0xaa) => disassembler::disassemble(&bytes),
+        _ => disassembler::decode(&bytes).map(|decoded| vec![decoded]),
+    };
+
+    match decoded {
+        Ok(decoded) => decoded.iter().for_each(print),
+        Err(message) => report(&message),
+    }
+}
+
+/// Run a session on stdin until `:quit` or the end of input.
+pub fn repl(environment: Environment) {
+    let mut session = Session::new(environment);
+    let mut buffer = String::new();
+    let mut lines = io::stdin().lock().lines();
+
+    println!(
+        "folio {}, enter `:help` for help",
+        env!("CARGO_PKG_VERSION")
+    );
+
+    loop {
+        let prompt = if buffer.is_empty() {
+            "folio> "
+        } else {
+            "  ...> "
+        };
+        print!("{prompt}");
+        io::stdout().flush().unwrap();
+
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            break;
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            if session.command(line.trim()) {
+                break;
+            }
+
+            continue;
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+
+        // Keep reading until every brace that was opened is closed.
+        if buffer.matches('{').count() > buffer.matches('}').count() {
+            continue;
+        }
+
+        let entry = std::mem::take(&mut buffer);
+
+        if !entry.trim().is_empty() {
+            session.enter(entry.trim().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session::new(Environment::new())
+    }
+
+    fn names(session: &Session) -> Vec<&str> {
+        session
+            .definitions
+            .iter()
+            .map(|definition| definition.name.as_str())
+            .collect()
+    }
+
+    fn opcodes(session: &Session) -> Vec<Opcode> {
+        session
+            .program
+            .iter()
+            .flat_map(|(_, opcodes)| opcodes.clone())
+            .collect()
+    }
+
+    fn allocate(delta_liquidity: u64) -> Opcode {
+        Opcode::Allocate {
+            use_max: 0.into(),
+            pool_id: 34.into(),
+            delta_liquidity: delta_liquidity.into(),
+        }
+    }
+
+    #[test]
+    fn define_and_redefine() {
+        let mut session = session();
+
+        session.enter("macro provide { allocate: poolId: 34 deltaLiquidity: 1 }".to_string());
+        session.enter(
+            "pub macro withdraw { deallocate: poolId: 34 deltaLiquidity: 1 }\nmacro helper { provide }"
+                .to_string(),
+        );
+
+        assert_eq!(names(&session), ["provide", "withdraw", "helper"]);
+        assert!(session.definitions[1].entry);
+        assert_eq!(
+            session.definitions[1].source,
+            "pub macro withdraw { deallocate: poolId: 34 deltaLiquidity: 1 }"
+        );
+        assert_eq!(
+            session.definitions[2].body,
+            [Spanned::new(
+                Item::Invocation("provide".to_string()),
+                Span::new(15, 22)
+            )]
+        );
+
+        // A redefinition replaces the macro, and moves it after the others.
+        session.enter("macro provide { allocate: poolId: 34 deltaLiquidity: 2 }".to_string());
+
+        assert_eq!(names(&session), ["withdraw", "helper", "provide"]);
+        assert_eq!(
+            session.definitions[2].body[0].node,
+            Item::Opcode(allocate(2))
+        );
+        assert!(session.program.is_empty());
+
+        // Invalid definitions are not kept.
+        session.enter("macro broken { allocate: poolId: 34 }".to_string());
+        assert_eq!(names(&session), ["withdraw", "helper", "provide"]);
+    }
+
+    #[test]
+    fn invocations() {
+        let mut session = session();
+
+        // Macros may invoke ones defined after them.
+        session.enter("macro outer { inner inner }".to_string());
+        session.enter("macro inner { allocate: poolId: 34 deltaLiquidity: 1 }".to_string());
+        session.enter("outer\nallocate: poolId: 34 deltaLiquidity: 3".to_string());
+
+        assert_eq!(opcodes(&session), [allocate(1), allocate(1), allocate(3)]);
+
+        // Entries expand against the latest definitions, without changing earlier entries.
+        session.enter("macro inner { allocate: poolId: 34 deltaLiquidity: 2 }".to_string());
+        session.enter("outer".to_string());
+
+        assert_eq!(
+            opcodes(&session),
+            [
+                allocate(1),
+                allocate(1),
+                allocate(3),
+                allocate(2),
+                allocate(2)
+            ]
+        );
+
+        // Entries that cannot be assembled are not added to the program.
+        session.enter("missing".to_string());
+        session.enter("macro recursive { recursive }".to_string());
+        session.enter("recursive".to_string());
+        session.enter("allocate: poolId: 34".to_string());
+
+        assert_eq!(session.program.len(), 2);
+    }
+
+    #[test]
+    fn undo_and_clear() {
+        let mut session = session();
+
+        session.enter("allocate: poolId: 34 deltaLiquidity: 1".to_string());
+        session.enter("allocate: poolId: 34 deltaLiquidity: 2".to_string());
+
+        assert!(!session.command(":undo"));
+        assert_eq!(opcodes(&session), [allocate(1)]);
+
+        session.command(":undo");
+        session.command(":undo");
+        assert!(session.program.is_empty());
+
+        session.enter("macro provide { allocate: poolId: 34 deltaLiquidity: 1 }".to_string());
+        session.enter("provide provide".to_string());
+        session.command(":clear");
+
+        assert!(session.program.is_empty());
+        assert_eq!(names(&session), ["provide"]);
+        assert!(session.command(":quit"));
+    }
+
+    #[test]
+    fn save() {
+        let mut session = session();
+
+        session
+            .enter("pub macro provide {\n  allocate: poolId: 34 deltaLiquidity: 1\n}".to_string());
+        session.enter("macro main { provide }".to_string());
+        session.enter("provide".to_string());
+        session.enter("allocate: poolId: 34\ndeltaLiquidity: 2".to_string());
+
+        // `main` is replaced by the program, and marked as an entry point, since `provide` is one.
+        let source = session.source();
+        assert_eq!(
+            source,
+            "pub macro provide {\n  allocate: poolId: 34 deltaLiquidity: 1\n}\n\n\
+             pub macro main {\n\tprovide\n\tallocate: poolId: 34\n\tdeltaLiquidity: 2\n}\n"
+        );
+
+        let path = std::env::temp_dir().join(format!("folio-repl-{}.fvm", std::process::id()));
+        assert!(!session.command(&format!(":save {}", path.display())));
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(saved, source);
+
+        let expressions = Assembler::new(Token::lex(&saved))
+            .assemble_entry("main")
+            .unwrap();
+        assert_eq!(Codegen::new(expressions).opcodes(), opcodes(&session));
+    }
+}
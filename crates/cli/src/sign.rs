@@ -0,0 +1,192 @@
+//! # Sign
+//!
+//! `folio build --sign`, which signs a built program as an EIP-1559 transaction to the FVM contract.
+//! The nonce, chain id and fees are given on the command line, and the key is read from an encrypted JSON keystore,
+//! or raw from a file or the environment, so signing never touches the network and can run on an air-gapped machine.
+//! Keys are never taken as arguments, which other users of the machine could read from the process list.
+
+use std::{env, fs, io, path::PathBuf};
+
+use clap::Args;
+use compiler::transaction::Parameters;
+use ethers::{signers::LocalWallet, types::U256};
+
+/// Environment variable holding the signing key, as hex, when no keystore or key file is given.
+pub const PRIVATE_KEY: &str = "FOLIO_PRIVATE_KEY";
+
+/// Arguments of `folio build --sign`.
+#[derive(Args)]
+pub struct SignArgs {
+    /// Sign the call to the FVM contract at `--to` as an EIP-1559 transaction, and output it raw, as hex.
+    #[clap(
+        long,
+        conflicts_with = "format",
+        requires_all = ["to", "chain_id", "nonce", "gas_limit", "max_fee", "max_priority_fee"]
+    )]
+    pub sign: bool,
+    /// Chain id of the transaction.
+    #[clap(long, requires = "sign")]
+    chain_id: Option<u64>,
+    /// Nonce of the transaction.
+    #[clap(long, requires = "sign")]
+    nonce: Option<u64>,
+    /// Gas limit of the transaction.
+    #[clap(long, requires = "sign")]
+    gas_limit: Option<u64>,
+    /// Most wei paid per unit of gas, including the priority fee.
+    #[clap(long, requires = "sign")]
+    max_fee: Option<u128>,
+    /// Most wei paid per unit of gas to the block producer.
+    #[clap(long, requires = "sign")]
+    max_priority_fee: Option<u128>,
+    /// Encrypted JSON keystore holding the signing key.
+    #[clap(long, requires = "sign", conflicts_with = "private_key_file")]
+    keystore: Option<PathBuf>,
+    /// File holding the password of the keystore. Without one, the password is read from stdin.
+    #[clap(long, requires = "keystore")]
+    password_file: Option<PathBuf>,
+    /// File holding the signing key, as hex.
+    /// Without a keystore or key file, the key is read from `FOLIO_PRIVATE_KEY`.
+    #[clap(long, requires = "sign")]
+    private_key_file: Option<PathBuf>,
+}
+
+impl SignArgs {
+    /// The wallet and transaction parameters to sign with, if signing.
+    /// `stdin` is whether the program is read from stdin, which leaves no way to prompt for a password.
+    pub fn signer(&self, stdin: bool) -> Result<Option<(LocalWallet, Parameters)>, String> {
+        if !self.sign {
+            return Ok(None);
+        }
+
+        let wallet = match (&self.keystore, &self.private_key_file) {
+            (Some(keystore), _) => {
+                let password = match &self.password_file {
+                    Some(path) => fs::read_to_string(path)
+                        .map_err(|error| format!("could not read `{}`: {error}", path.display()))?,
+                    None if stdin => {
+                        return Err(
+                            "the program is read from stdin, so the keystore password must be given with `--password-file`"
+                                .into(),
+                        )
+                    }
+                    None => {
+                        eprint!("Password for {}: ", keystore.display());
+
+                        let mut password = String::new();
+                        io::stdin()
+                            .read_line(&mut password)
+                            .map_err(|error| format!("could not read the password: {error}"))?;
+                        password
+                    }
+                };
+
+                LocalWallet::decrypt_keystore(keystore, password.trim_end_matches(['\r', '\n']))
+                    .map_err(|error| {
+                        format!("could not decrypt `{}`: {error}", keystore.display())
+                    })?
+            }
+            (None, Some(path)) => key(&fs::read_to_string(path)
+                .map_err(|error| format!("could not read `{}`: {error}", path.display()))?)?,
+            (None, None) => match env::var(PRIVATE_KEY) {
+                Ok(private_key) => key(&private_key)?,
+                Err(_) => {
+                    return Err(format!(
+                        "`--sign` requires `--keystore`, `--private-key-file` or `{PRIVATE_KEY}`"
+                    ))
+                }
+            },
+        };
+
+        // Every parameter is required by `--sign`, so clap has checked they are all set.
+        let parameters = Parameters {
+            chain_id: self.chain_id.unwrap(),
+            nonce: U256::from(self.nonce.unwrap()),
+            gas: U256::from(self.gas_limit.unwrap()),
+            max_fee_per_gas: U256::from(self.max_fee.unwrap()),
+            max_priority_fee_per_gas: U256::from(self.max_priority_fee.unwrap()),
+        };
+
+        if parameters.max_priority_fee_per_gas > parameters.max_fee_per_gas {
+            return Err("`--max-priority-fee` cannot be higher than `--max-fee`".into());
+        }
+
+        Ok(Some((wallet, parameters)))
+    }
+}
+
+/// A wallet for a signing key given as hex.
+fn key(private_key: &str) -> Result<LocalWallet, String> {
+    private_key
+        .trim()
+        .trim_start_matches("0x")
+        .parse()
+        .map_err(|error| format!("invalid private key: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::signers::Signer;
+
+    use super::*;
+
+    const KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn args() -> SignArgs {
+        SignArgs {
+            sign: true,
+            chain_id: Some(1),
+            nonce: Some(0),
+            gas_limit: Some(300_000),
+            max_fee: Some(2),
+            max_priority_fee: Some(1),
+            keystore: None,
+            password_file: None,
+            private_key_file: None,
+        }
+    }
+
+    #[test]
+    fn private_key_file() {
+        let path = env::temp_dir().join(format!("folio-key-{}", std::process::id()));
+        fs::write(&path, format!("{KEY}\n")).unwrap();
+
+        let (wallet, parameters) = SignArgs {
+            private_key_file: Some(path.clone()),
+            ..args()
+        }
+        .signer(true)
+        .unwrap()
+        .unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(wallet.address(), key(KEY).unwrap().address());
+        assert_eq!(parameters.chain_id, 1);
+    }
+
+    #[test]
+    fn password_from_stdin() {
+        let error = SignArgs {
+            keystore: Some(PathBuf::from("key.json")),
+            ..args()
+        }
+        .signer(true)
+        .unwrap_err();
+
+        assert!(error.contains("`--password-file`"), "{error}");
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert!(key("0x1234")
+            .unwrap_err()
+            .starts_with("invalid private key"));
+        assert!(SignArgs {
+            private_key_file: Some(PathBuf::from("missing")),
+            ..args()
+        }
+        .signer(false)
+        .unwrap_err()
+        .starts_with("could not read `missing`"));
+    }
+}
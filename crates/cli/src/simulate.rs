@@ -0,0 +1,364 @@
+//! # Simulate
+//!
+//! `folio simulate`, which runs a program against a model of the FVM and prints what each instruction did.
+//! The initial state is read from a JSON file of pairs, pools, positions and balances of the sender,
+//! in which tokens and controllers can be named from the address book.
+//! Amounts are simulated as floats, which round them above 2^53, so the trace notes when it is approximate.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use colored::Colorize;
+use compiler::{
+    environment::Environment,
+    opcode::Address,
+    simulator::{Pair, Parameters, Pool, Position, Simulation, State, EXACT},
+};
+use serde::Deserialize;
+
+/// The contents of a state file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateFile {
+    #[serde(default)]
+    pairs: Vec<PairTable>,
+    #[serde(default)]
+    pools: Vec<PoolTable>,
+    /// Balances of the sender, by token.
+    #[serde(default)]
+    balances: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PairTable {
+    id: u32,
+    token0: String,
+    token1: String,
+}
+
+/// A pool, with the parameters of `createPool` and its current price and liquidity.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct PoolTable {
+    id: u64,
+    pair_id: u32,
+    controller: Option<String>,
+    #[serde(default)]
+    priority_fee: f64,
+    fee: f64,
+    vol: f64,
+    dur: f64,
+    max_price: f64,
+    price: f64,
+    /// Total liquidity of the pool.
+    #[serde(default)]
+    liquidity: f64,
+    /// Liquidity of the sender's position, which is part of the total.
+    #[serde(default)]
+    position: f64,
+}
+
+/// Read the state file at `path`.
+pub fn load(path: &Path, environment: &Environment) -> Result<State, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("could not read `{}`: {error}", path.display()))?;
+
+    parse(&contents, &path.display().to_string(), environment)
+}
+
+/// Parse the contents of the state file `name`.
+fn parse(contents: &str, name: &str, environment: &Environment) -> Result<State, String> {
+    let file: StateFile = serde_json::from_str(contents)
+        .map_err(|error| format!("invalid state `{name}`: {error}"))?;
+
+    let address = |address: &str| {
+        environment
+            .address(address)
+            .ok_or_else(|| format!("unknown address `{address}` in `{name}`"))
+    };
+
+    let mut state = State::new();
+
+    for pair in file.pairs {
+        state.pairs.insert(
+            pair.id,
+            Pair {
+                token_0: address(&pair.token0)?,
+                token_1: address(&pair.token1)?,
+            },
+        );
+        state.last_pair = state.last_pair.max(Some(pair.id));
+    }
+
+    for pool in file.pools {
+        if !state.pairs.contains_key(&pool.pair_id) {
+            return Err(format!(
+                "pool {} has an unknown pair {}",
+                pool.id, pool.pair_id
+            ));
+        }
+
+        if pool.position > pool.liquidity {
+            return Err(format!(
+                "the position in pool {} is larger than its liquidity",
+                pool.id
+            ));
+        }
+
+        let controller = match &pool.controller {
+            Some(controller) => address(controller)?,
+            None => Address::zero(),
+        };
+
+        let parameters = Parameters {
+            controller,
+            priority_fee: pool.priority_fee,
+            fee: pool.fee,
+            vol: pool.vol,
+            dur: pool.dur,
+            max_price: pool.max_price,
+            price: pool.price,
+        };
+
+        let model = Pool::new(pool.pair_id, parameters)
+            .map_err(|error| format!("invalid pool {}: {error}", pool.id))?;
+
+        state.pools.insert(
+            pool.id,
+            Pool {
+                liquidity: pool.liquidity,
+                ..model
+            },
+        );
+
+        if pool.position > 0.0 {
+            state.positions.insert(
+                pool.id,
+                Position {
+                    liquidity: pool.position,
+                    ..Position::default()
+                },
+            );
+        }
+
+        state.last_pool = Some(pool.id);
+    }
+
+    for (token, balance) in file.balances {
+        state.balances.insert(address(&token)?, balance);
+    }
+
+    Ok(state)
+}
+
+/// Print the trace of a simulation and the final balances, or the instruction that reverted.
+pub fn print(simulation: &Simulation, environment: &Environment) {
+    for line in trace(simulation, environment) {
+        println!("{line}");
+    }
+}
+
+/// The lines [`print`] prints.
+fn trace(simulation: &Simulation, environment: &Environment) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for (index, step) in simulation.steps.iter().enumerate() {
+        lines.push(format!(
+            "{index:>3}  {}",
+            step.opcode.instruction().name.bold()
+        ));
+
+        for delta in &step.deltas {
+            lines.push(format!("       {delta}"));
+        }
+    }
+
+    if let Some(revert) = &simulation.revert {
+        let name = simulation.steps[revert.index].opcode.instruction().name;
+
+        lines.push(
+            format!(
+                "reverted at instruction {} (`{name}`): {}",
+                revert.index, revert.reason
+            )
+            .red()
+            .bold()
+            .to_string(),
+        );
+    } else {
+        lines.push("balances".bold().to_string());
+
+        for (token, balance) in &simulation.state.balances {
+            let name = environment
+                .addresses
+                .iter()
+                .find(|(_, address)| *address == token)
+                .map(|(name, _)| format!(" ({name})"))
+                .unwrap_or_default();
+
+            lines.push(format!("       {token:?}{name}  {balance}"));
+        }
+    }
+
+    if !simulation.is_exact() {
+        lines.push(format!(
+            "{}: amounts above {EXACT} are rounded, so they are approximate",
+            "note".bold()
+        ));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use compiler::opcode::{Opcode, U256};
+
+    use super::*;
+
+    const TOKEN_0: Address = Address::repeat_byte(1);
+    const TOKEN_1: Address = Address::repeat_byte(2);
+
+    fn environment() -> Environment {
+        let mut environment = Environment::new();
+        environment.addresses.insert("usdc".to_string(), TOKEN_0);
+        environment.addresses.insert("weth".to_string(), TOKEN_1);
+
+        environment
+    }
+
+    /// The trace of `simulation`, without colors.
+    fn plain(simulation: &Simulation) -> Vec<String> {
+        colored::control::set_override(false);
+        trace(simulation, &environment())
+    }
+
+    #[test]
+    fn state_files() {
+        let state = parse(
+            r#"{
+                "pairs": [{ "id": 1, "token0": "usdc", "token1": "0x0202020202020202020202020202020202020202" }],
+                "pools": [{
+                    "id": 7, "pairId": 1, "controller": "weth", "fee": 30, "vol": 1000, "dur": 365,
+                    "maxPrice": 2e18, "price": 1e18, "liquidity": 100, "position": 40
+                }],
+                "balances": { "usdc": 5, "weth": 6 }
+            }"#,
+            "state.json",
+            &environment(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            state.pairs[&1],
+            Pair {
+                token_0: TOKEN_0,
+                token_1: TOKEN_1
+            }
+        );
+        assert_eq!(state.last_pair, Some(1));
+        assert_eq!(state.last_pool, Some(7));
+
+        let pool = &state.pools[&7];
+        assert_eq!(pool.controller, TOKEN_1);
+        assert_eq!(pool.liquidity, 100.0);
+        assert!((pool.price() - 1.0).abs() < 1e-6);
+
+        assert_eq!(state.positions[&7].liquidity, 40.0);
+        assert_eq!(state.balances[&TOKEN_0], 5.0);
+        assert_eq!(state.balances[&TOKEN_1], 6.0);
+
+        assert_eq!(parse("{}", "state.json", &environment()), Ok(State::new()));
+    }
+
+    #[test]
+    fn invalid_state_files() {
+        let error = |contents: &str| parse(contents, "state.json", &environment()).unwrap_err();
+        let pool = |fields: &str| {
+            format!(
+                r#"{{
+                    "pairs": [{{ "id": 1, "token0": "usdc", "token1": "weth" }}],
+                    "pools": [{{ "id": 7, "fee": 30, "vol": 1000, "dur": 365, "maxPrice": 2e18, "price": 1e18, {fields} }}]
+                }}"#
+            )
+        };
+
+        assert!(error(r#"{ "accounts": [] }"#)
+            .starts_with("invalid state `state.json`: unknown field `accounts`"));
+        assert_eq!(
+            error(r#"{ "balances": { "dai": 1 } }"#),
+            "unknown address `dai` in `state.json`"
+        );
+        assert_eq!(
+            error(&pool(r#""pairId": 2"#)),
+            "pool 7 has an unknown pair 2"
+        );
+        assert_eq!(
+            error(&pool(r#""pairId": 1, "liquidity": 1, "position": 2"#)),
+            "the position in pool 7 is larger than its liquidity"
+        );
+        assert_eq!(
+            error(&pool(r#""pairId": 1, "priorityFee": 40"#)),
+            "invalid pool 7: fee of 30 and priority fee of 40 are out of bounds"
+        );
+    }
+
+    #[test]
+    fn traces() {
+        let mut state = State::new();
+        state.balances.insert(TOKEN_0, 5.0);
+
+        let simulation = state.simulate(&[Opcode::CreatePair {
+            token_0: TOKEN_0,
+            token_1: TOKEN_1,
+        }]);
+
+        assert_eq!(
+            plain(&simulation),
+            [
+                "  0  createPair",
+                "       created pair 1",
+                "balances",
+                "       0x0101010101010101010101010101010101010101 (usdc)  5",
+            ]
+        );
+    }
+
+    #[test]
+    fn reverts() {
+        let simulation = State::new().simulate(&[
+            Opcode::CreatePair {
+                token_0: TOKEN_0,
+                token_1: TOKEN_1,
+            },
+            Opcode::Claim {
+                pool_id: U256::from(3),
+                fee_0: U256::zero(),
+                fee_1: U256::zero(),
+            },
+        ]);
+
+        assert_eq!(
+            plain(&simulation),
+            [
+                "  0  createPair",
+                "       created pair 1",
+                "  1  claim",
+                "reverted at instruction 1 (`claim`): unknown pool 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn approximate_amounts() {
+        let mut state = State::new();
+        state.balances.insert(TOKEN_1, 1e18 + 1.0);
+
+        let lines = plain(&state.simulate(&[]));
+
+        assert_eq!(
+            lines.last().unwrap(),
+            "note: amounts above 9007199254740992 are rounded, so they are approximate"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! # Stats
+//!
+//! `folio build --stats`, which reports what each instruction of a program adds to its calldata.
+//! Calldata is priced per byte as in EIP-2028, so bytes are counted as zero or non-zero,
+//! and attributed to the macro each instruction was written in.
+//! The bytes the entrypoint adds around the payload, such as the selector and abi encoding of `multiprocess`, are counted on their own.
+
+use std::{collections::BTreeMap, ops::AddAssign};
+
+use colored::Colorize;
+use compiler::{
+    codegen::Codegen,
+    sourcemap::Origin,
+    transaction::{self, Entrypoint},
+};
+
+/// Gas of a zero byte of calldata.
+const ZERO_BYTE_GAS: usize = 4;
+/// Gas of a non-zero byte of calldata.
+const NON_ZERO_BYTE_GAS: usize = 16;
+
+/// The zero and non-zero bytes of some calldata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Bytes {
+    zero: usize,
+    non_zero: usize,
+}
+
+impl Bytes {
+    fn of(bytes: &[u8]) -> Self {
+        let zero = bytes.iter().filter(|byte| **byte == 0).count();
+
+        Self {
+            zero,
+            non_zero: bytes.len() - zero,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.zero + self.non_zero
+    }
+
+    fn gas(&self) -> usize {
+        self.zero * ZERO_BYTE_GAS + self.non_zero * NON_ZERO_BYTE_GAS
+    }
+
+    /// A row of the report.
+    fn row(&self, label: &str) -> String {
+        format!(
+            "{label:<24}{:>7}{:>7}{:>10}{:>9}",
+            self.len(),
+            self.zero,
+            self.non_zero,
+            self.gas()
+        )
+    }
+}
+
+impl AddAssign for Bytes {
+    fn add_assign(&mut self, other: Self) {
+        self.zero += other.zero;
+        self.non_zero += other.non_zero;
+    }
+}
+
+/// The calldata cost of a program.
+#[derive(Debug)]
+struct Report<'a> {
+    /// Index, name and macro of each encoded instruction, with its length prefix.
+    instructions: Vec<(usize, &'static str, &'a str, Bytes)>,
+    /// The `jump` and instruction count the payload starts with.
+    header: Bytes,
+    /// The bytes the entrypoint adds around the payload.
+    encoding: Bytes,
+    /// Every byte of the calldata.
+    total: Bytes,
+    by_macro: BTreeMap<&'a str, Bytes>,
+}
+
+impl<'a> Report<'a> {
+    /// The cost of calling `entrypoint` with `payload`, given the opcodes it was generated from and their origins.
+    fn new(
+        codegen: &Codegen,
+        origins: &'a [Origin],
+        payload: &[u8],
+        entrypoint: Entrypoint,
+    ) -> Self {
+        let mut instructions = Vec::new();
+        let mut by_macro = BTreeMap::<&str, Bytes>::new();
+
+        // The payload starts with `jump` and the instruction count.
+        let (header, mut rest) = payload.split_at(2.min(payload.len()));
+
+        for (index, (opcode, origin)) in codegen.opcodes().iter().zip(origins).enumerate() {
+            // Some opcodes, like `unknown`, are not encoded at all.
+            if Codegen::from(vec![opcode.clone()]).encode().is_empty() {
+                continue;
+            }
+
+            // Each instruction is prefixed with its length.
+            let length = rest.first().map_or(0, |length| 1 + usize::from(*length));
+            let (instruction, tail) = rest.split_at(length.min(rest.len()));
+            let bytes = Bytes::of(instruction);
+            rest = tail;
+
+            // Instructions are attributed to the macro they were written in.
+            let mac = origin.stack.last().map_or("-", |frame| frame.name.as_str());
+            *by_macro.entry(mac).or_default() += bytes;
+            instructions.push((index, opcode.instruction().name, mac, bytes));
+        }
+
+        let (total, bytes) = (
+            Bytes::of(&transaction::calldata(payload, entrypoint)),
+            Bytes::of(payload),
+        );
+
+        Self {
+            instructions,
+            header: Bytes::of(header),
+            encoding: Bytes {
+                zero: total.zero - bytes.zero,
+                non_zero: total.non_zero - bytes.non_zero,
+            },
+            total,
+            by_macro,
+        }
+    }
+}
+
+/// Print the calldata cost of each instruction of a program, of the `jump` that wraps them,
+/// of the encoding of `entrypoint`, and of each macro, given the origin of each opcode and the payload generated from them.
+pub fn print(
+    title: &str,
+    codegen: &Codegen,
+    origins: &[Origin],
+    payload: &[u8],
+    entrypoint: Entrypoint,
+) {
+    let report = Report::new(codegen, origins, payload, entrypoint);
+
+    eprintln!("{}", title.bold());
+    eprintln!(
+        "{}",
+        format!(
+            "{:<24}{:>7}{:>7}{:>10}{:>9}  macro",
+            "instruction", "bytes", "zero", "non-zero", "gas"
+        )
+        .dimmed()
+    );
+
+    for (index, name, mac, bytes) in &report.instructions {
+        eprintln!("{}  {mac}", bytes.row(&format!("{index:>3}  {name}")));
+    }
+
+    eprintln!("{}", report.header.row("     jump"));
+
+    if report.encoding.len() > 0 {
+        eprintln!("{}", report.encoding.row(&format!("     {entrypoint}")));
+    }
+
+    eprintln!("{}", report.total.row("     total").bold());
+
+    eprintln!(
+        "{}",
+        format!(
+            "{:<24}{:>7}{:>7}{:>10}{:>9}",
+            "macro", "bytes", "zero", "non-zero", "gas"
+        )
+        .dimmed()
+    );
+
+    for (mac, bytes) in &report.by_macro {
+        eprintln!("{}", bytes.row(&format!("     {mac}")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compiler::{
+        opcode::{Opcode, U256},
+        sourcemap::Frame,
+    };
+
+    use super::*;
+
+    fn origin(mac: &str) -> Origin {
+        Origin {
+            location: None,
+            stack: vec![Frame {
+                name: mac.to_string(),
+                location: None,
+            }],
+        }
+    }
+
+    fn program() -> (Codegen, Vec<Origin>) {
+        let codegen = Codegen::from(vec![
+            Opcode::Allocate {
+                use_max: U256::zero(),
+                pool_id: U256::from(34),
+                delta_liquidity: U256::from(10),
+            },
+            Opcode::Unknown,
+            Opcode::Deallocate {
+                use_max: U256::one(),
+                pool_id: U256::from(34),
+                delta_liquidity: U256::from(10),
+            },
+        ]);
+
+        (
+            codegen,
+            vec![origin("main"), origin("main"), origin("exit")],
+        )
+    }
+
+    #[test]
+    fn fallback() {
+        let (codegen, origins) = program();
+        let payload = codegen.payload().unwrap();
+        let report = Report::new(&codegen, &origins, &payload, Entrypoint::Fallback);
+
+        // The length `1a`, the opcode `01`, 7 zeros and `22` for the pool, and `01` then 15 zeros and `01` for the amount.
+        let allocate = Bytes {
+            zero: 22,
+            non_zero: 5,
+        };
+        // The same, with `useMax` set in the opcode.
+        let deallocate = allocate;
+
+        assert_eq!(
+            report.instructions,
+            [
+                (0, "allocate", "main", allocate),
+                (2, "deallocate", "exit", deallocate),
+            ]
+        );
+        assert_eq!(
+            report.header,
+            Bytes {
+                zero: 0,
+                non_zero: 2
+            }
+        );
+        assert_eq!(report.encoding, Bytes::default());
+        assert_eq!(report.total, Bytes::of(&payload));
+        assert_eq!(report.total.len(), 2 + 27 + 27);
+        assert_eq!(report.total.gas(), 44 * 4 + 12 * 16);
+        assert_eq!(report.by_macro["main"], allocate);
+    }
+
+    #[test]
+    fn multiprocess() {
+        let (codegen, origins) = program();
+        let payload = codegen.payload().unwrap();
+        let report = Report::new(&codegen, &origins, &payload, Entrypoint::Multiprocess);
+        let fallback = Report::new(&codegen, &origins, &payload, Entrypoint::Fallback);
+
+        // A selector, a word for the offset of the bytes and one for their length, and the payload padded to a word.
+        let selector = Bytes::of(&transaction::calldata(&[], Entrypoint::Multiprocess)[..4]);
+        let encoding = Bytes {
+            zero: selector.zero + 31 + 31 + (64 - payload.len()),
+            non_zero: selector.non_zero + 1 + 1,
+        };
+
+        assert_eq!(report.instructions, fallback.instructions);
+        assert_eq!(report.encoding, encoding);
+        assert_eq!(report.total.len(), 4 + 32 + 32 + 64);
+        assert_eq!(
+            report.total,
+            Bytes::of(&transaction::calldata(&payload, Entrypoint::Multiprocess))
+        );
+        assert_eq!(
+            report.total.gas(),
+            fallback.total.gas() + encoding.zero * 4 + encoding.non_zero * 16
+        );
+    }
+}
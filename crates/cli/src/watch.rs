@@ -0,0 +1,276 @@
+//! # Watch
+//!
+//! `folio build --watch`, which rebuilds whenever a file the build read changes.
+//! After each rebuild, the instructions that changed in each artifact are printed as a diff.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use colored::Colorize;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{build, report, Build, BuildArgs};
+
+/// Time to wait for further changes before rebuilding, since editors often write a file in several steps.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Build, then rebuild on every change until interrupted.
+pub fn watch(args: &BuildArgs) {
+    if args.paths.iter().any(|path| path == "-") {
+        report("`--watch` cannot read from stdin");
+        std::process::exit(1);
+    }
+
+    let mut changes = match Changes::new() {
+        Ok(changes) => changes,
+        Err(message) => {
+            report(&message);
+            std::process::exit(1);
+        }
+    };
+
+    let mut previous = build(args);
+
+    loop {
+        if let Err(message) = changes.wait(&previous.dependencies) {
+            report(&message);
+            std::process::exit(1);
+        }
+
+        eprintln!("{}", "Change detected, rebuilding".bold());
+
+        let mut next = build(args);
+        diff(&previous, &next);
+
+        // Keep the last good artifacts of sources that failed, so that fixing them shows a diff.
+        for (key, instructions) in previous.artifacts {
+            next.artifacts.entry(key).or_insert(instructions);
+        }
+
+        next.dependencies.extend(previous.dependencies);
+        previous = next;
+    }
+}
+
+/// Changes to the files of a build.
+/// A single watcher runs for the whole session, so that changes made while rebuilding are queued rather than missed.
+struct Changes {
+    watcher: RecommendedWatcher,
+    receiver: Receiver<notify::Result<Event>>,
+    /// Directories being watched.
+    directories: BTreeSet<PathBuf>,
+}
+
+impl Changes {
+    fn new() -> Result<Self, String> {
+        let (sender, receiver) = mpsc::channel();
+
+        let watcher = notify::recommended_watcher(sender)
+            .map_err(|error| format!("could not watch for changes: {error}"))?;
+
+        Ok(Self {
+            watcher,
+            receiver,
+            directories: BTreeSet::new(),
+        })
+    }
+
+    /// Block until a dependency, or a source file next to one, is created, modified or removed.
+    fn wait(&mut self, dependencies: &BTreeSet<PathBuf>) -> Result<(), String> {
+        // Directories are watched rather than files, since editors often replace a file instead of writing to it.
+        let directories = dependencies
+            .iter()
+            .filter_map(|path| path.parent())
+            .chain(std::iter::once(Path::new(".")))
+            .map(Path::to_path_buf)
+            .collect::<BTreeSet<_>>();
+
+        for directory in directories.difference(&self.directories) {
+            self.watcher
+                .watch(directory, RecursiveMode::NonRecursive)
+                .map_err(|error| format!("could not watch `{}`: {error}", directory.display()))?;
+        }
+
+        self.directories.extend(directories);
+
+        let relevant = |event: notify::Result<Event>| {
+            event.is_ok_and(|event| {
+                matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) && event.paths.iter().any(|path| {
+                    dependencies.contains(path)
+                        || path.extension().is_some_and(|extension| extension == "fvm")
+                })
+            })
+        };
+
+        // Changes made during the last build are already queued, and trigger a rebuild straight away.
+        loop {
+            let event = self
+                .receiver
+                .recv()
+                .map_err(|error| format!("could not watch for changes: {error}"))?;
+
+            if relevant(event) {
+                break;
+            }
+        }
+
+        while self.receiver.recv_timeout(DEBOUNCE).is_ok() {}
+
+        Ok(())
+    }
+}
+
+/// Print the instructions that changed in each artifact between two builds.
+fn diff(previous: &Build, next: &Build) {
+    for ((source, entry), instructions) in &next.artifacts {
+        let name = match entry.as_str() {
+            "main" => source.clone(),
+            entry => format!("{source} ({entry})"),
+        };
+
+        let Some(old) = previous.artifacts.get(&(source.clone(), entry.clone())) else {
+            eprintln!("{name}: {} instructions", instructions.len());
+            continue;
+        };
+
+        if old == instructions {
+            eprintln!("{name}: unchanged");
+            continue;
+        }
+
+        eprintln!("{name}:");
+
+        for line in lines(old, instructions) {
+            match line {
+                Line::Kept(instruction) => eprintln!("    {instruction}"),
+                Line::Removed(instruction) => eprintln!("{}", format!("  - {instruction}").red()),
+                Line::Added(instruction) => eprintln!("{}", format!("  + {instruction}").green()),
+            }
+        }
+    }
+}
+
+/// A line of a diff.
+#[derive(Debug, PartialEq, Eq)]
+enum Line<'a> {
+    Kept(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Diff two lists of instructions, using their longest common subsequence.
+fn lines<'a>(old: &'a [String], new: &'a [String]) -> Vec<Line<'a>> {
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(Line::Kept(&old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            lines.push(Line::Removed(&old[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(&new[j]));
+            j += 1;
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instructions(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn unchanged() {
+        let old = instructions(&["a", "b"]);
+
+        assert_eq!(lines(&old, &old), [Line::Kept("a"), Line::Kept("b")]);
+    }
+
+    #[test]
+    fn added_and_removed() {
+        let old = instructions(&["a", "b", "c"]);
+        let new = instructions(&["a", "c", "d"]);
+
+        assert_eq!(
+            lines(&old, &new),
+            [
+                Line::Kept("a"),
+                Line::Removed("b"),
+                Line::Kept("c"),
+                Line::Added("d")
+            ]
+        );
+    }
+
+    #[test]
+    fn replaced() {
+        let old = instructions(&["a", "b", "c"]);
+        let new = instructions(&["a", "x", "c"]);
+
+        assert_eq!(
+            lines(&old, &new),
+            [
+                Line::Kept("a"),
+                Line::Removed("b"),
+                Line::Added("x"),
+                Line::Kept("c")
+            ]
+        );
+    }
+
+    #[test]
+    fn longest_common_subsequence() {
+        // Keeping `b c d` is longer than keeping `a`, even though `a` comes first.
+        let old = instructions(&["a", "b", "c", "d"]);
+        let new = instructions(&["b", "c", "d", "a"]);
+
+        assert_eq!(
+            lines(&old, &new),
+            [
+                Line::Removed("a"),
+                Line::Kept("b"),
+                Line::Kept("c"),
+                Line::Kept("d"),
+                Line::Added("a")
+            ]
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let new = instructions(&["a"]);
+
+        assert_eq!(lines(&[], &new), [Line::Added("a")]);
+        assert_eq!(lines(&new, &[]), [Line::Removed("a")]);
+        assert!(lines(&[], &[]).is_empty());
+    }
+}
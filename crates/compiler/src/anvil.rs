@@ -0,0 +1,159 @@
+//! # Anvil
+//!
+//! A harness for end-to-end tests, behind the `anvil` feature.
+//! [`Harness`] spawns a local anvil node, deploys the FVM contract to it and sends it programs built by folio,
+//! so that tests can check the state the encoded calldata leaves on chain, without any external network.
+//!
+//! The FVM contract is not part of this repository. Its creation bytecode is read from the file named by
+//! `FOLIO_FVM_ARTIFACT`, either a forge artifact or bare hex, followed by the hex-encoded constructor arguments
+//! in `FOLIO_FVM_CONSTRUCTOR_ARGS`, if any. Tests using the harness are ignored unless run with `--ignored`,
+//! and fail if anvil is not on the path or no artifact is given.
+
+use std::{env, fs, process::Command};
+
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::{Address, Bytes, TransactionReceipt, TransactionRequest, U64},
+    utils::{hex, Anvil, AnvilInstance},
+};
+use tokio::runtime::Runtime;
+
+use crate::transaction::{self, Entrypoint};
+
+/// Environment variable naming the file that holds the creation bytecode of the FVM contract.
+pub const ARTIFACT: &str = "FOLIO_FVM_ARTIFACT";
+
+/// Environment variable holding the hex-encoded constructor arguments of the FVM contract.
+pub const CONSTRUCTOR_ARGS: &str = "FOLIO_FVM_CONSTRUCTOR_ARGS";
+
+/// Creation code of a stand-in for a token. `decimals()` returns `18`, and every other call returns `1`,
+/// which makes any transfer or approval look successful.
+const TOKEN: &str =
+    "602480600b6000396000f360003560e01c63313ce56714601957600160005260206000f35b601260005260206000f3";
+
+/// A local node with the FVM contract deployed to it.
+pub struct Harness {
+    runtime: Runtime,
+    provider: Provider<Http>,
+    /// Kept alive for as long as the harness, since dropping it stops the node.
+    _anvil: AnvilInstance,
+    /// Unlocked account every transaction is sent from.
+    pub sender: Address,
+    /// Address of the FVM contract.
+    pub fvm: Address,
+}
+
+impl Harness {
+    /// Spawn a node and deploy the FVM contract.
+    pub fn spawn() -> Result<Self, String> {
+        if Command::new("anvil").arg("--version").output().is_err() {
+            return Err("`anvil` is not installed".to_string());
+        }
+
+        let path = env::var(ARTIFACT).map_err(|_| format!("`{ARTIFACT}` is not set"))?;
+
+        let mut code = Harness::bytecode(&path)?;
+
+        if let Ok(arguments) = env::var(CONSTRUCTOR_ARGS) {
+            code.extend(
+                hex::decode(arguments.trim().trim_start_matches("0x"))
+                    .map_err(|error| format!("invalid `{CONSTRUCTOR_ARGS}`: {error}"))?,
+            );
+        }
+
+        let anvil = Anvil::new().spawn();
+        let provider = Provider::<Http>::try_from(anvil.endpoint())
+            .map_err(|error| format!("could not connect to anvil: {error}"))?;
+        let runtime = Runtime::new().map_err(|error| error.to_string())?;
+
+        let mut harness = Self {
+            runtime,
+            provider,
+            sender: anvil.addresses()[0],
+            _anvil: anvil,
+            fvm: Address::zero(),
+        };
+
+        harness.fvm = harness.deploy(code)?;
+        Ok(harness)
+    }
+
+    /// Deploy a contract from its creation code, returning its address.
+    pub fn deploy(&self, code: Vec<u8>) -> Result<Address, String> {
+        let receipt = self.send(TransactionRequest::new().data(code))?;
+
+        receipt
+            .contract_address
+            .ok_or_else(|| "the deployment created no contract".to_string())
+    }
+
+    /// Deploy a stand-in for a token, returning its address.
+    pub fn token(&self) -> Result<Address, String> {
+        self.deploy(hex::decode(TOKEN).unwrap())
+    }
+
+    /// Send a payload to the FVM contract through `entrypoint`, returning the receipt of the transaction.
+    /// A transaction that reverts is an error.
+    pub fn process(
+        &self,
+        payload: &[u8],
+        entrypoint: Entrypoint,
+    ) -> Result<TransactionReceipt, String> {
+        self.send(
+            TransactionRequest::new()
+                .to(self.fvm)
+                .data(transaction::calldata(payload, entrypoint)),
+        )
+    }
+
+    /// Call the FVM contract without sending a transaction, returning what it returned.
+    pub fn call(&self, calldata: Vec<u8>) -> Result<Bytes, String> {
+        let request = TransactionRequest::new().to(self.fvm).data(calldata).into();
+
+        self.runtime
+            .block_on(self.provider.call(&request, None))
+            .map_err(|error| format!("call failed: {error}"))
+    }
+
+    fn send(&self, request: TransactionRequest) -> Result<TransactionReceipt, String> {
+        let request = request.from(self.sender);
+
+        let receipt = self.runtime.block_on(async {
+            self.provider
+                .send_transaction(request, None)
+                .await
+                .map_err(|error| format!("could not send the transaction: {error}"))?
+                .await
+                .map_err(|error| format!("could not get the receipt: {error}"))
+        })?;
+
+        let receipt = receipt.ok_or("the transaction was dropped")?;
+
+        if receipt.status != Some(U64::one()) {
+            return Err(format!(
+                "transaction {:?} reverted",
+                receipt.transaction_hash
+            ));
+        }
+
+        Ok(receipt)
+    }
+
+    /// Creation code from a forge artifact, or a file of bare hex.
+    fn bytecode(path: &str) -> Result<Vec<u8>, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| format!("could not read `{path}`: {error}"))?;
+
+        let code = match serde_json::from_str::<serde_json::Value>(&contents) {
+            Ok(artifact) => artifact["bytecode"]["object"]
+                .as_str()
+                .or_else(|| artifact["bytecode"].as_str())
+                .ok_or_else(|| format!("`{path}` has no `bytecode`"))?
+                .to_string(),
+            Err(_) => contents,
+        };
+
+        hex::decode(code.trim().trim_start_matches("0x"))
+            .map_err(|error| format!("`{path}` does not hold valid bytecode: {error}"))
+    }
+}
@@ -1,17 +1,24 @@
+//! # Assembler
+//!
+//! The parsing module of Folio.
+//! Convert a [`Vec<Token>`] into a abstract syntax tree.
+//! This can be used by downstream code generation modules to generate FVM bytecode.
+
 use std::{cell::Cell, collections::HashMap};
 
 use eth_encode_packed::ethabi::ethereum_types::{Address, U256};
 
 use crate::{
-    opcode::Opcode,
-    token::{Token, TokenType},
+    environment::Environment,
+    error::Error,
+    opcode::{Argument, Opcode},
+    semantic::Externs,
+    spec::{self, Kind},
+    token::{Span, Spanned, Token, TokenType},
 };
 
-/// # Assembler
-///
-/// The parsing module of Folio.
-/// Convert a [`Vec<Token>`] into a abstract syntax tree.
-/// This can be used by downstream code generation modules to generate FVM bytecode.
+/// Definitions used by assemblers constructed without an environment.
+static EMPTY: Environment = Environment::new();
 
 /// Type representing an Opcode parser.
 #[derive(Debug)]
@@ -20,11 +27,20 @@ pub struct Assembler<'a> {
     pub tokens: Vec<Token<'a>>,
     /// Cursor
     pub cursor: Cell<usize>,
+    /// Named addresses, token decimals and parameter defaults available to the source.
+    pub environment: &'a Environment,
+    /// Spans of literals standing in for values only known later, which are not validated.
+    pub placeholders: &'a [Span],
 }
 
 /// Represents an expression.
 /// An expression is a node in the AST, parsed inside macros.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "camelCase")
+)]
 #[allow(clippy::large_enum_variant)]
 pub enum Expression<'a> {
     /// An opcode.
@@ -33,13 +49,28 @@ pub enum Expression<'a> {
     Invocation(&'a str),
 }
 
+/// Expressions with their spans, as in the body of a macro.
+pub type Body<'a> = Vec<Spanned<Expression<'a>>>;
+
+/// The macros an expanded expression is nested in, outermost first:
+/// the entry point, spanning its name, then each invoked macro, spanning the invocation.
+pub type Stack<'a> = Vec<Spanned<&'a str>>;
+
 /// Represents a macro, a reusable building block of opcodes.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Macro<'a> {
     /// Macro name.
     pub name: &'a str,
+    /// Span of the macro name.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
+    /// Whether the macro is marked `pub` or `entry`, and so is built as an entry point.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub entry: bool,
     /// Body of the macro: the opcodes inside of it.
-    pub body: Vec<Expression<'a>>,
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub body: Vec<Spanned<Expression<'a>>>,
 }
 
 impl<'a> Assembler<'a> {
@@ -48,297 +79,845 @@ impl<'a> Assembler<'a> {
         Self {
             tokens,
             cursor: Cell::new(0),
+            environment: &EMPTY,
+            placeholders: &[],
+        }
+    }
+
+    /// Resolve names, units and defaults against `environment`.
+    pub fn with_environment(mut self, environment: &'a Environment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Skip validation of the literals at `placeholders`, whose values are substituted after parsing.
+    pub fn with_placeholders(mut self, placeholders: &'a [Span]) -> Self {
+        self.placeholders = placeholders;
+        self
+    }
+
+    /// The path of every `import` statement in the token stream.
+    pub fn imports(&self) -> Vec<Spanned<&'a str>> {
+        self.tokens
+            .windows(2)
+            .filter(|pair| {
+                pair[0].ttype == TokenType::Import && pair[1].ttype == TokenType::StringLiteral
+            })
+            .map(|pair| Spanned::new(pair[1].slice.trim_matches('"'), pair[1].span))
+            .collect()
+    }
+
+    /// The pairs and pools declared with `extern pair` and `extern pool` in the token stream.
+    pub fn externs(&self) -> Externs {
+        let mut externs = Externs::default();
+
+        for window in self.tokens.windows(3) {
+            let (Some(id), TokenType::Extern) =
+                (U256::from_dec_str(window[2].slice).ok(), window[0].ttype)
+            else {
+                continue;
+            };
+
+            match window[1].slice {
+                "pair" => externs.pairs.insert(id),
+                "pool" => externs.pools.insert(id),
+                _ => continue,
+            };
         }
+
+        externs
     }
 
     /// Parse a vector of tokens, lexed from a source file, into an AST.
-    pub fn parse(tokens: Vec<Token<'a>>) -> Vec<Expression> {
-        let mut main_macro: Macro;
-        let mut occurences: usize = 0;
+    /// Every invocation reachable from the `main` macro is expanded in place.
+    pub fn parse(tokens: Vec<Token<'a>>) -> Result<Vec<Expression<'a>>, Error> {
+        Assembler::new(tokens).assemble()
+    }
+
+    /// Parse the token stream and expand the `main` macro.
+    pub fn assemble(&self) -> Result<Vec<Expression<'a>>, Error> {
+        self.assemble_entry("main")
+    }
+
+    /// Parse the token stream and expand the macro `entry`.
+    pub fn assemble_entry(&self, entry: &str) -> Result<Vec<Expression<'a>>, Error> {
+        let macros = self.parse_macros()?;
+        let body = Assembler::expand(&macros, entry)?;
+
+        Ok(body.into_iter().map(|expression| expression.node).collect())
+    }
+
+    /// Parse the token stream and expand every entry point, in order of definition.
+    /// A source without any `pub` or `entry` macros has `main` as its only entry point.
+    pub fn assemble_entries(&self) -> Result<Vec<(&'a str, Vec<Expression<'a>>)>, Error> {
+        Ok(self
+            .expand_entries()?
+            .into_iter()
+            .map(|(name, body)| {
+                (
+                    name,
+                    body.into_iter().map(|expression| expression.node).collect(),
+                )
+            })
+            .collect())
+    }
+
+    /// Parse the token stream and expand every entry point, keeping the span of each expression.
+    pub fn expand_entries(&self) -> Result<Vec<(&'a str, Body<'a>)>, Error> {
+        let macros = self.parse_macros()?;
+
+        Assembler::entries(&macros)
+            .into_iter()
+            .map(|name| Ok((name, Assembler::expand(&macros, name)?)))
+            .collect()
+    }
+
+    /// The name of every entry point, in order of definition: each `pub` or `entry` macro, or `main` if there are none.
+    pub fn entries(macros: &HashMap<&'a str, Macro<'a>>) -> Vec<&'a str> {
+        let mut entries = macros.values().filter(|mac| mac.entry).collect::<Vec<_>>();
+        entries.sort_by_key(|mac| mac.span.start);
+
+        if entries.is_empty() {
+            vec!["main"]
+        } else {
+            entries.iter().map(|mac| mac.name).collect()
+        }
+    }
+
+    /// Parse every macro definition in the token stream, keyed by name.
+    pub fn parse_macros(&self) -> Result<HashMap<&'a str, Macro<'a>>, Error> {
         let mut macros = HashMap::new();
 
-        let opcodes = Assembler::new(tokens.clone());
+        // Start from the first token, so that the stream can be assembled more than once.
+        self.cursor.set(0);
 
-        tokens.clone().into_iter().for_each(|token| {
-            if token.ttype == TokenType::Macro {
-                occurences += 1
+        while self.cursor.get() < self.tokens.len() {
+            // Imports are resolved by the loader before parsing.
+            if self.peek()?.ttype == TokenType::Import {
+                self.match_token(TokenType::Import)?;
+                self.match_token(TokenType::StringLiteral)?;
+                continue;
             }
-        });
 
-        for _ in 0..occurences {
-            let mac = opcodes.parse_macro().unwrap();
-
-            if macros.get(&mac.name).is_some() {
-                std::process::exit(1);
+            // Declarations are collected by `externs`, and only checked here.
+            if self.peek()?.ttype == TokenType::Extern {
+                self.parse_extern()?;
+                continue;
             }
 
-            if macros.get(&mac.name).is_none() {
-                macros.insert(mac.clone().name, mac);
+            let mac = self.parse_macro()?;
+
+            if macros.contains_key(&mac.name) {
+                return Err(Error::new(
+                    format!("macro `{}` is defined more than once", mac.name),
+                    mac.span,
+                ));
             }
-        }
 
-        match macros.get(&"main") {
-            Some(r#main) => main_macro = r#main.clone(),
-            None => panic!("no main macro found"),
+            macros.insert(mac.name, mac);
         }
 
-        loop {
-            let mut invocation_found = false;
+        Ok(macros)
+    }
+
+    /// Expand the macro `name`, recursively replacing invocations with the bodies of the macros they name.
+    pub fn expand(
+        macros: &HashMap<&'a str, Macro<'a>>,
+        name: &str,
+    ) -> Result<Vec<Spanned<Expression<'a>>>, Error> {
+        Ok(Assembler::trace(macros, name)?
+            .into_iter()
+            .map(|(expression, _)| expression)
+            .collect())
+    }
+
+    /// Expand the macro `name`, pairing each expression with the stack of macros it was expanded from.
+    pub fn trace(
+        macros: &HashMap<&'a str, Macro<'a>>,
+        name: &str,
+    ) -> Result<Vec<(Spanned<Expression<'a>>, Stack<'a>)>, Error> {
+        let entry = macros
+            .get(name)
+            .ok_or_else(|| Error::new(format!("no `{name}` macro found"), Span::default()))?;
+
+        let mut body = Vec::new();
+        let mut stack = vec![Spanned::new(entry.name, entry.span)];
+        Assembler::expand_into(macros, &entry.body, &mut stack, &mut body)?;
 
-            for (i, n) in main_macro.clone().body.iter().enumerate() {
-                match n {
-                    Expression::Invocation(slice) => {
-                        let replacer = macros.get(slice);
-                        let mut index: usize = i;
+        Ok(body)
+    }
 
-                        main_macro.body.remove(i);
+    /// Expand every invocation in `expressions`, which are not part of any macro.
+    pub fn expand_body(
+        macros: &HashMap<&'a str, Macro<'a>>,
+        expressions: &[Spanned<Expression<'a>>],
+    ) -> Result<Vec<Spanned<Expression<'a>>>, Error> {
+        let mut body = Vec::new();
+        Assembler::expand_into(macros, expressions, &mut Vec::new(), &mut body)?;
 
-                        for g in &replacer.unwrap().body {
-                            main_macro.body.insert(index, g.clone());
-                            index += 1;
-                        }
+        Ok(body.into_iter().map(|(expression, _)| expression).collect())
+    }
 
-                        invocation_found = true;
+    fn expand_into(
+        macros: &HashMap<&'a str, Macro<'a>>,
+        expressions: &[Spanned<Expression<'a>>],
+        stack: &mut Stack<'a>,
+        body: &mut Vec<(Spanned<Expression<'a>>, Stack<'a>)>,
+    ) -> Result<(), Error> {
+        for expression in expressions {
+            match expression.node {
+                Expression::Invocation(name) => {
+                    let invoked = macros.get(name).ok_or_else(|| {
+                        Error::new(format!("cannot find macro `{name}`"), expression.span)
+                    })?;
+
+                    if stack.iter().any(|frame| frame.node == name) {
+                        return Err(Error::new(
+                            format!("macro `{name}` invokes itself recursively"),
+                            expression.span,
+                        ));
                     }
-                    _ => continue,
-                }
-            }
 
-            if !invocation_found {
-                break;
+                    stack.push(Spanned::new(name, expression.span));
+                    Assembler::expand_into(macros, &invoked.body, stack, body)?;
+                    stack.pop();
+                }
+                _ => body.push((expression.clone(), stack.clone())),
             }
         }
 
-        // println!("{body:#?}");
+        Ok(())
+    }
+
+    /// The token under the cursor.
+    fn peek(&self) -> Result<Token<'a>, Error> {
+        self.tokens
+            .get(self.cursor.get())
+            .copied()
+            .ok_or_else(|| Error::new("unexpected end of input", self.eof()))
+    }
 
-        main_macro.body
+    /// An empty span just past the last token.
+    fn eof(&self) -> Span {
+        self.tokens
+            .last()
+            .map(|token| Span::new(token.span.end, token.span.end))
+            .unwrap_or_default()
     }
 
-    fn match_token(&self, expected: TokenType) -> Result<(), ()> {
-        if self.tokens[self.cursor.get()].ttype == expected {
-            let mut curr = self.cursor.get();
-            curr += 1;
-            self.cursor.set(curr);
+    fn unrecognized(token: &Token) -> Error {
+        Error::new(format!("unrecognized token `{}`", token.slice), token.span)
+    }
+
+    fn previous(&self) -> Token<'a> {
+        self.tokens[self.cursor.get() - 1]
+    }
+
+    fn match_token(&self, expected: TokenType) -> Result<(), Error> {
+        let token = self.tokens.get(self.cursor.get()).ok_or_else(|| {
+            Error::new(
+                format!("expected {expected}, found end of input"),
+                self.eof(),
+            )
+        })?;
+
+        if token.ttype == expected {
+            self.cursor.set(self.cursor.get() + 1);
             Ok(())
+        } else if token.ttype == TokenType::Error {
+            Err(Assembler::unrecognized(token))
         } else {
-            Err(())
+            Err(Error::new(
+                format!("expected {expected}, found `{}`", token.slice),
+                token.span,
+            ))
         }
     }
 
-    fn parse_parameter(&self, key: TokenType, value: TokenType) -> Result<(), ()> {
-        self.match_token(key)?;
-        self.match_token(TokenType::Colon)?;
-        self.match_token(value)?;
+    fn previous_literal(&self) -> Result<U256, Error> {
+        let token = self.previous();
 
-        Ok(())
+        U256::from_dec_str(token.slice).map_err(|_| {
+            Error::new(
+                format!("`{}` is not a valid unsigned integer", token.slice),
+                token.span,
+            )
+        })
+    }
+
+    /// Parse an address literal, or a name from the address book.
+    fn parse_address(&self) -> Result<Address, Error> {
+        let token = self.peek()?;
+
+        if token.ttype != TokenType::Identifier {
+            self.match_token(TokenType::AddressLiteral)?;
+            return self.previous_address();
+        }
+
+        self.match_token(TokenType::Identifier)?;
+
+        self.environment
+            .address(token.slice)
+            .ok_or_else(|| Error::new(format!("unknown address `{}`", token.slice), token.span))
+    }
+
+    /// Parse an integer literal, optionally followed by a token symbol it is denominated in.
+    fn parse_integer(&self) -> Result<U256, Error> {
+        self.match_token(TokenType::Literal)?;
+        let literal = self.previous();
+
+        let unit = self
+            .tokens
+            .get(self.cursor.get())
+            .filter(|token| token.ttype == TokenType::Identifier)
+            .filter(|token| self.environment.decimals.contains_key(token.slice));
+
+        match unit {
+            Some(unit) => {
+                self.match_token(TokenType::Identifier)?;
+
+                self.environment
+                    .scale(literal.slice, unit.slice)
+                    .ok_or_else(|| {
+                        Error::new(
+                            format!(
+                                "`{}` has more decimal places than `{}`",
+                                literal.slice, unit.slice
+                            ),
+                            literal.span.to(unit.span),
+                        )
+                    })
+            }
+            None => self.previous_literal(),
+        }
+    }
+
+    fn previous_address(&self) -> Result<Address, Error> {
+        let token = self.previous();
+
+        token.slice.parse::<Address>().map_err(|_| {
+            Error::new(
+                format!("`{}` is not a valid address", token.slice),
+                token.span,
+            )
+        })
+    }
+
+    /// Parse instructions and invocations outside of any macro, until the end of the token stream.
+    pub fn parse_body(&self) -> Result<Vec<Spanned<Expression<'a>>>, Error> {
+        let mut body = Vec::new();
+
+        while self.cursor.get() < self.tokens.len() {
+            body.push(self.parse_expression()?);
+        }
+
+        Ok(body)
     }
 
-    fn previous_literal(&self) -> Result<U256, ()> {
-        let literal = U256::from_dec_str(self.tokens[self.cursor.get() - 1].slice).unwrap();
+    /// Parse an `extern pair <id>` or `extern pool <id>` declaration.
+    fn parse_extern(&self) -> Result<(), Error> {
+        self.match_token(TokenType::Extern)?;
+        self.match_token(TokenType::Identifier)?;
+
+        let kind = self.previous();
+
+        if !matches!(kind.slice, "pair" | "pool") {
+            return Err(Error::new(
+                format!("expected `pair` or `pool`, found `{}`", kind.slice),
+                kind.span,
+            ));
+        }
 
-        Ok(literal)
+        self.match_token(TokenType::Literal)?;
+        self.previous_literal()?;
+
+        Ok(())
     }
 
-    /// Expand all macros.
-    fn parse_macro(&self) -> Result<Macro<'a>, ()> {
-        let mut body: Vec<Expression> = Vec::new();
+    fn parse_macro(&self) -> Result<Macro<'a>, Error> {
+        let mut body = Vec::new();
+
+        let entry = self.peek()?.ttype == TokenType::Pub;
+
+        if entry {
+            self.match_token(TokenType::Pub)?;
+        }
 
         self.match_token(TokenType::Macro)?;
         self.match_token(TokenType::Identifier)?;
-        let name: &str = self.tokens[self.cursor.get() - 1].slice;
+        let name = self.previous();
         self.match_token(TokenType::OpenBrace)?;
 
-        while self.tokens[self.cursor.get()].ttype != TokenType::CloseBrace {
+        while self.peek()?.ttype != TokenType::CloseBrace {
             body.push(self.parse_expression()?);
         }
 
         self.match_token(TokenType::CloseBrace)?;
 
-        let _macro = Macro { name, body };
+        let _macro = Macro {
+            name: name.slice,
+            span: name.span,
+            entry,
+            body,
+        };
 
         Ok(_macro)
     }
 
-    fn parse_expression(&self) -> Result<Expression<'a>, ()> {
-        let current_token = self.tokens[self.cursor.get()];
-
-        match current_token.ttype {
-            TokenType::Unknown => {
-                self.match_token(TokenType::Unknown)?;
-                Ok(Expression::Opcode(Opcode::Unknown))
-            }
-            TokenType::Allocate => Ok(Expression::Opcode(self.allocate()?)),
-            TokenType::Deallocate => Ok(Expression::Opcode(self.deallocate()?)),
-            TokenType::Claim => Ok(Expression::Opcode(self.claim()?)),
-            TokenType::Swap => Ok(Expression::Opcode(self.swap()?)),
-            TokenType::CreatePool => Ok(Expression::Opcode(self.create_pool()?)),
-            TokenType::CreatePair => Ok(Expression::Opcode(self.create_pair()?)),
-            TokenType::Jump => {
-                self.match_token(TokenType::Jump)?;
-                Ok(Expression::Opcode(Opcode::Jump))
-            }
+    fn parse_expression(&self) -> Result<Spanned<Expression<'a>>, Error> {
+        let current_token = self.peek()?;
+
+        let expression = match current_token.ttype {
+            TokenType::Unknown
+            | TokenType::Allocate
+            | TokenType::Deallocate
+            | TokenType::Claim
+            | TokenType::Swap
+            | TokenType::CreatePool
+            | TokenType::CreatePair
+            | TokenType::Jump => Expression::Opcode(self.parse_instruction()?),
             TokenType::Identifier => {
                 self.match_token(TokenType::Identifier)?;
-                Ok(Expression::Invocation(current_token.slice))
+                Expression::Invocation(current_token.slice)
             }
-            _ => panic!("something went wrong"),
-        }
-    }
-
-    fn create_pair(&self) -> Result<Opcode, ()> {
-        self.match_token(TokenType::CreatePair)?;
-        self.match_token(TokenType::Colon)?;
-
-        self.parse_parameter(TokenType::Token0, TokenType::AddressLiteral)?;
-
-        let token_0 = self.tokens[self.cursor.get() - 1]
-            .slice
-            .parse::<Address>()
-            .unwrap();
-
-        self.parse_parameter(TokenType::Token1, TokenType::AddressLiteral)?;
-
-        let token_1 = self.tokens[self.cursor.get() - 1]
-            .slice
-            .parse::<Address>()
-            .unwrap();
+            TokenType::Error => return Err(Assembler::unrecognized(&current_token)),
+            _ => {
+                return Err(Error::new(
+                    format!(
+                        "expected an instruction or macro invocation, found `{}`",
+                        current_token.slice
+                    ),
+                    current_token.span,
+                ))
+            }
+        };
 
-        Ok(Opcode::CreatePair { token_0, token_1 })
+        Ok(Spanned::new(
+            expression,
+            current_token.span.to(self.previous().span),
+        ))
     }
 
-    fn create_pool(&self) -> Result<Opcode, ()> {
-        self.match_token(TokenType::CreatePool)?;
-        self.match_token(TokenType::Colon)?;
+    /// Parse an instruction and its parameters, which may be given in any order.
+    /// Omitted parameters take their default from the [`spec`], and are otherwise an error.
+    fn parse_instruction(&self) -> Result<Opcode, Error> {
+        let keyword = self.peek()?;
+        let instruction = spec::instruction(keyword.slice).ok_or_else(|| {
+            Error::new(
+                format!("`{}` is not an instruction", keyword.slice),
+                keyword.span,
+            )
+        })?;
 
-        self.parse_parameter(TokenType::PairId, TokenType::Literal)?;
-        let pair_id = self.previous_literal()?;
+        self.match_token(keyword.ttype)?;
 
-        self.parse_parameter(TokenType::Controller, TokenType::AddressLiteral)?;
+        let mut arguments = HashMap::new();
 
-        let controller = self.tokens[self.cursor.get() - 1]
-            .slice
-            .parse::<Address>()
-            .unwrap();
+        if !instruction.parameters.is_empty() {
+            self.match_token(TokenType::Colon)?;
+        }
+
+        while let Some(key) = self
+            .tokens
+            .get(self.cursor.get())
+            .filter(|token| token.ttype.is_parameter())
+        {
+            let parameter = instruction.parameter(key.slice).ok_or_else(|| {
+                Error::new(
+                    format!(
+                        "`{}` is not a parameter of `{}`",
+                        key.slice, instruction.name
+                    ),
+                    key.span,
+                )
+            })?;
+
+            if arguments.contains_key(parameter.name) {
+                return Err(Error::new(
+                    format!("parameter `{}` is given more than once", parameter.name),
+                    key.span,
+                ));
+            }
 
-        self.parse_parameter(TokenType::PriorityFee, TokenType::Literal)?;
-        let priority_fee = self.previous_literal()?;
+            self.match_token(key.ttype)?;
+            self.match_token(TokenType::Colon)?;
 
-        self.parse_parameter(TokenType::Fee, TokenType::Literal)?;
-        let fee = self.previous_literal()?;
+            let start = self.peek()?.span;
 
-        self.parse_parameter(TokenType::Vol, TokenType::Literal)?;
-        let vol = self.previous_literal()?;
+            let argument = match parameter.kind {
+                Kind::Address => Argument::Address(self.parse_address()?),
+                _ => Argument::Integer(self.parse_integer()?),
+            };
 
-        self.parse_parameter(TokenType::Dur, TokenType::Literal)?;
-        let dur = self.previous_literal()?;
+            if !self.placeholders.contains(&start) {
+                parameter
+                    .validate(&argument)
+                    .map_err(|message| Error::new(message, start.to(self.previous().span)))?;
+            }
 
-        self.parse_parameter(TokenType::Jit, TokenType::Literal)?;
-        let jit = self.previous_literal()?;
+            arguments.insert(parameter.name, argument);
+        }
 
-        self.parse_parameter(TokenType::MaxPrice, TokenType::Literal)?;
-        let max_price = self.previous_literal()?;
+        for parameter in instruction.parameters {
+            if arguments.contains_key(parameter.name) {
+                continue;
+            }
 
-        self.parse_parameter(TokenType::Price, TokenType::Literal)?;
-        let price = self.previous_literal()?;
+            let default = self
+                .environment
+                .default(instruction.name, parameter.name)
+                .or(parameter.default)
+                .ok_or_else(|| {
+                    Error::new(
+                        format!(
+                            "missing parameter `{}` of `{}`",
+                            parameter.name, instruction.name
+                        ),
+                        keyword.span,
+                    )
+                })?;
+
+            let argument = match parameter.kind {
+                Kind::Address => self.environment.address(default).map(Argument::Address),
+                _ => U256::from_dec_str(default).ok().map(Argument::Integer),
+            }
+            .filter(|argument| parameter.validate(argument).is_ok())
+            .ok_or_else(|| {
+                Error::new(
+                    format!(
+                        "invalid default `{default}` for parameter `{}` of `{}`",
+                        parameter.name, instruction.name
+                    ),
+                    keyword.span,
+                )
+            })?;
+
+            arguments.insert(parameter.name, argument);
+        }
 
-        Ok(Opcode::CreatePool {
-            pair_id,
-            controller,
-            priority_fee,
-            fee,
-            vol,
-            dur,
-            jit,
-            max_price,
-            price,
-        })
+        let integer = |name: &str| match arguments[name] {
+            Argument::Integer(value) => value,
+            Argument::Address(_) => unreachable!("`{name}` is an integer parameter"),
+        };
+
+        let address = |name: &str| match arguments[name] {
+            Argument::Address(value) => value,
+            Argument::Integer(_) => unreachable!("`{name}` is an address parameter"),
+        };
+
+        let opcode = match keyword.ttype {
+            TokenType::Allocate => Opcode::Allocate {
+                use_max: integer("useMax"),
+                pool_id: integer("poolId"),
+                delta_liquidity: integer("deltaLiquidity"),
+            },
+            TokenType::Deallocate => Opcode::Deallocate {
+                use_max: integer("useMax"),
+                pool_id: integer("poolId"),
+                delta_liquidity: integer("deltaLiquidity"),
+            },
+            TokenType::Claim => Opcode::Claim {
+                pool_id: integer("poolId"),
+                fee_0: integer("fee0"),
+                fee_1: integer("fee1"),
+            },
+            TokenType::Swap => Opcode::Swap {
+                use_max: integer("useMax"),
+                pool_id: integer("poolId"),
+                amount_0: integer("amount0"),
+                amount_1: integer("amount1"),
+                sell_asset: integer("sellAsset"),
+            },
+            TokenType::CreatePool => Opcode::CreatePool {
+                pair_id: integer("pairId"),
+                controller: address("controller"),
+                priority_fee: integer("priorityFee"),
+                fee: integer("fee"),
+                vol: integer("vol"),
+                dur: integer("dur"),
+                jit: integer("jit"),
+                max_price: integer("maxPrice"),
+                price: integer("price"),
+            },
+            TokenType::CreatePair => Opcode::CreatePair {
+                token_0: address("token0"),
+                token_1: address("token1"),
+            },
+            TokenType::Jump => Opcode::Jump,
+            _ => Opcode::Unknown,
+        };
+
+        Ok(opcode)
     }
+}
 
-    fn swap(&self) -> Result<Opcode, ()> {
-        self.match_token(TokenType::Swap)?;
-        self.match_token(TokenType::Colon)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment() -> Environment {
+        let mut environment = Environment::new();
+        environment
+            .addresses
+            .insert("treasury".to_string(), Address::repeat_byte(1));
+        environment.decimals.insert("USDC".to_string(), 6);
+        environment.defaults.insert(
+            "swap".to_string(),
+            [("useMax".to_string(), "1".to_string())].into(),
+        );
+        environment.defaults.insert(
+            "createPool".to_string(),
+            [
+                ("controller".to_string(), "treasury".to_string()),
+                ("jit".to_string(), "4".to_string()),
+            ]
+            .into(),
+        );
+
+        environment
+    }
 
-        self.parse_parameter(TokenType::UseMax, TokenType::Literal)?;
-        let use_max = self.previous_literal()?;
+    /// The opcodes of `main` in `source`, assembled against [`environment`].
+    fn assemble(source: &str) -> Result<Vec<Opcode>, Error> {
+        let environment = environment();
+        let expressions = Assembler::new(Token::lex(source))
+            .with_environment(&environment)
+            .assemble()?;
+
+        Ok(expressions
+            .into_iter()
+            .map(|expression| match expression {
+                Expression::Opcode(opcode) => opcode,
+                Expression::Invocation(name) => panic!("unexpanded invocation of `{name}`"),
+            })
+            .collect())
+    }
 
-        self.parse_parameter(TokenType::PoolId, TokenType::Literal)?;
-        let pool_id = self.previous_literal()?;
+    /// The message of the error assembling `source`, and the source it points at.
+    fn error(source: &str) -> (String, &str) {
+        let error = assemble(source).unwrap_err();
 
-        self.parse_parameter(TokenType::Amount0, TokenType::Literal)?;
-        let amount_0 = self.previous_literal()?;
+        (error.message, &source[error.span.start..error.span.end])
+    }
 
-        self.parse_parameter(TokenType::Amount1, TokenType::Literal)?;
-        let amount_1 = self.previous_literal()?;
+    #[test]
+    fn imports() {
+        let source = "import \"a.fvm\"\nmacro main {}\nimport \"lib/b.fvm\"";
+        let imports = Assembler::new(Token::lex(source)).imports();
+
+        assert_eq!(
+            imports.iter().map(|import| import.node).collect::<Vec<_>>(),
+            ["a.fvm", "lib/b.fvm"]
+        );
+        assert_eq!(
+            &source[imports[1].span.start..imports[1].span.end],
+            "\"lib/b.fvm\""
+        );
+
+        // Imports are skipped when parsing.
+        assert_eq!(assemble(source), Ok(Vec::new()));
+    }
 
-        self.parse_parameter(TokenType::SellAsset, TokenType::Literal)?;
-        let sell_asset = self.previous_literal()?;
+    #[test]
+    fn externs() {
+        let source = "extern pair 1\nextern pool 34\nextern pool 35\nmacro main {}";
 
-        Ok(Opcode::Swap {
-            use_max,
-            pool_id,
-            amount_0,
-            amount_1,
-            sell_asset,
-        })
+        assert_eq!(
+            Assembler::new(Token::lex(source)).externs(),
+            Externs {
+                pairs: [U256::from(1)].into(),
+                pools: [U256::from(34), U256::from(35)].into(),
+            }
+        );
+        assert_eq!(assemble(source), Ok(Vec::new()));
+
+        assert_eq!(
+            error("extern token 1\nmacro main {}"),
+            (
+                "expected `pair` or `pool`, found `token`".to_string(),
+                "token"
+            )
+        );
+        assert_eq!(
+            error("extern pool 1.5\nmacro main {}"),
+            ("`1.5` is not a valid unsigned integer".to_string(), "1.5")
+        );
     }
 
-    fn claim(&self) -> Result<Opcode, ()> {
-        self.match_token(TokenType::Claim)?;
-        self.match_token(TokenType::Colon)?;
+    #[test]
+    fn defaults() {
+        // The environment's defaults replace those of the spec, and fill in required parameters.
+        let source = "macro main {
+            swap: poolId: 34 amount0: 10 amount1: 20 sellAsset: 1
+            swap: useMax: 0 poolId: 34 amount0: 10 amount1: 20 sellAsset: 1
+            createPool: pairId: 1 fee: 30 vol: 100 dur: 365 maxPrice: 2000 price: 1000
+        }";
+
+        let opcodes = assemble(source).unwrap();
+
+        assert!(matches!(opcodes[0], Opcode::Swap { use_max, .. } if use_max == U256::one()));
+        assert!(matches!(opcodes[1], Opcode::Swap { use_max, .. } if use_max.is_zero()));
+        assert!(matches!(
+            opcodes[2],
+            Opcode::CreatePool { controller, jit, priority_fee, .. }
+                if controller == Address::repeat_byte(1) && jit == U256::from(4) && priority_fee.is_zero()
+        ));
+
+        assert_eq!(
+            error("macro main { allocate: poolId: 34 }"),
+            (
+                "missing parameter `deltaLiquidity` of `allocate`".to_string(),
+                "allocate"
+            )
+        );
+
+        let mut environment = environment();
+        environment.defaults.insert(
+            "claim".to_string(),
+            [("fee0".to_string(), "vault".to_string())].into(),
+        );
+        let error = Assembler::new(Token::lex("macro main { claim: poolId: 34 fee1: 1 }"))
+            .with_environment(&environment)
+            .assemble()
+            .unwrap_err();
+
+        assert_eq!(
+            error.message,
+            "invalid default `vault` for parameter `fee0` of `claim`"
+        );
+    }
 
-        self.parse_parameter(TokenType::PoolId, TokenType::Literal)?;
-        let pool_id = self.previous_literal()?;
+    #[test]
+    fn named_addresses() {
+        assert_eq!(
+            assemble("macro main { createPair: token0: treasury token1: 0x0202020202020202020202020202020202020202 }"),
+            Ok(vec![Opcode::CreatePair {
+                token_0: Address::repeat_byte(1),
+                token_1: Address::repeat_byte(2),
+            }])
+        );
+        assert_eq!(
+            error("macro main { createPair: token0: vault token1: treasury }"),
+            ("unknown address `vault`".to_string(), "vault")
+        );
+    }
 
-        self.parse_parameter(TokenType::Fee0, TokenType::Literal)?;
-        let fee_0 = self.previous_literal()?;
+    #[test]
+    fn units() {
+        assert_eq!(
+            assemble("macro main { allocate: poolId: 34 deltaLiquidity: 1.5 USDC }"),
+            Ok(vec![Opcode::Allocate {
+                use_max: U256::zero(),
+                pool_id: U256::from(34),
+                delta_liquidity: U256::from(1_500_000),
+            }])
+        );
+        assert_eq!(
+            error("macro main { allocate: poolId: 34 deltaLiquidity: 1.0000001 USDC }"),
+            (
+                "`1.0000001` has more decimal places than `USDC`".to_string(),
+                "1.0000001 USDC"
+            )
+        );
+
+        // A literal with a fraction and no unit is not an integer.
+        assert_eq!(
+            error("macro main { allocate: poolId: 34 deltaLiquidity: 1.5 }"),
+            ("`1.5` is not a valid unsigned integer".to_string(), "1.5")
+        );
+    }
 
-        self.parse_parameter(TokenType::Fee1, TokenType::Literal)?;
-        let fee_1 = self.previous_literal()?;
+    /// The name and opcodes of each entry point, as built by `folio build` without `--entry`.
+    fn entries(source: &str) -> Vec<(&str, Vec<Expression<'_>>)> {
+        Assembler::new(Token::lex(source))
+            .assemble_entries()
+            .unwrap()
+    }
 
-        Ok(Opcode::Claim {
-            pool_id,
-            fee_0,
-            fee_1,
+    fn allocate(delta_liquidity: u64) -> Expression<'static> {
+        Expression::Opcode(Opcode::Allocate {
+            use_max: U256::zero(),
+            pool_id: U256::from(34),
+            delta_liquidity: U256::from(delta_liquidity),
         })
     }
 
-    fn deallocate(&self) -> Result<Opcode, ()> {
-        self.match_token(TokenType::Deallocate)?;
-        self.match_token(TokenType::Colon)?;
+    #[test]
+    fn entry_tokens() {
+        let tokens = Token::lex("pub entry macro");
 
-        self.parse_parameter(TokenType::UseMax, TokenType::Literal)?;
-        let use_max = self.previous_literal()?;
-
-        self.parse_parameter(TokenType::PoolId, TokenType::Literal)?;
-        let pool_id = self.previous_literal()?;
+        assert_eq!(
+            tokens.iter().map(|token| token.ttype).collect::<Vec<_>>(),
+            [TokenType::Pub, TokenType::Pub, TokenType::Macro]
+        );
+    }
 
-        self.parse_parameter(TokenType::DeltaLiquidity, TokenType::Literal)?;
-        let delta_liquidity = self.previous_literal()?;
+    #[test]
+    fn main_by_default() {
+        let source = "macro main { helper }
+            macro helper { allocate: poolId: 34 deltaLiquidity: 1 }";
 
-        Ok(Opcode::Deallocate {
-            use_max,
-            pool_id,
-            delta_liquidity,
-        })
+        assert_eq!(entries(source), [("main", vec![allocate(1)])]);
     }
 
-    fn allocate(&self) -> Result<Opcode, ()> {
-        self.match_token(TokenType::Allocate)?;
-        self.match_token(TokenType::Colon)?;
-
-        self.parse_parameter(TokenType::UseMax, TokenType::Literal)?;
-        let use_max = self.previous_literal()?;
+    #[test]
+    fn public_entries() {
+        // Every `pub` or `entry` macro is built, in order of definition, and `main` only if it is marked as well.
+        let source = "macro main { provide }
+            pub macro withdraw { allocate: poolId: 34 deltaLiquidity: 2 }
+            macro helper { allocate: poolId: 34 deltaLiquidity: 1 }
+            entry macro provide { helper }";
+
+        assert_eq!(
+            entries(source),
+            [
+                ("withdraw", vec![allocate(2)]),
+                ("provide", vec![allocate(1)])
+            ]
+        );
+
+        let macros = Assembler::new(Token::lex(source)).parse_macros().unwrap();
+        assert!(macros["withdraw"].entry && macros["provide"].entry);
+        assert!(!macros["main"].entry && !macros["helper"].entry);
+    }
 
-        self.parse_parameter(TokenType::PoolId, TokenType::Literal)?;
-        let pool_id = self.previous_literal()?;
+    #[test]
+    fn entry_by_name() {
+        // `--entry` builds any macro, whether it is marked as an entry point or not.
+        let source = "pub macro provide { helper }
+            macro helper { allocate: poolId: 34 deltaLiquidity: 1 }";
+        let assembler = Assembler::new(Token::lex(source));
 
-        self.parse_parameter(TokenType::DeltaLiquidity, TokenType::Literal)?;
-        let delta_liquidity = self.previous_literal()?;
+        assert_eq!(assembler.assemble_entry("provide"), Ok(vec![allocate(1)]));
+        assert_eq!(assembler.assemble_entry("helper"), Ok(vec![allocate(1)]));
+    }
 
-        Ok(Opcode::Allocate {
-            use_max,
-            pool_id,
-            delta_liquidity,
-        })
+    #[test]
+    fn missing_entry() {
+        let source = "pub macro provide {}";
+        let assembler = Assembler::new(Token::lex(source));
+
+        assert_eq!(
+            assembler.assemble_entry("rebalance").unwrap_err().message,
+            "no `rebalance` macro found"
+        );
+        assert_eq!(
+            assembler.assemble().unwrap_err().message,
+            "no `main` macro found"
+        );
+        assert_eq!(
+            Assembler::new(Token::lex("macro helper {}"))
+                .assemble_entries()
+                .unwrap_err()
+                .message,
+            "no `main` macro found"
+        );
+        assert_eq!(
+            Assembler::new(Token::lex("pub helper {}"))
+                .assemble_entries()
+                .unwrap_err()
+                .message,
+            "expected `macro`, found `helper`"
+        );
     }
 }
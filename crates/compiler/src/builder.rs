@@ -0,0 +1,274 @@
+//! # Builder
+//!
+//! A typed API for assembling FVM programs directly from Rust.
+//! [`Program`] yields the same [`Vec<Opcode>`] as parsing a source file, without formatting and re-lexing `.fvm` text.
+
+use crate::{
+    codegen::Codegen,
+    opcode::{Address, Opcode, U256},
+};
+
+/// Parameters of a `createPool` instruction.
+/// Every field is required, so a pool cannot be built with missing parameters.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    pub pair_id: U256,
+    pub controller: Address,
+    pub priority_fee: U256,
+    pub fee: U256,
+    pub vol: U256,
+    pub dur: U256,
+    pub jit: U256,
+    pub max_price: U256,
+    pub price: U256,
+}
+
+/// An FVM program, built one instruction at a time.
+///
+/// ```
+/// use compiler::{builder::Program, opcode::Address};
+///
+/// let opcodes = Program::new()
+///     .create_pair(Address::zero(), Address::repeat_byte(1))
+///     .allocate(false, 34, 675)
+///     .swap(false, 34, 10, 20, true)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(opcodes.len(), 3);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    /// Instructions, in execution order.
+    opcodes: Vec<Opcode>,
+}
+
+impl Program {
+    /// Public constructor function to instantiate an empty `Program`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an arbitrary opcode.
+    pub fn push(mut self, opcode: Opcode) -> Self {
+        self.opcodes.push(opcode);
+        self
+    }
+
+    /// Append a `createPair` instruction.
+    pub fn create_pair(self, token_0: Address, token_1: Address) -> Self {
+        self.push(Opcode::CreatePair { token_0, token_1 })
+    }
+
+    /// Append a `createPool` instruction.
+    pub fn create_pool(self, pool: Pool) -> Self {
+        self.push(Opcode::CreatePool {
+            pair_id: pool.pair_id,
+            controller: pool.controller,
+            priority_fee: pool.priority_fee,
+            fee: pool.fee,
+            vol: pool.vol,
+            dur: pool.dur,
+            jit: pool.jit,
+            max_price: pool.max_price,
+            price: pool.price,
+        })
+    }
+
+    /// Append an `allocate` instruction.
+    pub fn allocate(
+        self,
+        use_max: bool,
+        pool_id: impl Into<U256>,
+        delta_liquidity: impl Into<U256>,
+    ) -> Self {
+        self.push(Opcode::Allocate {
+            use_max: U256::from(use_max as u8),
+            pool_id: pool_id.into(),
+            delta_liquidity: delta_liquidity.into(),
+        })
+    }
+
+    /// Append a `deallocate` instruction.
+    pub fn deallocate(
+        self,
+        use_max: bool,
+        pool_id: impl Into<U256>,
+        delta_liquidity: impl Into<U256>,
+    ) -> Self {
+        self.push(Opcode::Deallocate {
+            use_max: U256::from(use_max as u8),
+            pool_id: pool_id.into(),
+            delta_liquidity: delta_liquidity.into(),
+        })
+    }
+
+    /// Append a `swap` instruction.
+    pub fn swap(
+        self,
+        use_max: bool,
+        pool_id: impl Into<U256>,
+        amount_0: impl Into<U256>,
+        amount_1: impl Into<U256>,
+        sell_asset: bool,
+    ) -> Self {
+        self.push(Opcode::Swap {
+            use_max: U256::from(use_max as u8),
+            pool_id: pool_id.into(),
+            amount_0: amount_0.into(),
+            amount_1: amount_1.into(),
+            sell_asset: U256::from(sell_asset as u8),
+        })
+    }
+
+    /// Append a `claim` instruction.
+    pub fn claim(
+        self,
+        pool_id: impl Into<U256>,
+        fee_0: impl Into<U256>,
+        fee_1: impl Into<U256>,
+    ) -> Self {
+        self.push(Opcode::Claim {
+            pool_id: pool_id.into(),
+            fee_0: fee_0.into(),
+            fee_1: fee_1.into(),
+        })
+    }
+
+    /// Consume the program, returning its opcodes.
+    /// Fails if an argument does not fit in its encoded field, which encoding would otherwise truncate.
+    pub fn build(self) -> Result<Vec<Opcode>, String> {
+        for (index, opcode) in self.opcodes.iter().enumerate() {
+            for (parameter, argument) in opcode.arguments() {
+                parameter.validate(&argument).map_err(|message| {
+                    format!(
+                        "instruction {index} (`{}`): {message}",
+                        opcode.instruction().name
+                    )
+                })?;
+            }
+        }
+
+        Ok(self.opcodes)
+    }
+}
+
+impl TryFrom<Program> for Codegen {
+    type Error = String;
+
+    fn try_from(program: Program) -> Result<Self, Self::Error> {
+        program.build().map(Codegen::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{Assembler, Expression},
+        token::Token,
+    };
+
+    #[test]
+    fn same_as_source() {
+        let source = "
+            macro main {
+                createPair:
+                    token0: 0x0000000000000000000000000000000000000001
+                    token1: 0x0000000000000000000000000000000000000002
+                createPool:
+                    pairId     : 1
+                    controller : 0x0000000000000000000000000000000000000003
+                    priorityFee: 10
+                    fee        : 30
+                    vol        : 10000
+                    dur        : 365
+                    jit        : 4
+                    maxPrice   : 2000
+                    price      : 1000
+                allocate:
+                    useMax        : 1
+                    poolId        : 34
+                    deltaLiquidity: 675
+                deallocate:
+                    poolId        : 34
+                    deltaLiquidity: 100
+                swap:
+                    poolId   : 34
+                    amount0  : 10
+                    amount1  : 20
+                    sellAsset: 1
+                claim:
+                    poolId: 34
+                    fee0  : 5
+                    fee1  : 6
+            }
+        ";
+
+        let parsed = Assembler::parse(Token::lex(source))
+            .unwrap()
+            .into_iter()
+            .map(|expression| match expression {
+                Expression::Opcode(opcode) => opcode,
+                Expression::Invocation(name) => panic!("unexpanded invocation of `{name}`"),
+            })
+            .collect::<Vec<_>>();
+
+        let built = Program::new()
+            .create_pair(Address::from_low_u64_be(1), Address::from_low_u64_be(2))
+            .create_pool(Pool {
+                pair_id: U256::from(1),
+                controller: Address::from_low_u64_be(3),
+                priority_fee: U256::from(10),
+                fee: U256::from(30),
+                vol: U256::from(10_000),
+                dur: U256::from(365),
+                jit: U256::from(4),
+                max_price: U256::from(2000),
+                price: U256::from(1000),
+            })
+            .allocate(true, 34, 675)
+            .deallocate(false, 34, 100)
+            .swap(false, 34, 10, 20, true)
+            .claim(34, 5, 6)
+            .build()
+            .unwrap();
+
+        assert_eq!(built, parsed);
+        assert_eq!(
+            Codegen::try_from(Program::new().allocate(true, 34, 675))
+                .unwrap()
+                .encode(),
+            Codegen::from(parsed[2..3].to_vec()).encode()
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(
+            Program::new()
+                .claim(34, 5, 6)
+                .allocate(false, 1u128 << 64, 675)
+                .build(),
+            Err(format!(
+                "instruction 1 (`allocate`): `poolId` must fit in 64 bits, but {} does not",
+                1u128 << 64
+            ))
+        );
+
+        let pool = Pool {
+            pair_id: U256::one() << 24,
+            controller: Address::zero(),
+            priority_fee: U256::zero(),
+            fee: U256::zero(),
+            vol: U256::zero(),
+            dur: U256::zero(),
+            jit: U256::zero(),
+            max_price: U256::zero(),
+            price: U256::zero(),
+        };
+
+        assert!(Program::new().create_pool(pool).build().is_err());
+        assert!(Codegen::try_from(Program::new().claim(1u128 << 64, 0, 0)).is_err());
+    }
+}
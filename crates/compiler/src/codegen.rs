@@ -1,11 +1,12 @@
-use eth_encode_packed::{abi, ethabi::ethereum_types::U256, SolidityDataType, TakeLastXBytes};
+//! # Codegen
+//!
+//! The bytecode generation module of folio.
+//! [`Codegen`] takes in a vector of Expressions and returns a hex string, which can be executed on the FVM.
 
-use crate::{assembler::Expression, opcode::Opcode};
+use eth_encode_packed::{abi, ethabi::ethereum_types::U256, SolidityDataType, TakeLastXBytes};
+use ethers::utils::hex;
 
-/// # Codegen
-///
-/// The bytecode generation module of folio.
-/// [`Codegen`] takes in a vector of Expressions and returns a hex string, which can be executed on the FVM.
+use crate::{assembler::Expression, opcode::Opcode, spec};
 
 /// Code generation struct.
 pub struct Codegen {
@@ -28,7 +29,13 @@ impl<'a> Codegen {
         Self { opcodes }
     }
 
-    fn from_amount(amount: U256) -> (U256, U256) {
+    /// The opcodes to generate bytecode from.
+    pub fn opcodes(&self) -> &[Opcode] {
+        &self.opcodes
+    }
+
+    /// Split an amount into a power of ten and a base.
+    pub(crate) fn from_amount(amount: U256) -> (U256, U256) {
         if amount == U256::from(0) {
             return (U256::from(0), U256::from(0));
         };
@@ -44,22 +51,88 @@ impl<'a> Codegen {
         (U256::from(power), base)
     }
 
-    pub fn generate(instructions: Vec<String>) -> String {
-        let mut payload = "AA".to_string() + &instructions.len().to_string();
+    /// Wrap encoded instructions in a jump instruction, which processes each of them in turn.
+    /// The instruction count and the byte length of each instruction are encoded as single bytes,
+    /// so a payload holds at most 255 instructions of at most 255 bytes each.
+    pub fn generate(instructions: Vec<String>) -> Result<String, String> {
+        let count = u8::try_from(instructions.len()).map_err(|_| {
+            format!(
+                "a payload holds at most {} instructions, but the program has {}",
+                u8::MAX,
+                instructions.len()
+            )
+        })?;
+
+        let mut payload = format!("{:02x}{count:02x}", spec::JUMP.opcode);
 
-        for i in instructions {
-            let edited = i.len().to_string() + &i;
+        for (index, i) in instructions.iter().enumerate() {
+            let length = u8::try_from(i.len() / 2).map_err(|_| {
+                format!(
+                    "instructions are at most {} bytes long, but instruction {index} is {}",
+                    u8::MAX,
+                    i.len() / 2
+                )
+            })?;
+
+            let edited = format!("{length:02x}") + i;
 
             payload += &edited;
         }
 
-        payload
+        Ok(payload)
+    }
+
+    /// Encode the opcodes and wrap them into a payload, as raw bytes.
+    pub fn payload(&self) -> Result<Vec<u8>, String> {
+        let payload = Codegen::generate(self.encode())?;
+
+        hex::decode(payload).map_err(|error| error.to_string())
+    }
+
+    /// Byte length of the payload `generate` wraps the encoded instructions in.
+    pub fn size(&self) -> usize {
+        2 + self
+            .encode()
+            .iter()
+            .map(|instruction| 1 + instruction.len() / 2)
+            .sum::<usize>()
     }
 
     fn pack(upper: U256, lower: U256) -> U256 {
         (upper << 4) | lower
     }
 
+    /// Encode an amount as its power of ten followed by its base.
+    fn encode_amount(amount: U256) -> Vec<u8> {
+        let (power, base) = Codegen::from_amount(amount);
+
+        let (encoded, _hash) = abi::encode_packed(&[
+            SolidityDataType::NumberWithShift(power, TakeLastXBytes(8)),
+            SolidityDataType::NumberWithShift(base, TakeLastXBytes(128)),
+        ]);
+
+        encoded
+    }
+
+    /// Encode `head`, a pointer, and two amounts.
+    /// The pointer is the byte offset of the second amount, derived from the encoded lengths of the fields before it.
+    fn encode_with_pointer(head: &[SolidityDataType], first: U256, second: U256) -> String {
+        let (head, _hash) = abi::encode_packed(head);
+        let first = Codegen::encode_amount(first);
+        let second = Codegen::encode_amount(second);
+
+        let pointer = head.len() + 1 + first.len();
+
+        let (_encoded, hash) = abi::encode_packed(&[
+            SolidityDataType::Bytes(&head),
+            SolidityDataType::NumberWithShift(U256::from(pointer), TakeLastXBytes(8)),
+            SolidityDataType::Bytes(&first),
+            SolidityDataType::Bytes(&second),
+        ]);
+
+        hash
+    }
+
     /// Expand all macros and encode into hex, ready to be executed on the FVM.
     pub fn encode(&self) -> Vec<String> {
         let mut bytes = Vec::new();
@@ -72,7 +145,7 @@ impl<'a> Codegen {
                     delta_liquidity,
                 } => {
                     let (power, base) = Codegen::from_amount(*delta_liquidity);
-                    let packed = Codegen::pack(*use_max, U256::from(1));
+                    let packed = Codegen::pack(*use_max, U256::from(spec::ALLOCATE.opcode));
 
                     let (_encoded, hash) = abi::encode_packed(&[
                         SolidityDataType::NumberWithShift(packed, TakeLastXBytes(8)),
@@ -89,7 +162,7 @@ impl<'a> Codegen {
                     delta_liquidity,
                 } => {
                     let (power, base) = Codegen::from_amount(*delta_liquidity);
-                    let deallocate = 3;
+                    let deallocate = spec::DEALLOCATE.opcode;
                     let packed = Codegen::pack(*use_max, U256::from(deallocate));
 
                     let (_encoded, hash) = abi::encode_packed(&[
@@ -102,7 +175,7 @@ impl<'a> Codegen {
                     bytes.push(hash)
                 }
                 Opcode::CreatePair { token_0, token_1 } => {
-                    let create_pair = 12;
+                    let create_pair = spec::CREATE_PAIR.opcode;
 
                     let (_encoded, hash) = abi::encode_packed(&[
                         SolidityDataType::NumberWithShift(
@@ -126,30 +199,25 @@ impl<'a> Codegen {
                     max_price,
                     price,
                 } => {
-                    let create_pool = 11;
-                    let pointer = 36 + 16;
+                    let create_pool = spec::CREATE_POOL.opcode;
 
-                    let (power0, base0) = Codegen::from_amount(*max_price);
-                    let (power1, base1) = Codegen::from_amount(*price);
-
-                    let (_encoded, hash) = abi::encode_packed(&[
-                        SolidityDataType::NumberWithShift(
-                            U256::from(create_pool),
-                            TakeLastXBytes(8),
-                        ),
-                        SolidityDataType::NumberWithShift(*pair_id, TakeLastXBytes(24)),
-                        SolidityDataType::Address(*controller),
-                        SolidityDataType::NumberWithShift(*priority_fee, TakeLastXBytes(16)),
-                        SolidityDataType::NumberWithShift(*fee, TakeLastXBytes(16)),
-                        SolidityDataType::NumberWithShift(*vol, TakeLastXBytes(16)),
-                        SolidityDataType::NumberWithShift(*dur, TakeLastXBytes(16)),
-                        SolidityDataType::NumberWithShift(*jit, TakeLastXBytes(16)),
-                        SolidityDataType::NumberWithShift(U256::from(pointer), TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(power0, TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(base0, TakeLastXBytes(128)),
-                        SolidityDataType::NumberWithShift(power1, TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(base1, TakeLastXBytes(128)),
-                    ]);
+                    let hash = Codegen::encode_with_pointer(
+                        &[
+                            SolidityDataType::NumberWithShift(
+                                U256::from(create_pool),
+                                TakeLastXBytes(8),
+                            ),
+                            SolidityDataType::NumberWithShift(*pair_id, TakeLastXBytes(24)),
+                            SolidityDataType::Address(*controller),
+                            SolidityDataType::NumberWithShift(*priority_fee, TakeLastXBytes(16)),
+                            SolidityDataType::NumberWithShift(*fee, TakeLastXBytes(16)),
+                            SolidityDataType::NumberWithShift(*vol, TakeLastXBytes(16)),
+                            SolidityDataType::NumberWithShift(*dur, TakeLastXBytes(16)),
+                            SolidityDataType::NumberWithShift(*jit, TakeLastXBytes(16)),
+                        ],
+                        *max_price,
+                        *price,
+                    );
 
                     bytes.push(hash)
                 }
@@ -161,25 +229,21 @@ impl<'a> Codegen {
                     sell_asset,
                 } => {
                     let swap = if *sell_asset == U256::from(1) {
-                        U256::from(6)
+                        U256::from(spec::SWAP.opcode + 1)
                     } else {
-                        U256::from(5)
+                        U256::from(spec::SWAP.opcode)
                     };
 
-                    let (power0, base0) = Codegen::from_amount(*amount_0);
-                    let (power1, base1) = Codegen::from_amount(*amount_1);
-
                     let packed = Codegen::pack(*use_max, swap);
 
-                    let (_encoded, hash) = abi::encode_packed(&[
-                        SolidityDataType::NumberWithShift(packed, TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(*pool_id, TakeLastXBytes(64)),
-                        SolidityDataType::NumberWithShift(U256::from(27), TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(power0, TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(base0, TakeLastXBytes(128)),
-                        SolidityDataType::NumberWithShift(power1, TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(base1, TakeLastXBytes(128)),
-                    ]);
+                    let hash = Codegen::encode_with_pointer(
+                        &[
+                            SolidityDataType::NumberWithShift(packed, TakeLastXBytes(8)),
+                            SolidityDataType::NumberWithShift(*pool_id, TakeLastXBytes(64)),
+                        ],
+                        *amount_0,
+                        *amount_1,
+                    );
 
                     bytes.push(hash)
                 }
@@ -188,19 +252,17 @@ impl<'a> Codegen {
                     fee_0,
                     fee_1,
                 } => {
-                    let claim = 4;
-                    let (power_fee0, base_fee0) = Codegen::from_amount(*fee_0);
-                    let (power_fee1, base_fee1) = Codegen::from_amount(*fee_1);
+                    let claim = spec::CLAIM.opcode;
+
+                    let hash = Codegen::encode_with_pointer(
+                        &[
+                            SolidityDataType::NumberWithShift(U256::from(claim), TakeLastXBytes(8)),
+                            SolidityDataType::NumberWithShift(*pool_id, TakeLastXBytes(64)),
+                        ],
+                        *fee_0,
+                        *fee_1,
+                    );
 
-                    let (_encoded, hash) = abi::encode_packed(&[
-                        SolidityDataType::NumberWithShift(U256::from(claim), TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(*pool_id, TakeLastXBytes(64)),
-                        SolidityDataType::NumberWithShift(U256::from(27), TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(power_fee0, TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(base_fee0, TakeLastXBytes(128)),
-                        SolidityDataType::NumberWithShift(power_fee1, TakeLastXBytes(8)),
-                        SolidityDataType::NumberWithShift(base_fee1, TakeLastXBytes(128)),
-                    ]);
                     bytes.push(hash);
                 }
                 Opcode::Jump => continue,
@@ -210,3 +272,129 @@ impl<'a> Codegen {
         bytes
     }
 }
+
+impl From<Vec<Opcode>> for Codegen {
+    fn from(opcodes: Vec<Opcode>) -> Self {
+        Self { opcodes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::Address;
+
+    /// Decode the hex encoding of a single opcode.
+    fn encode(opcode: Opcode) -> Vec<u8> {
+        hex::decode(Codegen::from(vec![opcode]).encode().pop().unwrap()).unwrap()
+    }
+
+    /// Check that the pointer at byte `at` is `expected`, and points at the encoding of `second`.
+    fn assert_pointer(encoded: &[u8], at: usize, expected: u8, second: U256) {
+        assert_eq!(encoded[at], expected);
+        assert_eq!(
+            encoded[usize::from(expected)..],
+            Codegen::encode_amount(second)
+        );
+    }
+
+    #[test]
+    fn create_pool_pointer() {
+        let encoded = encode(Opcode::CreatePool {
+            pair_id: U256::from(1),
+            controller: Address::repeat_byte(1),
+            priority_fee: U256::from(0),
+            fee: U256::from(100),
+            vol: U256::from(1000),
+            dur: U256::from(365),
+            jit: U256::from(4),
+            max_price: U256::from(1000),
+            price: U256::from(10),
+        });
+
+        assert_pointer(&encoded, 34, 52, U256::from(10));
+    }
+
+    #[test]
+    fn swap_pointer() {
+        for sell_asset in 0..2 {
+            let encoded = encode(Opcode::Swap {
+                use_max: U256::from(0),
+                pool_id: U256::from(34),
+                amount_0: U256::from(1500),
+                amount_1: U256::from(7),
+                sell_asset: U256::from(sell_asset),
+            });
+
+            assert_pointer(&encoded, 9, 27, U256::from(7));
+        }
+    }
+
+    #[test]
+    fn claim_pointer() {
+        let encoded = encode(Opcode::Claim {
+            pool_id: U256::from(34),
+            fee_0: U256::from(0),
+            fee_1: U256::from(25_000),
+        });
+
+        assert_pointer(&encoded, 9, 27, U256::from(25_000));
+    }
+
+    #[test]
+    fn payload_layout() {
+        // The jump opcode and the instruction count, then each instruction prefixed with its length in bytes.
+        assert_eq!(
+            Codegen::generate(vec!["0102".to_string(), "0a0b0c".to_string()]),
+            Ok("aa02020102030a0b0c".to_string())
+        );
+    }
+
+    #[test]
+    fn instruction_count_limit() {
+        let allocate = Opcode::Allocate {
+            use_max: U256::from(0),
+            pool_id: U256::from(34),
+            delta_liquidity: U256::from(675),
+        };
+
+        let payload = Codegen::from(vec![allocate.clone(); 255])
+            .payload()
+            .unwrap();
+        assert_eq!(payload[..2], [spec::JUMP.opcode, 255]);
+
+        assert_eq!(
+            Codegen::from(vec![allocate; 256]).payload(),
+            Err("a payload holds at most 255 instructions, but the program has 256".to_string())
+        );
+    }
+
+    #[test]
+    fn instruction_length_limit() {
+        assert_eq!(
+            Codegen::generate(vec!["00".repeat(255)]).map(|payload| payload.len()),
+            Ok(2 * 258)
+        );
+
+        assert_eq!(
+            Codegen::generate(vec!["00".to_string(), "00".repeat(256)]),
+            Err("instructions are at most 255 bytes long, but instruction 1 is 256".to_string())
+        );
+    }
+
+    #[test]
+    fn pointer_follows_head() {
+        let head = [SolidityDataType::NumberWithShift(
+            U256::from(1),
+            TakeLastXBytes(8),
+        )];
+        let encoded = hex::decode(Codegen::encode_with_pointer(
+            &head,
+            U256::from(1),
+            U256::from(2),
+        ))
+        .unwrap();
+
+        assert_pointer(&encoded, 1, 19, U256::from(2));
+    }
+}
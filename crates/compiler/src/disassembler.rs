@@ -0,0 +1,470 @@
+//! # Disassembler
+//!
+//! Decodes FVM bytecode back into opcodes.
+//! This is the inverse of [`Codegen`](crate::codegen::Codegen): each instruction is split into its fields,
+//! so that encoded bytes can be inspected and checked against the [`spec`].
+
+use std::ops::Range;
+
+use crate::{
+    opcode::{Address, Opcode, U256},
+    sourcemap::{Mapping, SourceMap},
+    spec,
+};
+
+/// A field of an encoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// Name of the parameter the field encodes, or `opcode` and `pointer` for the fields that are not parameters.
+    pub name: &'static str,
+    /// Byte range of the field within the instruction.
+    pub range: Range<usize>,
+    /// Decoded value.
+    pub value: String,
+}
+
+/// An instruction decoded from bytecode.
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    pub opcode: Opcode,
+    /// Every field of the instruction, in encoded order.
+    pub fields: Vec<Field>,
+    /// Offset of the instruction within the payload it was decoded from, or `0` if it was decoded on its own.
+    pub offset: usize,
+}
+
+/// Reads fields from an encoded instruction in order.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+    fields: Vec<Field>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            cursor: 0,
+            fields: Vec::new(),
+        }
+    }
+
+    fn take(&mut self, name: &'static str, length: usize) -> Result<&'a [u8], String> {
+        let range = self.cursor..self.cursor + length;
+        let bytes = self.bytes.get(range.clone()).ok_or_else(|| {
+            format!(
+                "expected `{name}` at byte {}, but the instruction is {} bytes long",
+                self.cursor,
+                self.bytes.len()
+            )
+        })?;
+
+        self.cursor = range.end;
+        Ok(bytes)
+    }
+
+    fn push(&mut self, name: &'static str, start: usize, value: String) {
+        self.fields.push(Field {
+            name,
+            range: start..self.cursor,
+            value,
+        });
+    }
+
+    fn integer(&mut self, name: &'static str, length: usize) -> Result<U256, String> {
+        let start = self.cursor;
+        let value = U256::from_big_endian(self.take(name, length)?);
+
+        self.push(name, start, value.to_string());
+        Ok(value)
+    }
+
+    fn address(&mut self, name: &'static str) -> Result<Address, String> {
+        let start = self.cursor;
+        let value = Address::from_slice(self.take(name, 20)?);
+
+        self.push(name, start, format!("{value:?}"));
+        Ok(value)
+    }
+
+    /// An amount, encoded as a one byte power of ten followed by a 16 byte base.
+    fn amount(&mut self, name: &'static str) -> Result<U256, String> {
+        let start = self.cursor;
+        let power = self.take(name, 1)?[0];
+        let base = U256::from_big_endian(self.take(name, 16)?);
+
+        let value = U256::from(10)
+            .checked_pow(U256::from(power))
+            .and_then(|scale| base.checked_mul(scale))
+            .ok_or_else(|| format!("`{name}` of {base}e{power} does not fit in 256 bits"))?;
+
+        self.push(name, start, format!("{value} ({base}e{power})"));
+        Ok(value)
+    }
+
+    /// The pointer to the second amount, which must follow the first.
+    fn pointer(&mut self) -> Result<(), String> {
+        let start = self.cursor;
+        let pointer = usize::from(self.take("pointer", 1)?[0]);
+        let expected = self.cursor + 17;
+
+        if pointer != expected {
+            return Err(format!(
+                "expected a pointer of {expected} at byte {start}, found {pointer}"
+            ));
+        }
+
+        self.push("pointer", start, pointer.to_string());
+        Ok(())
+    }
+
+    fn finish(self, opcode: Opcode) -> Result<Decoded, String> {
+        if self.cursor != self.bytes.len() {
+            return Err(format!(
+                "`{}` is {} bytes long, but {} bytes were given",
+                opcode.instruction().name,
+                self.cursor,
+                self.bytes.len()
+            ));
+        }
+
+        Ok(Decoded {
+            opcode,
+            fields: self.fields,
+            offset: 0,
+        })
+    }
+}
+
+/// Decode a single encoded instruction.
+pub fn decode(bytes: &[u8]) -> Result<Decoded, String> {
+    let mut reader = Reader::new(bytes);
+
+    let first = reader.take("opcode", 1)?[0];
+    let (use_max, code) = (U256::from(first >> 4), first & 0x0f);
+
+    let instruction = spec::INSTRUCTIONS
+        .iter()
+        .find(|instruction| instruction.opcode == code)
+        .or((code == spec::SWAP.opcode + 1).then_some(&spec::SWAP))
+        .ok_or_else(|| format!("unknown opcode {first:#04x}"))?;
+
+    reader.push("opcode", 0, instruction.name.to_string());
+
+    let opcode = match instruction.name {
+        "allocate" | "deallocate" => {
+            let pool_id = reader.integer("poolId", 8)?;
+            let delta_liquidity = reader.amount("deltaLiquidity")?;
+
+            if instruction.opcode == spec::ALLOCATE.opcode {
+                Opcode::Allocate {
+                    use_max,
+                    pool_id,
+                    delta_liquidity,
+                }
+            } else {
+                Opcode::Deallocate {
+                    use_max,
+                    pool_id,
+                    delta_liquidity,
+                }
+            }
+        }
+        "claim" => {
+            let pool_id = reader.integer("poolId", 8)?;
+            reader.pointer()?;
+
+            Opcode::Claim {
+                pool_id,
+                fee_0: reader.amount("fee0")?,
+                fee_1: reader.amount("fee1")?,
+            }
+        }
+        "swap" => {
+            let pool_id = reader.integer("poolId", 8)?;
+            reader.pointer()?;
+
+            Opcode::Swap {
+                use_max,
+                pool_id,
+                amount_0: reader.amount("amount0")?,
+                amount_1: reader.amount("amount1")?,
+                sell_asset: U256::from(u8::from(code == spec::SWAP.opcode + 1)),
+            }
+        }
+        "createPool" => {
+            let pair_id = reader.integer("pairId", 3)?;
+            let controller = reader.address("controller")?;
+            let priority_fee = reader.integer("priorityFee", 2)?;
+            let fee = reader.integer("fee", 2)?;
+            let vol = reader.integer("vol", 2)?;
+            let dur = reader.integer("dur", 2)?;
+            let jit = reader.integer("jit", 2)?;
+            reader.pointer()?;
+
+            Opcode::CreatePool {
+                pair_id,
+                controller,
+                priority_fee,
+                fee,
+                vol,
+                dur,
+                jit,
+                max_price: reader.amount("maxPrice")?,
+                price: reader.amount("price")?,
+            }
+        }
+        "createPair" => Opcode::CreatePair {
+            token_0: reader.address("token0")?,
+            token_1: reader.address("token1")?,
+        },
+        _ => Opcode::Unknown,
+    };
+
+    reader.finish(opcode)
+}
+
+/// Decode a payload produced by [`Codegen::generate`](crate::codegen::Codegen::generate):
+/// a jump instruction wrapping a list of length-prefixed instructions.
+pub fn disassemble(payload: &[u8]) -> Result<Vec<Decoded>, String> {
+    let (&jump, rest) = payload.split_first().ok_or("the payload is empty")?;
+
+    if jump != spec::JUMP.opcode {
+        return Err(format!(
+            "expected the payload to start with `jump` ({:#04x}), found {jump:#04x}",
+            spec::JUMP.opcode
+        ));
+    }
+
+    let (&count, mut rest) = rest
+        .split_first()
+        .ok_or("expected an instruction count after `jump`")?;
+
+    let mut instructions = Vec::new();
+
+    for index in 0..count {
+        let (&length, tail) = rest
+            .split_first()
+            .ok_or_else(|| format!("expected the length of instruction {index}"))?;
+
+        if tail.len() < usize::from(length) {
+            return Err(format!(
+                "instruction {index} is {length} bytes long, but only {} bytes remain",
+                tail.len()
+            ));
+        }
+
+        let (bytes, tail) = tail.split_at(usize::from(length));
+        let decoded = decode(bytes).map_err(|error| format!("instruction {index}: {error}"))?;
+
+        instructions.push(Decoded {
+            offset: payload.len() - tail.len() - bytes.len(),
+            ..decoded
+        });
+        rest = tail;
+    }
+
+    if !rest.is_empty() {
+        return Err(format!("{} bytes follow the last instruction", rest.len()));
+    }
+
+    Ok(instructions)
+}
+
+/// Decode a payload, pairing each instruction with its mapping in `source_map`, if it has one.
+pub fn annotate<'a>(
+    payload: &[u8],
+    source_map: &'a SourceMap,
+) -> Result<Vec<(Decoded, Option<&'a Mapping>)>, String> {
+    Ok(disassemble(payload)?
+        .into_iter()
+        .map(|decoded| {
+            let mapping = source_map.at(decoded.offset);
+            (decoded, mapping)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::utils::hex;
+
+    use super::*;
+    use crate::codegen::Codegen;
+
+    /// One of each encoded opcode, with every flag set both ways.
+    fn opcodes() -> Vec<Opcode> {
+        let mut opcodes = Vec::new();
+
+        for flag in [U256::zero(), U256::one()] {
+            opcodes.extend([
+                Opcode::Allocate {
+                    use_max: flag,
+                    pool_id: U256::from(u64::MAX),
+                    delta_liquidity: U256::from(675) * U256::exp10(18),
+                },
+                Opcode::Deallocate {
+                    use_max: flag,
+                    pool_id: U256::from(34),
+                    delta_liquidity: U256::zero(),
+                },
+                Opcode::Swap {
+                    use_max: flag,
+                    pool_id: U256::from(34),
+                    amount_0: U256::from(1500),
+                    amount_1: U256::from(u128::MAX),
+                    sell_asset: flag,
+                },
+            ]);
+        }
+
+        opcodes.extend([
+            Opcode::Claim {
+                pool_id: U256::from(34),
+                fee_0: U256::from(5),
+                fee_1: U256::from(25_000),
+            },
+            Opcode::CreatePool {
+                pair_id: U256::from(0xffffff),
+                controller: Address::repeat_byte(0xab),
+                priority_fee: U256::from(0),
+                fee: U256::from(100),
+                vol: U256::from(1000),
+                dur: U256::from(365),
+                jit: U256::from(u16::MAX),
+                max_price: U256::from(1000),
+                price: U256::from(10),
+            },
+            Opcode::CreatePair {
+                token_0: Address::repeat_byte(1),
+                token_1: Address::repeat_byte(2),
+            },
+        ]);
+
+        opcodes
+    }
+
+    fn encode(opcode: &Opcode) -> Vec<u8> {
+        hex::decode(Codegen::from(vec![opcode.clone()]).encode().pop().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        for opcode in opcodes() {
+            let encoded = encode(&opcode);
+            let decoded = decode(&encoded).unwrap();
+
+            assert_eq!(decoded.opcode, opcode);
+            assert_eq!(decoded.fields.first().unwrap().range, 0..1);
+            assert_eq!(decoded.fields.last().unwrap().range.end, encoded.len());
+        }
+    }
+
+    #[test]
+    fn round_trip_payload() {
+        let codegen = Codegen::from(opcodes());
+        let payload = codegen.payload().unwrap();
+        let decoded = disassemble(&payload).unwrap();
+
+        assert_eq!(
+            decoded
+                .iter()
+                .map(|decoded| &decoded.opcode)
+                .collect::<Vec<_>>(),
+            codegen.opcodes().iter().collect::<Vec<_>>()
+        );
+
+        // Each instruction starts after its length prefix.
+        for decoded in &decoded {
+            assert_eq!(
+                usize::from(payload[decoded.offset - 1]),
+                encode(&decoded.opcode).len()
+            );
+        }
+    }
+
+    #[test]
+    fn unknown() {
+        assert_eq!(decode(&[0x00]).unwrap().opcode, Opcode::Unknown);
+    }
+
+    #[test]
+    fn truncated() {
+        for opcode in opcodes() {
+            let encoded = encode(&opcode);
+
+            for length in 0..encoded.len() {
+                assert!(
+                    decode(&encoded[..length]).is_err(),
+                    "{} bytes of {opcode:?} decoded",
+                    length
+                );
+            }
+        }
+
+        assert_eq!(
+            decode(&encode(&opcodes()[0])[..10]).unwrap_err(),
+            "expected `deltaLiquidity` at byte 10, but the instruction is 10 bytes long"
+        );
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut encoded = encode(&opcodes()[0]);
+        encoded.push(0);
+
+        assert_eq!(
+            decode(&encoded).unwrap_err(),
+            "`allocate` is 26 bytes long, but 27 bytes were given"
+        );
+    }
+
+    #[test]
+    fn garbage() {
+        assert_eq!(decode(&[0x0f]).unwrap_err(), "unknown opcode 0x0f");
+        assert_eq!(decode(&[0xaa]).unwrap_err(), "unknown opcode 0xaa");
+
+        // A swap whose pointer does not point past the first amount.
+        let mut encoded = encode(&opcodes()[2]);
+        encoded[9] = 26;
+
+        assert_eq!(
+            decode(&encoded).unwrap_err(),
+            "expected a pointer of 27 at byte 9, found 26"
+        );
+
+        // An amount whose power of ten overflows 256 bits.
+        let mut encoded = encode(&opcodes()[0]);
+        encoded[9] = 0xff;
+
+        assert!(decode(&encoded)
+            .unwrap_err()
+            .ends_with("does not fit in 256 bits"));
+    }
+
+    #[test]
+    fn malformed_payloads() {
+        let cases: [(&[u8], &str); 5] = [
+            (&[], "the payload is empty"),
+            (
+                &[0x01],
+                "expected the payload to start with `jump` (0xaa), found 0x01",
+            ),
+            (&[0xaa], "expected an instruction count after `jump`"),
+            (&[0xaa, 0x01], "expected the length of instruction 0"),
+            (
+                &[0xaa, 0x01, 0x02, 0x00],
+                "instruction 0 is 2 bytes long, but only 1 bytes remain",
+            ),
+        ];
+
+        for (payload, message) in cases {
+            assert_eq!(disassemble(payload).unwrap_err(), message);
+        }
+
+        assert_eq!(
+            disassemble(&[0xaa, 0x01, 0x01, 0x00, 0x00]).unwrap_err(),
+            "1 bytes follow the last instruction"
+        );
+    }
+}
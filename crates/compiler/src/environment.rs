@@ -0,0 +1,130 @@
+//! # Environment
+//!
+//! Values supplied to the assembler from outside the source, typically by a project manifest.
+//! This covers named addresses, token decimals used to scale amounts, and default parameter values.
+
+use std::collections::BTreeMap;
+
+use crate::opcode::{Address, U256};
+
+/// Definitions available to a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Environment {
+    /// Addresses that can be referred to by name, e.g. `controller: treasury`.
+    pub addresses: BTreeMap<String, Address>,
+    /// Decimals of token symbols, so that `amount0: 1.5 USDC` encodes `1500000`.
+    pub decimals: BTreeMap<String, u8>,
+    /// Default parameter values keyed by instruction name, then parameter name.
+    /// Values are written as in source: an integer, an address, or a name from the address book.
+    pub defaults: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Environment {
+    /// An environment with no definitions.
+    pub const fn new() -> Self {
+        Self {
+            addresses: BTreeMap::new(),
+            decimals: BTreeMap::new(),
+            defaults: BTreeMap::new(),
+        }
+    }
+
+    /// Resolve an address literal, or a name from the address book.
+    pub fn address(&self, value: &str) -> Option<Address> {
+        match self.addresses.get(value) {
+            Some(address) => Some(*address),
+            None if value.starts_with("0x") || value.starts_with("0X") => value.parse().ok(),
+            None => None,
+        }
+    }
+
+    /// Scale a literal such as `1.5` by the decimals of `symbol`.
+    /// Returns `None` if the symbol is unknown or the literal has more decimal places than the token.
+    pub fn scale(&self, literal: &str, symbol: &str) -> Option<U256> {
+        let decimals = usize::from(*self.decimals.get(symbol)?);
+        let (integer, fraction) = literal.split_once('.').unwrap_or((literal, ""));
+
+        if fraction.len() > decimals || (integer.is_empty() && fraction.is_empty()) {
+            return None;
+        }
+
+        let digits = format!("{integer}{fraction:0<decimals$}");
+
+        U256::from_dec_str(&digits).ok()
+    }
+
+    /// The default value of a parameter, if one is configured.
+    pub fn default(&self, instruction: &str, parameter: &str) -> Option<&str> {
+        self.defaults
+            .get(instruction)?
+            .get(parameter)
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment() -> Environment {
+        let mut environment = Environment::new();
+        environment
+            .addresses
+            .insert("treasury".to_string(), Address::repeat_byte(1));
+        environment.decimals.insert("USDC".to_string(), 6);
+        environment.decimals.insert("WETH".to_string(), 18);
+        environment.defaults.insert(
+            "createPool".to_string(),
+            [("controller".to_string(), "treasury".to_string())].into(),
+        );
+
+        environment
+    }
+
+    #[test]
+    fn addresses() {
+        let environment = environment();
+
+        assert_eq!(
+            environment.address("treasury"),
+            Some(Address::repeat_byte(1))
+        );
+        assert_eq!(
+            environment.address("0x0202020202020202020202020202020202020202"),
+            Some(Address::repeat_byte(2))
+        );
+        assert_eq!(environment.address("vault"), None);
+        assert_eq!(environment.address("0x1234"), None);
+    }
+
+    #[test]
+    fn scale() {
+        let environment = environment();
+
+        assert_eq!(
+            environment.scale("1.5", "USDC"),
+            Some(U256::from(1_500_000))
+        );
+        assert_eq!(environment.scale("2", "USDC"), Some(U256::from(2_000_000)));
+        assert_eq!(environment.scale(".000001", "USDC"), Some(U256::one()));
+        assert_eq!(environment.scale("1.", "WETH"), Some(U256::exp10(18)));
+
+        // More decimal places than the token has, an unknown symbol, or no digits at all.
+        assert_eq!(environment.scale("0.0000001", "USDC"), None);
+        assert_eq!(environment.scale("1", "DAI"), None);
+        assert_eq!(environment.scale(".", "USDC"), None);
+        assert_eq!(environment.scale("-1", "USDC"), None);
+    }
+
+    #[test]
+    fn defaults() {
+        let environment = environment();
+
+        assert_eq!(
+            environment.default("createPool", "controller"),
+            Some("treasury")
+        );
+        assert_eq!(environment.default("createPool", "fee"), None);
+        assert_eq!(environment.default("swap", "controller"), None);
+    }
+}
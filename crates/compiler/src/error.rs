@@ -0,0 +1,108 @@
+//! # Error
+//!
+//! Errors and warnings raised while compiling folio source.
+//! Every diagnostic points at the span of source that caused it, so it can be rendered as a diagnostic.
+
+use std::fmt;
+
+use codemap::CodeMap;
+use codemap_diagnostic::{ColorConfig, Diagnostic, Emitter, Level, SpanLabel, SpanStyle};
+
+use crate::token::Span;
+
+/// A compilation error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    /// Human readable description of the error.
+    pub message: String,
+    /// Span of source the error refers to.
+    pub span: Span,
+}
+
+impl Error {
+    /// Instantiate an error.
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// Render the error against `source` and print it to stderr.
+    pub fn emit(&self, name: &str, source: &str) {
+        let mut codemap = CodeMap::new();
+        let file = codemap.add_file(name.to_string(), source.to_string());
+
+        self.emit_with(
+            &codemap,
+            file.span
+                .subspan(self.span.start as u64, self.span.end as u64),
+        );
+    }
+
+    /// Print the error to stderr, pointing at `span` of a file in `codemap`.
+    pub fn emit_with(&self, codemap: &CodeMap, span: codemap::Span) {
+        emit(Level::Error, &self.message, Some((codemap, span)));
+    }
+
+    /// Print the error to stderr, without pointing at any source.
+    pub fn emit_unlocated(&self) {
+        emit(Level::Error, &self.message, None);
+    }
+}
+
+/// A problem that does not stop compilation, but likely makes the program revert.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Human readable description of the warning.
+    pub message: String,
+    /// Span of source the warning refers to.
+    pub span: Span,
+}
+
+impl Warning {
+    /// Instantiate a warning.
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// Print the warning to stderr, pointing at `span` of a file in `codemap`.
+    pub fn emit_with(&self, codemap: &CodeMap, span: codemap::Span) {
+        emit(Level::Warning, &self.message, Some((codemap, span)));
+    }
+
+    /// Print the warning to stderr, without pointing at any source.
+    pub fn emit_unlocated(&self) {
+        emit(Level::Warning, &self.message, None);
+    }
+}
+
+/// Print a diagnostic to stderr, pointing at `location` if any.
+fn emit(level: Level, message: &str, location: Option<(&CodeMap, codemap::Span)>) {
+    let diagnostic = Diagnostic {
+        level,
+        message: message.to_string(),
+        code: None,
+        spans: location
+            .iter()
+            .map(|(_, span)| SpanLabel {
+                span: *span,
+                label: None,
+                style: SpanStyle::Primary,
+            })
+            .collect(),
+    };
+
+    Emitter::stderr(ColorConfig::Auto, location.map(|(codemap, _)| codemap)).emit(&[diagnostic]);
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,663 @@
+//! # Formatter
+//!
+//! Re-emits folio source in a canonical layout, keeping comments.
+//! Macros open their brace on the same line, instructions take one parameter per line in [`spec`] order,
+//! and the colons of each instruction's parameters are aligned.
+//! Values are kept as written, so names from the address book and token units survive formatting.
+
+use std::cell::Cell;
+
+use crate::{
+    error::Error,
+    spec,
+    token::{Span, Token, TokenType},
+};
+
+/// Indentation of one level.
+const INDENT: &str = "\t";
+
+/// Comments attached to a node of the syntax tree.
+#[derive(Debug, Default)]
+struct Comments<'a> {
+    /// Comments on the lines before the node.
+    leading: Vec<&'a str>,
+    /// A comment at the end of the node's last line.
+    trailing: Option<&'a str>,
+    /// Whether a blank line separates the node, or its leading comments, from what comes before it.
+    blank: bool,
+}
+
+/// A node of the syntax tree, with its comments.
+#[derive(Debug)]
+struct Node<'a, T> {
+    node: T,
+    comments: Comments<'a>,
+}
+
+#[derive(Debug)]
+enum Item<'a> {
+    Import(&'a str),
+    Extern {
+        kind: &'a str,
+        id: &'a str,
+    },
+    Macro {
+        /// The `pub` or `entry` keyword, if the macro is an entry point.
+        entry: Option<&'a str>,
+        name: &'a str,
+        /// A comment after the opening brace.
+        opening: Option<&'a str>,
+        body: Vec<Node<'a, Statement<'a>>>,
+        /// Comments before the closing brace.
+        closing: Vec<&'a str>,
+    },
+}
+
+#[derive(Debug)]
+enum Statement<'a> {
+    Invocation(&'a str),
+    Instruction {
+        name: &'a str,
+        /// A comment after the instruction name.
+        header: Option<&'a str>,
+        parameters: Vec<Node<'a, Parameter<'a>>>,
+    },
+}
+
+#[derive(Debug)]
+struct Parameter<'a> {
+    key: &'a str,
+    /// Each token of the value, e.g. a literal and its unit.
+    value: Vec<&'a str>,
+}
+
+/// Parses source into a syntax tree that keeps comments and values as written.
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token<'a>>,
+    cursor: Cell<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            tokens: Token::lex_with_comments(source),
+            cursor: Cell::new(0),
+        }
+    }
+
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.cursor.get()).copied()
+    }
+
+    fn advance(&self) -> Token<'a> {
+        let token = self.tokens[self.cursor.get()];
+        self.cursor.set(self.cursor.get() + 1);
+        token
+    }
+
+    /// The end of the previous token, or the start of the source.
+    fn previous_end(&self) -> usize {
+        match self.cursor.get() {
+            0 => 0,
+            cursor => self.tokens[cursor - 1].span.end,
+        }
+    }
+
+    fn newlines(&self, start: usize, end: usize) -> usize {
+        self.source[start..end].matches('\n').count()
+    }
+
+    fn expect(&self, expected: TokenType) -> Result<Token<'a>, Error> {
+        match self.peek() {
+            Some(token) if token.ttype == expected => Ok(self.advance()),
+            Some(token) if token.ttype == TokenType::Error => Err(Error::new(
+                format!("unrecognized token `{}`", token.slice),
+                token.span,
+            )),
+            Some(token) => Err(Error::new(
+                format!("expected {expected}, found `{}`", token.slice),
+                token.span,
+            )),
+            None => Err(Error::new(
+                format!("expected {expected}, found end of input"),
+                Span::new(self.source.len(), self.source.len()),
+            )),
+        }
+    }
+
+    /// Consume the comments before the next node.
+    fn leading(&self) -> Comments<'a> {
+        let mut comments = Comments::default();
+
+        if let Some(next) = self.peek() {
+            comments.blank =
+                self.cursor.get() > 0 && self.newlines(self.previous_end(), next.span.start) > 1;
+        }
+
+        while let Some(comment) = self
+            .peek()
+            .filter(|token| token.ttype == TokenType::Comment)
+        {
+            comments.leading.push(comment.slice.trim_end());
+            self.advance();
+        }
+
+        comments
+    }
+
+    /// Consume a comment on the same line as the previous token.
+    fn trailing(&self) -> Option<&'a str> {
+        let comment = self
+            .peek()
+            .filter(|token| token.ttype == TokenType::Comment)
+            .filter(|token| self.newlines(self.previous_end(), token.span.start) == 0)?;
+
+        self.advance();
+        Some(comment.slice.trim_end())
+    }
+
+    /// Parse every item, returning them with the comments after the last one.
+    fn parse(&self) -> Result<(Vec<Node<'a, Item<'a>>>, Comments<'a>), Error> {
+        let mut items = Vec::new();
+
+        loop {
+            let mut comments = self.leading();
+
+            let Some(token) = self.peek() else {
+                return Ok((items, comments));
+            };
+
+            let item = match token.ttype {
+                TokenType::Import => {
+                    self.advance();
+                    Item::Import(self.expect(TokenType::StringLiteral)?.slice)
+                }
+                TokenType::Extern => {
+                    self.advance();
+                    Item::Extern {
+                        kind: self.expect(TokenType::Identifier)?.slice,
+                        id: self.expect(TokenType::Literal)?.slice,
+                    }
+                }
+                TokenType::Pub | TokenType::Macro => self.parse_macro()?,
+                _ => {
+                    return Err(Error::new(
+                        format!(
+                            "expected `macro`, `import` or `extern`, found `{}`",
+                            token.slice
+                        ),
+                        token.span,
+                    ))
+                }
+            };
+
+            comments.trailing = self.trailing();
+            items.push(Node {
+                node: item,
+                comments,
+            });
+        }
+    }
+
+    fn parse_macro(&self) -> Result<Item<'a>, Error> {
+        let entry = match self.peek() {
+            Some(token) if token.ttype == TokenType::Pub => Some(self.advance().slice),
+            _ => None,
+        };
+
+        self.expect(TokenType::Macro)?;
+        let name = self.expect(TokenType::Identifier)?.slice;
+        self.expect(TokenType::OpenBrace)?;
+        let opening = self.trailing();
+
+        let mut body = Vec::new();
+
+        let closing = loop {
+            let mut comments = self.leading();
+
+            match self.peek() {
+                Some(token) if token.ttype == TokenType::CloseBrace => break comments.leading,
+                None => break comments.leading,
+                _ => {}
+            }
+
+            let statement = self.parse_statement()?;
+            comments.trailing = self.trailing();
+
+            body.push(Node {
+                node: statement,
+                comments,
+            });
+        };
+
+        self.expect(TokenType::CloseBrace)?;
+
+        Ok(Item::Macro {
+            entry,
+            name,
+            opening,
+            body,
+            closing,
+        })
+    }
+
+    fn parse_statement(&self) -> Result<Statement<'a>, Error> {
+        let token = self.advance();
+
+        match token.ttype {
+            TokenType::Identifier => Ok(Statement::Invocation(token.slice)),
+            TokenType::Unknown
+            | TokenType::Allocate
+            | TokenType::Deallocate
+            | TokenType::Claim
+            | TokenType::Swap
+            | TokenType::CreatePool
+            | TokenType::CreatePair
+            | TokenType::Jump => {
+                if self
+                    .peek()
+                    .is_some_and(|token| token.ttype == TokenType::Colon)
+                {
+                    self.advance();
+                }
+
+                let header = self.trailing();
+                let mut parameters = Vec::new();
+
+                loop {
+                    let cursor = self.cursor.get();
+                    let mut comments = self.leading();
+
+                    if !self.peek().is_some_and(|token| token.ttype.is_parameter()) {
+                        // The comments belong to the next statement.
+                        self.cursor.set(cursor);
+                        break;
+                    }
+
+                    let parameter = self.parse_parameter()?;
+                    comments.trailing = self.trailing();
+
+                    parameters.push(Node {
+                        node: parameter,
+                        comments,
+                    });
+                }
+
+                Ok(Statement::Instruction {
+                    name: token.slice,
+                    header,
+                    parameters,
+                })
+            }
+            TokenType::Error => Err(Error::new(
+                format!("unrecognized token `{}`", token.slice),
+                token.span,
+            )),
+            _ => Err(Error::new(
+                format!(
+                    "expected an instruction or macro invocation, found `{}`",
+                    token.slice
+                ),
+                token.span,
+            )),
+        }
+    }
+
+    fn parse_parameter(&self) -> Result<Parameter<'a>, Error> {
+        let key = self.advance().slice;
+        self.expect(TokenType::Colon)?;
+
+        let value = match self.peek() {
+            Some(token)
+                if matches!(
+                    token.ttype,
+                    TokenType::Literal | TokenType::AddressLiteral | TokenType::Identifier
+                ) =>
+            {
+                self.advance()
+            }
+            Some(token) => {
+                return Err(Error::new(
+                    format!("expected a value for `{key}`, found `{}`", token.slice),
+                    token.span,
+                ))
+            }
+            None => {
+                return Err(Error::new(
+                    format!("expected a value for `{key}`, found end of input"),
+                    Span::new(self.source.len(), self.source.len()),
+                ))
+            }
+        };
+
+        let mut parameter = Parameter {
+            key,
+            value: vec![value.slice],
+        };
+
+        // A token symbol on the same line denominates a literal.
+        if let Some(unit) = self
+            .peek()
+            .filter(|_| value.ttype == TokenType::Literal)
+            .filter(|token| token.ttype == TokenType::Identifier)
+            .filter(|token| self.newlines(value.span.end, token.span.start) == 0)
+        {
+            self.advance();
+            parameter.value.push(unit.slice);
+        }
+
+        Ok(parameter)
+    }
+}
+
+/// Appends formatted lines to a string.
+#[derive(Default)]
+struct Printer {
+    output: String,
+}
+
+impl Printer {
+    fn line(&mut self, depth: usize, text: &str, trailing: Option<&str>) {
+        self.output.push_str(&INDENT.repeat(depth));
+        self.output.push_str(text);
+
+        if let Some(comment) = trailing {
+            self.output.push(' ');
+            self.output.push_str(comment);
+        }
+
+        self.output.push('\n');
+    }
+
+    fn blank(&mut self) {
+        self.output.push('\n');
+    }
+
+    fn comments(&mut self, depth: usize, comments: &[&str]) {
+        for comment in comments {
+            self.line(depth, comment, None);
+        }
+    }
+
+    fn item(&mut self, item: &Item) {
+        match item {
+            Item::Import(path) => self.output.push_str(&format!("import {path}")),
+            Item::Extern { kind, id } => self.output.push_str(&format!("extern {kind} {id}")),
+            Item::Macro {
+                entry,
+                name,
+                opening,
+                body,
+                closing,
+            } => {
+                let entry = entry.map(|entry| format!("{entry} ")).unwrap_or_default();
+
+                if body.is_empty() && closing.is_empty() && opening.is_none() {
+                    self.output.push_str(&format!("{entry}macro {name} {{}}"));
+                    return;
+                }
+
+                self.line(0, &format!("{entry}macro {name} {{"), *opening);
+
+                for (index, statement) in body.iter().enumerate() {
+                    if index > 0 && statement.comments.blank {
+                        self.blank();
+                    }
+
+                    self.comments(1, &statement.comments.leading);
+                    self.statement(&statement.node, statement.comments.trailing);
+                }
+
+                self.comments(1, closing);
+                self.output.push('}');
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, trailing: Option<&str>) {
+        match statement {
+            Statement::Invocation(name) => self.line(1, name, trailing),
+            Statement::Instruction {
+                name,
+                header,
+                parameters,
+            } => {
+                let instruction = spec::instruction(name);
+                let colon = if parameters.is_empty()
+                    && instruction.is_some_and(|instruction| instruction.parameters.is_empty())
+                {
+                    ""
+                } else {
+                    ":"
+                };
+
+                self.line(1, &format!("{name}{colon}"), header.or(trailing));
+
+                // Parameters the instruction does not have keep their order, after the others.
+                let position = |key: &str| {
+                    instruction
+                        .and_then(|instruction| {
+                            instruction
+                                .parameters
+                                .iter()
+                                .position(|parameter| parameter.name == key)
+                        })
+                        .unwrap_or(usize::MAX)
+                };
+
+                let mut parameters = parameters.iter().collect::<Vec<_>>();
+                parameters.sort_by_key(|parameter| position(parameter.node.key));
+
+                let width = parameters
+                    .iter()
+                    .map(|parameter| parameter.node.key.len())
+                    .max()
+                    .unwrap_or_default();
+
+                for parameter in parameters {
+                    self.comments(2, &parameter.comments.leading);
+                    self.line(
+                        2,
+                        &format!(
+                            "{:<width$}: {}",
+                            parameter.node.key,
+                            parameter.node.value.join(" ")
+                        ),
+                        parameter.comments.trailing,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Format `source` in the canonical layout.
+pub fn format(source: &str) -> Result<String, Error> {
+    let (items, end) = Parser::new(source).parse()?;
+    let mut printer = Printer::default();
+
+    for (index, item) in items.iter().enumerate() {
+        let separated = index > 0
+            && (item.comments.blank
+                || matches!(item.node, Item::Macro { .. })
+                || matches!(items[index - 1].node, Item::Macro { .. }));
+
+        if separated {
+            printer.blank();
+        }
+
+        printer.comments(0, &item.comments.leading);
+        printer.item(&item.node);
+
+        if let Some(comment) = item.comments.trailing {
+            printer.output.push(' ');
+            printer.output.push_str(comment);
+        }
+
+        printer.output.push('\n');
+    }
+
+    if !end.leading.is_empty() {
+        if !items.is_empty() {
+            printer.blank();
+        }
+
+        printer.comments(0, &end.leading);
+    }
+
+    Ok(printer.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    const SOURCE: &str = "\
+// Pools used below.
+extern pool 34 // the ETH/USDC pool
+extern pair 7
+import \"base.fvm\"
+pub macro main { // entry point
+  // Provide liquidity first.
+  allocate: deltaLiquidity: 10 poolId: 34 // the pool, in wei
+    useMax: 0
+  swap: // sell the first asset
+    // Exact input.
+    amount0: 15 poolId: 34 amount1: 20 sellAsset: 1 useMax: 0
+
+
+  // Then collect.
+  fees
+  // Nothing else.
+}
+macro fees {claim: poolId: 34 fee0: 1 fee1: 2}
+macro empty {}
+// End of file.
+";
+
+    const FORMATTED: &str = "\
+// Pools used below.
+extern pool 34 // the ETH/USDC pool
+extern pair 7
+import \"base.fvm\"
+
+pub macro main { // entry point
+\t// Provide liquidity first.
+\tallocate:
+\t\tuseMax        : 0
+\t\tpoolId        : 34 // the pool, in wei
+\t\tdeltaLiquidity: 10
+\tswap: // sell the first asset
+\t\tuseMax   : 0
+\t\tpoolId   : 34
+\t\t// Exact input.
+\t\tamount0  : 15
+\t\tamount1  : 20
+\t\tsellAsset: 1
+
+\t// Then collect.
+\tfees
+\t// Nothing else.
+}
+
+macro fees {
+\tclaim:
+\t\tpoolId: 34
+\t\tfee0  : 1
+\t\tfee1  : 2
+}
+
+macro empty {}
+
+// End of file.
+";
+
+    fn comments(source: &str) -> Vec<&str> {
+        Token::lex_with_comments(source)
+            .into_iter()
+            .filter(|token| token.ttype == TokenType::Comment)
+            .map(|token| token.slice.trim_end())
+            .collect()
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(format(SOURCE).unwrap(), FORMATTED);
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let formatted = format(SOURCE).unwrap();
+
+        assert_eq!(comments(&formatted), comments(SOURCE));
+        assert_eq!(comments(SOURCE).len(), 10);
+    }
+
+    #[test]
+    fn idempotent() {
+        let sources = [
+            SOURCE,
+            FORMATTED,
+            include_str!("../../../examples/example.fvm"),
+            "",
+            "// Only a comment.",
+            "macro main{swap:poolId:34 amount0:1 ether amount1:2 sellAsset:0 useMax:1}",
+            "macro main {\n\tcreatePair: token0: WETH token1: USDC // names\n}",
+        ];
+
+        for source in sources {
+            let once = format(source).unwrap();
+            assert_eq!(format(&once).unwrap(), once, "formatting changed:\n{once}");
+        }
+    }
+
+    #[test]
+    fn same_program() {
+        let source = SOURCE.replace("import \"base.fvm\"\n", "");
+        let formatted = format(&source).unwrap();
+
+        assert_eq!(
+            Assembler::parse(Token::lex(&formatted)).unwrap(),
+            Assembler::parse(Token::lex(&source)).unwrap()
+        );
+    }
+
+    #[test]
+    fn units_stay_on_their_line() {
+        let formatted =
+            format("macro main {\n\tallocate: deltaLiquidity: 1 ether\n\tfoo\n}").unwrap();
+
+        assert!(formatted.contains("deltaLiquidity: 1 ether\n"));
+        assert!(formatted.contains("\n\tfoo\n"));
+
+        let formatted = format("macro main {\n\tallocate: deltaLiquidity: 1\n\tfoo\n}").unwrap();
+
+        assert!(formatted.contains("deltaLiquidity: 1\n\tfoo\n"));
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [
+            ("macro main {", "expected `}`, found end of input"),
+            (
+                "macro main { swap: poolId: }",
+                "expected a value for `poolId`, found `}`",
+            ),
+            (
+                "swap: poolId: 1",
+                "expected `macro`, `import` or `extern`, found `swap`",
+            ),
+            (
+                "macro main { 34 }",
+                "expected an instruction or macro invocation, found `34`",
+            ),
+        ];
+
+        for (source, message) in cases {
+            assert_eq!(format(source).unwrap_err().message, message, "{source}");
+        }
+    }
+}
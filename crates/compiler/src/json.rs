@@ -0,0 +1,238 @@
+//! # Json
+//!
+//! An alternative front end that reads a program from a JSON or YAML document, enabled by the `json` feature.
+//! A document is a list of instructions, e.g. `[{"op": "allocate", "useMax": 0, "poolId": 34, "deltaLiquidity": 675}]`,
+//! and produces the same [`Vec<Opcode>`] as [`Assembler::parse`](crate::assembler::Assembler::parse).
+//! Parameters with a default in the [`spec`] may be omitted, as in source.
+//! Integers are numbers or strings, and must be strings if they do not fit in 64 bits, which numbers cannot hold exactly.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    error::Error,
+    opcode::Opcode,
+    serialize,
+    spec::{self, Kind},
+    token::Span,
+};
+
+/// Syntax of a strategy document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+/// Parse a strategy document into a vector of opcodes.
+/// Errors name the path of the offending value within the document, e.g. `[2].poolId`.
+pub fn parse(source: &str, format: Format) -> Result<Vec<Opcode>, Error> {
+    let (path, message, offset) = match format {
+        Format::Json => {
+            let deserializer = &mut serde_json::Deserializer::from_str(source);
+
+            match serde_path_to_error::deserialize(deserializer) {
+                Ok(opcodes) => return validate(opcodes),
+                Err(error) => {
+                    let inner = error.inner();
+                    let message = inner.to_string();
+                    let message = match message.rsplit_once(" at line ") {
+                        Some((message, _position)) => message.to_string(),
+                        None => message,
+                    };
+
+                    // Syntax errors have no meaningful path, only a position.
+                    let path = if inner.is_data() {
+                        error.path().to_string()
+                    } else {
+                        ".".to_string()
+                    };
+
+                    (path, message, offset(source, inner.line(), inner.column()))
+                }
+            }
+        }
+        Format::Yaml => {
+            let deserializer = serde_yaml::Deserializer::from_str(source);
+
+            match serde_path_to_error::deserialize(deserializer) {
+                Ok(opcodes) => return validate(opcodes),
+                Err(error) => {
+                    let inner = error.inner();
+                    let offset = inner
+                        .location()
+                        .map(|location| location.index())
+                        .unwrap_or_default();
+
+                    // Serde buffers internally tagged instructions without 128-bit integers,
+                    // so integers too large for 64 bits fail before reaching the `U256` visitor.
+                    let message = inner.to_string();
+                    let message = if message.contains(" as u128,") || message.contains(" as i128,")
+                    {
+                        format!("integer out of range; {}", serialize::u256::LARGE_INTEGERS)
+                    } else {
+                        message
+                    };
+
+                    (error.path().to_string(), message, offset)
+                }
+            }
+        }
+    };
+
+    // Instructions are internally tagged by `op`, so serde only tracks paths down to the instruction.
+    // Re-check the fields of the failing instruction one by one to find the offending parameter.
+    let path = refine(source, format).unwrap_or(path);
+
+    let message = if path == "." {
+        message
+    } else {
+        format!("at `{path}`: {message}")
+    };
+
+    Err(Error::new(message, Span::new(offset, offset)))
+}
+
+/// Check every argument against the range of its parameter.
+fn validate(opcodes: Vec<Opcode>) -> Result<Vec<Opcode>, Error> {
+    for (index, opcode) in opcodes.iter().enumerate() {
+        for (parameter, argument) in opcode.arguments() {
+            parameter.validate(&argument).map_err(|message| {
+                Error::new(
+                    format!("at `[{index}].{}`: {message}", parameter.name),
+                    Span::default(),
+                )
+            })?;
+        }
+    }
+
+    Ok(opcodes)
+}
+
+/// Path of the first parameter that fails to deserialize, if any.
+fn refine(source: &str, format: Format) -> Option<String> {
+    let document: Vec<Value> = match format {
+        Format::Json => serde_json::from_str(source).ok()?,
+        Format::Yaml => serde_yaml::from_str(source).ok()?,
+    };
+
+    document
+        .iter()
+        .enumerate()
+        .find_map(|(index, instruction)| {
+            Opcode::deserialize(instruction).err()?;
+
+            let object = instruction.as_object()?;
+            let spec = spec::instruction(object.get("op")?.as_str()?)?;
+
+            object.iter().find_map(|(key, value)| {
+                let invalid = match spec.parameter(key).map(|parameter| parameter.kind) {
+                    _ if key == "op" => false,
+                    None => true,
+                    Some(Kind::Address) => serialize::address::deserialize(value).is_err(),
+                    Some(_) => serialize::u256::deserialize(value).is_err(),
+                };
+
+                invalid.then(|| format!("[{index}].{key}"))
+            })
+        })
+}
+
+/// Byte offset of a one-based line and column.
+fn offset(source: &str, line: usize, column: usize) -> usize {
+    let start: usize = source
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+
+    (start + column.saturating_sub(1)).min(source.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, codegen::Codegen, opcode::U256, token::Token};
+
+    fn delta_liquidity(source: &str, format: Format) -> Result<U256, Error> {
+        match parse(source, format)?.pop() {
+            Some(Opcode::Allocate {
+                delta_liquidity, ..
+            }) => Ok(delta_liquidity),
+            opcode => panic!("expected an allocate, found {opcode:?}"),
+        }
+    }
+
+    #[test]
+    fn large_integers_as_strings() {
+        let source = r#"[{"op": "allocate", "useMax": 0, "poolId": 34, "deltaLiquidity": "100000000000000000000"}]"#;
+
+        assert_eq!(delta_liquidity(source, Format::Json), Ok(U256::exp10(20)));
+    }
+
+    #[test]
+    fn large_integers_as_numbers() {
+        for literal in ["100000000000000000000", "1e20"] {
+            let source = format!(
+                r#"[{{"op": "allocate", "useMax": 0, "poolId": 34, "deltaLiquidity": {literal}}}]"#
+            );
+            let error = delta_liquidity(&source, Format::Json).unwrap_err();
+
+            assert!(
+                error.message.starts_with("at `[0].deltaLiquidity`: `100000000000000000000` is not an integer that fits in 64 bits"),
+                "{}",
+                error.message
+            );
+        }
+    }
+
+    #[test]
+    fn large_integers_in_yaml() {
+        let source =
+            "- op: allocate\n  useMax: 0\n  poolId: 34\n  deltaLiquidity: 100000000000000000000\n";
+
+        assert_eq!(
+            delta_liquidity(source, Format::Yaml).unwrap_err().message,
+            format!(
+                "at `[0].deltaLiquidity`: integer out of range; {}",
+                serialize::u256::LARGE_INTEGERS
+            )
+        );
+
+        let source = source.replace("100000000000000000000", "\"100000000000000000000\"");
+
+        assert_eq!(delta_liquidity(&source, Format::Yaml), Ok(U256::exp10(20)));
+    }
+
+    #[test]
+    fn defaults_match_source() {
+        let json = r#"[
+            {"op": "createPool", "pairId": 1, "controller": "0x0000000000000000000000000000000000000001", "fee": 100, "vol": 1000, "dur": 365, "jit": 4, "maxPrice": 1000, "price": 10},
+            {"op": "allocate", "poolId": 34, "deltaLiquidity": 675},
+            {"op": "swap", "sellAsset": 1, "amount1": 20, "amount0": 10, "poolId": 34}
+        ]"#;
+
+        let source = "macro main {
+            createPool: pairId: 1 controller: 0x0000000000000000000000000000000000000001 fee: 100 vol: 1000 dur: 365 jit: 4 maxPrice: 1000 price: 10
+            allocate: poolId: 34 deltaLiquidity: 675
+            swap: sellAsset: 1 amount1: 20 amount0: 10 poolId: 34
+        }";
+
+        let expressions = Assembler::parse(Token::lex(source)).unwrap();
+
+        assert_eq!(
+            Codegen::from(parse(json, Format::Json).unwrap()).encode(),
+            Codegen::new(expressions).encode()
+        );
+    }
+
+    #[test]
+    fn negative_integers() {
+        let source = r#"[{"op": "allocate", "useMax": 0, "poolId": -1, "deltaLiquidity": 1}]"#;
+
+        assert_eq!(
+            parse(source, Format::Json).unwrap_err().message,
+            "at `[0].poolId`: `-1` is negative"
+        );
+    }
+}
@@ -1,4 +1,23 @@
+#[cfg(feature = "anvil")]
+pub mod anvil;
 pub mod assembler;
+pub mod builder;
 pub mod codegen;
+pub mod disassembler;
+pub mod environment;
+pub mod error;
+pub mod formatter;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod lint;
+pub mod loader;
 pub mod opcode;
+pub mod optimizer;
+pub mod semantic;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod simulator;
+pub mod sourcemap;
+pub mod spec;
 pub mod token;
+pub mod transaction;
@@ -0,0 +1,573 @@
+//! # Lint
+//!
+//! Checks for programs that assemble, but are likely mistakes.
+//! Each [`Rule`] has an id and a [`Level`], which a project can change in the `[lints]` table of its manifest.
+//! A `// folio:allow(rule)` comment allows a rule for the instruction it is on or precedes,
+//! or for the whole macro when it precedes a macro definition.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use crate::{
+    assembler::{Assembler, Body, Expression, Macro},
+    error::{Error, Warning},
+    loader::Loader,
+    opcode::{Opcode, U256},
+    spec,
+    token::{Span, TokenType},
+};
+
+/// What to do when a rule is broken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Ignore it.
+    Allow,
+    /// Report a warning.
+    Warn,
+    /// Report an error, which fails the build.
+    Deny,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            level => Err(format!(
+                "unknown lint level `{level}`, expected `allow`, `warn` or `deny`"
+            )),
+        }
+    }
+}
+
+/// A lint rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub id: &'static str,
+    pub description: &'static str,
+    /// Level of the rule, unless a project changes it.
+    pub level: Level,
+}
+
+pub const IDENTICAL_TOKENS: Rule = Rule {
+    id: "identical_tokens",
+    description: "`createPair` with the same `token0` and `token1`",
+    level: Level::Warn,
+};
+
+pub const ZERO_AMOUNT_SWAP: Rule = Rule {
+    id: "zero_amount_swap",
+    description: "`swap` of an `amount0` of zero, without `useMax`",
+    level: Level::Warn,
+};
+
+pub const IMMEDIATE_DEALLOCATE: Rule = Rule {
+    id: "immediate_deallocate",
+    description: "`deallocate` right after an `allocate` to the same pool",
+    level: Level::Warn,
+};
+
+pub const UNUSED_MACRO: Rule = Rule {
+    id: "unused_macro",
+    description: "macro that no entry point invokes",
+    level: Level::Warn,
+};
+
+pub const MAX_PRICE_BELOW_PRICE: Rule = Rule {
+    id: "max_price_below_price",
+    description: "`createPool` with a `maxPrice` lower than its `price`",
+    level: Level::Warn,
+};
+
+pub const FEE_OUT_OF_RANGE: Rule = Rule {
+    id: "fee_out_of_range",
+    description: "`createPool` with a `fee` outside of 1 to 1000 basis points",
+    level: Level::Warn,
+};
+
+/// Every rule.
+pub static RULES: [Rule; 6] = [
+    IDENTICAL_TOKENS,
+    ZERO_AMOUNT_SWAP,
+    IMMEDIATE_DEALLOCATE,
+    UNUSED_MACRO,
+    MAX_PRICE_BELOW_PRICE,
+    FEE_OUT_OF_RANGE,
+];
+
+/// Look up a rule by id.
+pub fn rule(id: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.id == id)
+}
+
+/// A broken rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: &'static Rule,
+    /// Level of the rule, as configured, which is never [`Level::Allow`].
+    pub level: Level,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Lint {
+    /// The lint as an error, if its rule is denied, or as a warning.
+    pub fn diagnostic(&self) -> Result<Warning, Error> {
+        match self.level {
+            Level::Deny => Err(Error::new(self.to_string(), self.span)),
+            _ => Ok(Warning::new(self.to_string(), self.span)),
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.rule.id)
+    }
+}
+
+/// Lint a parsed source.
+///
+/// `loader` holds the source the macros were parsed from, and `entries` is the expanded body of each entry point.
+/// `levels` overrides the level of rules by id. An `allow` comment naming an unknown rule is an error.
+pub fn lint(
+    macros: &HashMap<&str, Macro>,
+    entries: &[(&str, Body)],
+    loader: &Loader,
+    levels: &BTreeMap<String, Level>,
+) -> Result<Vec<Lint>, Error> {
+    let allowed = allowed(loader)?;
+    let mut lints = Vec::new();
+
+    let mut report = |rule: &'static Rule, message: String, span: Span| {
+        let level = levels.get(rule.id).copied().unwrap_or(rule.level);
+        let allowed = allowed.iter().any(|(id, range)| {
+            *id == rule.id && range.start <= span.start && span.start < range.end
+        });
+
+        if level != Level::Allow && !allowed {
+            lints.push(Lint {
+                rule,
+                level,
+                message,
+                span,
+            });
+        }
+    };
+
+    for (_, body) in entries {
+        let mut previous: Option<&Opcode> = None;
+
+        for expression in body {
+            let Expression::Opcode(opcode) = &expression.node else {
+                continue;
+            };
+
+            let span = expression.span;
+
+            match opcode {
+                Opcode::CreatePair { token_0, token_1 } if token_0 == token_1 => report(
+                    &IDENTICAL_TOKENS,
+                    format!("`createPair` of {token_0:?} with itself"),
+                    span,
+                ),
+                Opcode::Swap {
+                    use_max, amount_0, ..
+                } if use_max.is_zero() && amount_0.is_zero() => report(
+                    &ZERO_AMOUNT_SWAP,
+                    "`swap` of nothing, since `amount0` is zero".to_string(),
+                    span,
+                ),
+                Opcode::Deallocate { pool_id, .. } => {
+                    if let Some(Opcode::Allocate {
+                        pool_id: allocated, ..
+                    }) = previous
+                    {
+                        if allocated == pool_id {
+                            report(
+                                &IMMEDIATE_DEALLOCATE,
+                                format!(
+                                    "`deallocate` from pool {pool_id} right after allocating to it"
+                                ),
+                                span,
+                            );
+                        }
+                    }
+                }
+                Opcode::CreatePool {
+                    fee,
+                    max_price,
+                    price,
+                    ..
+                } => {
+                    if max_price < price {
+                        report(
+                            &MAX_PRICE_BELOW_PRICE,
+                            format!(
+                                "`maxPrice` of {max_price} is lower than the `price` of {price}"
+                            ),
+                            span,
+                        );
+                    }
+
+                    if fee.is_zero() || *fee > U256::from(1000) {
+                        report(
+                            &FEE_OUT_OF_RANGE,
+                            format!("`fee` of {fee} basis points is outside of 1 to 1000"),
+                            span,
+                        );
+                    }
+                }
+                _ => {}
+            }
+
+            previous = Some(opcode);
+        }
+    }
+
+    // Macros used by entry points other than the ones being linted are not unused.
+    let names = Assembler::entries(macros)
+        .into_iter()
+        .chain(entries.iter().map(|(name, _)| *name));
+    let used = used(macros, names);
+    let mut unused = macros
+        .values()
+        .filter(|mac| !used.contains(mac.name))
+        .collect::<Vec<_>>();
+    unused.sort_by_key(|mac| mac.span.start);
+
+    for mac in unused {
+        report(
+            &UNUSED_MACRO,
+            format!("macro `{}` is never invoked", mac.name),
+            mac.span,
+        );
+    }
+
+    // Entry points often share macros, so each lint is only reported once.
+    lints.sort_by(|a, b| (a.span.start, &a.message).cmp(&(b.span.start, &b.message)));
+    lints.dedup();
+
+    Ok(lints)
+}
+
+/// Every macro reachable from `entries`.
+fn used<'a>(
+    macros: &HashMap<&'a str, Macro<'a>>,
+    entries: impl Iterator<Item = &'a str>,
+) -> HashSet<&'a str> {
+    let mut used = HashSet::new();
+    let mut pending = entries.collect::<Vec<_>>();
+
+    while let Some(name) = pending.pop() {
+        if !used.insert(name) {
+            continue;
+        }
+
+        if let Some(mac) = macros.get(name) {
+            pending.extend(
+                mac.body
+                    .iter()
+                    .filter_map(|expression| match expression.node {
+                        Expression::Invocation(name) => Some(name),
+                        Expression::Opcode(_) => None,
+                    }),
+            );
+        }
+    }
+
+    used
+}
+
+/// The rules allowed by `folio:allow` comments, with the span of source each applies to.
+fn allowed(loader: &Loader) -> Result<Vec<(&'static str, Span)>, Error> {
+    let tokens = loader.tokens();
+
+    // Tokens that start an instruction, an invocation or a macro definition.
+    let heads = tokens
+        .iter()
+        .enumerate()
+        .filter(|(index, token)| match token.ttype {
+            TokenType::Macro | TokenType::Pub => true,
+            TokenType::Identifier => {
+                *index == 0
+                    || !matches!(
+                        tokens[index - 1].ttype,
+                        TokenType::Colon | TokenType::Literal
+                    )
+            }
+            _ => spec::instruction(token.slice).is_some(),
+        })
+        .map(|(_, token)| *token)
+        .collect::<Vec<_>>();
+
+    let mut allowed = Vec::new();
+
+    for comment in loader.comments() {
+        let Some(rules) = comment
+            .slice
+            .trim_start_matches('/')
+            .trim()
+            .strip_prefix("folio:allow(")
+            .and_then(|rules| rules.strip_suffix(')'))
+        else {
+            continue;
+        };
+
+        let rules = rules
+            .split(',')
+            .map(str::trim)
+            .map(|rule| {
+                self::rule(rule)
+                    .ok_or_else(|| Error::new(format!("unknown lint rule `{rule}`"), comment.span))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // A comment after a token on the same line applies to what that token is part of,
+        // and otherwise to what follows it.
+        let trailing = tokens
+            .iter()
+            .rev()
+            .find(|token| token.span.end <= comment.span.start)
+            .and_then(|previous| {
+                let gap = Span::new(previous.span.end, comment.span.start);
+                let (_, source, local) = loader.locate(gap)?;
+
+                // The gap is clamped to a single file, so a token in another file does not count.
+                let same_file = local.end - local.start == gap.end - gap.start;
+                Some(same_file && !source[local.start..local.end].contains('\n'))
+            })
+            .unwrap_or_default();
+
+        let head = if trailing {
+            heads
+                .iter()
+                .rev()
+                .find(|head| head.span.start < comment.span.start)
+        } else {
+            heads
+                .iter()
+                .find(|head| head.span.start >= comment.span.end)
+        };
+
+        let Some(head) = head else {
+            continue;
+        };
+
+        let span = match head.ttype {
+            TokenType::Macro | TokenType::Pub => {
+                let end = tokens
+                    .iter()
+                    .find(|token| {
+                        token.ttype == TokenType::CloseBrace && token.span.start > head.span.start
+                    })
+                    .map_or(head.span.end, |token| token.span.end);
+
+                Span::new(head.span.start, end)
+            }
+            _ => head.span,
+        };
+
+        allowed.extend(rules.iter().map(|rule| (rule.id, span)));
+    }
+
+    Ok(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0x0000000000000000000000000000000000000001";
+
+    fn pool(fee: u64, max_price: u64, price: u64) -> String {
+        format!(
+            "createPool: pairId: 0 controller: {ADDRESS} priorityFee: 0 fee: {fee} vol: 1000 dur: 365 \
+             jit: 0 maxPrice: {max_price} price: {price}"
+        )
+    }
+
+    fn swap(use_max: u8, amount_0: u64) -> String {
+        format!("swap: useMax: {use_max} poolId: 34 amount0: {amount_0} amount1: 0 sellAsset: 1")
+    }
+
+    /// Lint the entry points of `source`, returning each lint's rule, level and the start of the source it points at.
+    fn run(
+        source: &str,
+        levels: &[(&str, Level)],
+    ) -> Result<Vec<(&'static str, Level, String)>, Error> {
+        let mut loader = Loader::new(Vec::new());
+        loader.load("main.fvm", None, source.to_string())?;
+
+        let assembler = Assembler::new(loader.tokens());
+        let macros = assembler.parse_macros()?;
+        let entries = Assembler::entries(&macros)
+            .into_iter()
+            .map(|name| Ok((name, Assembler::expand(&macros, name)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let levels = levels
+            .iter()
+            .map(|(id, level)| (id.to_string(), *level))
+            .collect();
+
+        Ok(lint(&macros, &entries, &loader, &levels)?
+            .into_iter()
+            .map(|lint| {
+                let text = &source[lint.span.start..lint.span.end];
+                let head = text.split([':', ' ', '\n']).next().unwrap();
+                (lint.rule.id, lint.level, head.to_string())
+            })
+            .collect())
+    }
+
+    fn lints(source: &str) -> Vec<(&'static str, String)> {
+        run(source, &[])
+            .unwrap()
+            .into_iter()
+            .map(|(id, _, head)| (id, head))
+            .collect()
+    }
+
+    fn main(body: &[&str]) -> String {
+        format!("macro main {{\n{}\n}}\n", body.join("\n"))
+    }
+
+    #[test]
+    fn identical_tokens() {
+        assert_eq!(
+            lints(include_str!("../../../examples/example.fvm")),
+            [("identical_tokens", "createPair".to_string())]
+        );
+        assert!(lints(&main(&[&format!(
+            "createPair: token0: {ADDRESS} token1: 0x0000000000000000000000000000000000000002"
+        )]))
+        .is_empty());
+    }
+
+    #[test]
+    fn zero_amount_swap() {
+        assert_eq!(
+            lints(&main(&[&swap(0, 0)])),
+            [("zero_amount_swap", "swap".to_string())]
+        );
+        assert!(lints(&main(&[&swap(1, 0), &swap(0, 1)])).is_empty());
+    }
+
+    #[test]
+    fn immediate_deallocate() {
+        let allocate = "allocate: useMax: 0 poolId: 34 deltaLiquidity: 1";
+        let deallocate =
+            |pool_id: u64| format!("deallocate: useMax: 0 poolId: {pool_id} deltaLiquidity: 1");
+
+        assert_eq!(
+            lints(&main(&[allocate, &deallocate(34)])),
+            [("immediate_deallocate", "deallocate".to_string())]
+        );
+        assert!(lints(&main(&[allocate, &deallocate(35)])).is_empty());
+        assert!(lints(&main(&[allocate, &swap(0, 1), &deallocate(34)])).is_empty());
+    }
+
+    #[test]
+    fn unused_macro() {
+        let source = "macro main {\n\tused\n}\n\nmacro used {}\n\nmacro unused {}\n";
+
+        assert_eq!(lints(source), [("unused_macro", "unused".to_string())]);
+
+        // Macros used by any entry point are used.
+        let source = "pub macro a {\n\tb\n}\n\npub macro c {}\n\nmacro b {}\n";
+        assert!(lints(source).is_empty());
+    }
+
+    #[test]
+    fn pool_parameters() {
+        assert_eq!(
+            lints(&main(&[&pool(30, 1, 2), &pool(0, 2, 1), &pool(1001, 2, 1)])),
+            [
+                ("max_price_below_price", "createPool".to_string()),
+                ("fee_out_of_range", "createPool".to_string()),
+                ("fee_out_of_range", "createPool".to_string()),
+            ]
+        );
+        assert!(lints(&main(&[&pool(1, 2, 2), &pool(1000, 2, 1)])).is_empty());
+    }
+
+    #[test]
+    fn levels() {
+        let source = main(&[&swap(0, 0), &pool(0, 2, 1)]);
+
+        assert_eq!(
+            run(
+                &source,
+                &[
+                    ("zero_amount_swap", Level::Deny),
+                    ("fee_out_of_range", Level::Allow)
+                ]
+            )
+            .unwrap(),
+            [("zero_amount_swap", Level::Deny, "swap".to_string())]
+        );
+
+        let lint = Lint {
+            rule: &ZERO_AMOUNT_SWAP,
+            level: Level::Deny,
+            message: "`swap` of nothing".to_string(),
+            span: Span::new(0, 4),
+        };
+
+        assert_eq!(
+            lint.diagnostic().unwrap_err().message,
+            "`swap` of nothing [zero_amount_swap]"
+        );
+        assert!(Lint {
+            level: Level::Warn,
+            ..lint
+        }
+        .diagnostic()
+        .is_ok());
+
+        assert_eq!("deny".parse(), Ok(Level::Deny));
+        assert!("error".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn allow_comments() {
+        let zero = swap(0, 0);
+
+        // On the instruction, before it, and before the macro it is in.
+        let trailing = main(&[&format!("{zero} // folio:allow(zero_amount_swap)"), &zero]);
+        let preceding = main(&["// folio:allow(zero_amount_swap)", &zero, &zero]);
+        let whole = format!(
+            "// folio:allow(zero_amount_swap)\n{}",
+            main(&[&zero, &zero])
+        );
+
+        assert_eq!(lints(&trailing), [("zero_amount_swap", "swap".to_string())]);
+        assert_eq!(
+            lints(&preceding),
+            [("zero_amount_swap", "swap".to_string())]
+        );
+        assert!(lints(&whole).is_empty());
+
+        // Each rule a comment names is allowed, and only those.
+        let several = main(&[
+            "// folio:allow(zero_amount_swap, fee_out_of_range)",
+            &pool(0, 1, 2),
+        ]);
+        assert_eq!(
+            lints(&several),
+            [("max_price_below_price", "createPool".to_string())]
+        );
+
+        let unknown = main(&["// folio:allow(zero_swap)", &zero]);
+        assert_eq!(
+            run(&unknown, &[]).unwrap_err().message,
+            "unknown lint rule `zero_swap`"
+        );
+    }
+}
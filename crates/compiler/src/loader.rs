@@ -0,0 +1,372 @@
+//! # Loader
+//!
+//! Loads a source file together with every file it imports.
+//! Imports are resolved relative to the importing file, then against each search path, and each file is loaded once.
+//! The tokens of all loaded files form a single stream, with spans offset so that every token maps back to its file.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use codemap::{CodeMap, File};
+
+use crate::{
+    assembler::Assembler,
+    error::{Error, Warning},
+    sourcemap::Location,
+    token::{Span, Token, TokenType},
+};
+
+/// A loaded source file.
+struct Loaded {
+    file: Arc<File>,
+    /// Canonical path of the file, if it was read from disk.
+    path: Option<PathBuf>,
+    /// Offset of the file's first byte in the combined token stream.
+    offset: usize,
+}
+
+/// A set of source files, linked by imports.
+pub struct Loader {
+    /// Directories searched for imports that are not found next to the importing file.
+    search_paths: Vec<PathBuf>,
+    codemap: CodeMap,
+    files: Vec<Loaded>,
+}
+
+impl Loader {
+    /// Instantiate a loader that resolves imports against `search_paths`.
+    pub fn new(search_paths: Vec<PathBuf>) -> Self {
+        Self {
+            search_paths,
+            codemap: CodeMap::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Load `contents`, read from `path` if it came from disk, and every file it imports.
+    pub fn load(&mut self, name: &str, path: Option<&Path>, contents: String) -> Result<(), Error> {
+        let path = path.and_then(|path| path.canonicalize().ok());
+
+        if path.is_some() && self.files.iter().any(|loaded| loaded.path == path) {
+            return Ok(());
+        }
+
+        let file = self.codemap.add_file(name.to_string(), contents);
+        let offset = self
+            .files
+            .first()
+            .map(|first| (file.span.low() - first.file.span.low()) as usize)
+            .unwrap_or_default();
+
+        self.files.push(Loaded {
+            file: file.clone(),
+            path: path.clone(),
+            offset,
+        });
+
+        let tokens = Loader::tokens_of(&file, offset);
+        let imports = Assembler::new(tokens).imports();
+
+        // Files read from stdin import relative to the working directory.
+        let directory = path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+
+        for import in imports {
+            let resolved = std::iter::once(&directory)
+                .chain(&self.search_paths)
+                .map(|directory| directory.join(import.node))
+                .find(|candidate| candidate.is_file())
+                .ok_or_else(|| {
+                    Error::new(format!("cannot find import `{}`", import.node), import.span)
+                })?;
+
+            let contents = fs::read_to_string(&resolved).map_err(|error| {
+                Error::new(
+                    format!("could not read `{}`: {error}", resolved.display()),
+                    import.span,
+                )
+            })?;
+
+            self.load(&resolved.display().to_string(), Some(&resolved), contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// The tokens of every loaded file, in load order.
+    pub fn tokens(&self) -> Vec<Token<'_>> {
+        self.files
+            .iter()
+            .flat_map(|loaded| Loader::tokens_of(&loaded.file, loaded.offset))
+            .collect()
+    }
+
+    /// The comments of every loaded file, in load order.
+    pub fn comments(&self) -> Vec<Token<'_>> {
+        self.files
+            .iter()
+            .flat_map(|loaded| {
+                Token::lex_with_comments(loaded.file.source())
+                    .into_iter()
+                    .filter(|token| token.ttype == TokenType::Comment)
+                    .map(|token| {
+                        let span = Span::new(
+                            token.span.start + loaded.offset,
+                            token.span.end + loaded.offset,
+                        );
+                        Token::new(token.ttype, token.slice, span)
+                    })
+            })
+            .collect()
+    }
+
+    /// The path of every loaded file that was read from disk.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files
+            .iter()
+            .filter_map(|loaded| loaded.path.as_deref())
+    }
+
+    /// The file a span of the token stream lies in: its path, if it was read from disk,
+    /// its source, and the span relative to the start of the file.
+    pub fn locate(&self, span: Span) -> Option<(Option<&Path>, &str, Span)> {
+        let (loaded, span) = self.file(span)?;
+
+        Some((loaded.path.as_deref(), loaded.file.source(), span))
+    }
+
+    /// The file, line and column a span of the token stream starts at.
+    pub fn location(&self, span: Span) -> Option<Location> {
+        let (loaded, span) = self.file(span)?;
+        let position = loaded.file.find_line_col(
+            loaded
+                .file
+                .span
+                .subspan(span.start as u64, span.start as u64)
+                .low(),
+        );
+
+        Some(Location {
+            file: loaded.file.name().to_string(),
+            line: position.line + 1,
+            column: position.column + 1,
+        })
+    }
+
+    /// Print `error`, raised against the tokens of this loader, to stderr.
+    /// An error outside of every loaded file is printed without a location.
+    pub fn emit(&self, error: &Error) {
+        match self.file(error.span) {
+            Some((loaded, span)) => error.emit_with(
+                &self.codemap,
+                loaded.file.span.subspan(span.start as u64, span.end as u64),
+            ),
+            None => error.emit_unlocated(),
+        }
+    }
+
+    /// Print `warning`, raised against the tokens of this loader, to stderr.
+    /// A warning outside of every loaded file is printed without a location.
+    pub fn warn(&self, warning: &Warning) {
+        match self.file(warning.span) {
+            Some((loaded, span)) => warning.emit_with(
+                &self.codemap,
+                loaded.file.span.subspan(span.start as u64, span.end as u64),
+            ),
+            None => warning.emit_unlocated(),
+        }
+    }
+
+    /// The file a span lies in, and the span relative to the start of the file.
+    fn file(&self, span: Span) -> Option<(&Loaded, Span)> {
+        let loaded = self
+            .files
+            .iter()
+            .rev()
+            .find(|loaded| loaded.offset <= span.start)?;
+
+        let length = loaded.file.source().len();
+        let start = (span.start - loaded.offset).min(length);
+        let end = (span.end - loaded.offset).clamp(start, length);
+
+        Some((loaded, Span::new(start, end)))
+    }
+
+    fn tokens_of(file: &File, offset: usize) -> Vec<Token<'_>> {
+        Token::lex(file.source())
+            .into_iter()
+            .map(|token| {
+                let span = Span::new(token.span.start + offset, token.span.end + offset);
+                Token::new(token.ttype, token.slice, span)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    /// An empty directory for the test `name`.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("folio-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        directory.canonicalize().unwrap()
+    }
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Load the file at `path`, searching for imports in `search_paths`.
+    fn load(path: &Path, search_paths: Vec<PathBuf>) -> (Loader, Result<(), Error>) {
+        let mut loader = Loader::new(search_paths);
+        let contents = fs::read_to_string(path).unwrap();
+        let result = loader.load(&path.display().to_string(), Some(path), contents);
+
+        (loader, result)
+    }
+
+    fn macros(loader: &Loader) -> BTreeSet<String> {
+        Assembler::new(loader.tokens())
+            .parse_macros()
+            .unwrap()
+            .into_keys()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn imports() {
+        let directory = directory("imports");
+        let main = directory.join("main.fvm");
+        write(
+            &main,
+            "import \"local.fvm\"\nimport \"shared.fvm\"\nmacro main { local shared }",
+        );
+        write(&directory.join("local.fvm"), "macro local {}");
+        write(&directory.join("lib").join("shared.fvm"), "macro shared {}");
+
+        // Imports next to the importing file are found first, then in the search paths.
+        let (loader, result) = load(&main, vec![directory.join("lib")]);
+        result.unwrap();
+
+        assert_eq!(
+            loader.paths().collect::<Vec<_>>(),
+            [
+                main.as_path(),
+                &directory.join("local.fvm"),
+                &directory.join("lib").join("shared.fvm")
+            ]
+        );
+        assert_eq!(
+            macros(&loader),
+            ["local", "main", "shared"].map(String::from).into()
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn cycles() {
+        let directory = directory("cycles");
+        let a = directory.join("a.fvm");
+        write(&a, "import \"b.fvm\"\nimport \"c.fvm\"\nmacro main { b c }");
+        write(
+            &directory.join("b.fvm"),
+            "import \"a.fvm\"\nimport \"c.fvm\"\nmacro b {}",
+        );
+        write(&directory.join("c.fvm"), "import \"b.fvm\"\nmacro c {}");
+
+        // Each file is loaded once, however many times it is imported.
+        let (loader, result) = load(&a, Vec::new());
+        result.unwrap();
+
+        assert_eq!(loader.paths().count(), 3);
+        assert_eq!(macros(&loader), ["b", "c", "main"].map(String::from).into());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn missing_import() {
+        let directory = directory("missing-import");
+        let main = directory.join("main.fvm");
+        write(&main, "macro main {}\nimport \"missing.fvm\"");
+
+        let (loader, result) = load(&main, vec![directory.join("lib")]);
+        let error = result.unwrap_err();
+
+        assert_eq!(error.message, "cannot find import `missing.fvm`");
+        assert_eq!(
+            loader.location(error.span).unwrap().to_string(),
+            format!("{}:2:8", main.display())
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn offsets() {
+        let directory = directory("offsets");
+        let main = directory.join("main.fvm");
+        let imported = directory.join("imported.fvm");
+        write(&main, "import \"imported.fvm\"\nmacro main { imported }");
+        write(
+            &imported,
+            "// Imported.\nmacro imported {\n\tclaim: poolId: 1\n}",
+        );
+
+        let (loader, result) = load(&main, Vec::new());
+        result.unwrap();
+
+        // The tokens of the imported file follow those of the main file, and map back to it.
+        let tokens = loader.tokens();
+        let claim = tokens.iter().find(|token| token.slice == "claim").unwrap();
+        assert!(claim.span.start > fs::read_to_string(&main).unwrap().len());
+
+        let (path, source, span) = loader.locate(claim.span).unwrap();
+        assert_eq!(path, Some(imported.as_path()));
+        assert_eq!(&source[span.start..span.end], "claim");
+        assert_eq!(
+            loader.location(claim.span).unwrap().to_string(),
+            format!("{}:3:2", imported.display())
+        );
+
+        let comments = loader.comments();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(
+            loader.location(comments[0].span).unwrap().to_string(),
+            format!("{}:1:1", imported.display())
+        );
+
+        let (path, source, span) = loader.locate(tokens[3].span).unwrap();
+        assert_eq!(path, Some(main.as_path()));
+        assert_eq!(&source[span.start..span.end], "main");
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn unlocated() {
+        let loader = Loader::new(Vec::new());
+
+        assert!(loader.locate(Span::new(0, 1)).is_none());
+        assert!(loader.location(Span::new(0, 1)).is_none());
+
+        // Diagnostics outside of every file are still printed, without a location.
+        loader.emit(&Error::new("unlocated error", Span::new(0, 1)));
+        loader.warn(&Warning::new("unlocated warning", Span::new(0, 1)));
+    }
+}
@@ -1,8 +1,20 @@
-use eth_encode_packed::ethabi::ethereum_types::{Address, U256};
+pub use eth_encode_packed::ethabi::ethereum_types::{Address, U256};
+
+use crate::spec::{self, Instruction, Parameter};
 
 /// Type representing an FVM opcode.
 /// This is the lowest level representation folio code will be lowered to, before bytecode is generated from it.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        tag = "op",
+        rename_all = "camelCase",
+        rename_all_fields = "camelCase",
+        deny_unknown_fields
+    )
+)]
 pub enum Opcode {
     /// This is the default opcode. It is used to represent an unknown opcode,
     /// and is used to initialize the FVM’s state.
@@ -11,53 +23,197 @@ pub enum Opcode {
     /// This instruction is used to add liquidity to a pool.
     /// It maintains invariant pricing for each pool that is interacted with.    
     Allocate {
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                with = "crate::serialize::u256",
+                default = "crate::serialize::defaults::use_max"
+            )
+        )]
         use_max: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         pool_id: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         delta_liquidity: U256,
     },
 
     /// This instruction is used to remove liquidity from a pool.
     /// It maintains invariant pricing for each pool that is interacted with.
     Deallocate {
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                with = "crate::serialize::u256",
+                default = "crate::serialize::defaults::use_max"
+            )
+        )]
         use_max: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         pool_id: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         delta_liquidity: U256,
     },
 
     /// This instruction is used to initialize a new pair of assets for which pools can be created.    
-    CreatePair { token_0: Address, token_1: Address },
+    CreatePair {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::address"))]
+        token_0: Address,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::address"))]
+        token_1: Address,
+    },
 
     /// This instruction is used to create a new pool.
     /// Initially, pools are not deployed with any capital, but are deployed with parameters for the CFMM as well as an initial price.    
     CreatePool {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         pair_id: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::address"))]
         controller: Address,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                with = "crate::serialize::u256",
+                default = "crate::serialize::defaults::priority_fee"
+            )
+        )]
         priority_fee: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         fee: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         vol: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         dur: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         jit: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         max_price: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         price: U256,
     },
 
     /// This instruction is used to swap between the tokens.
     /// It maintains the invariant of the trading curve.    
     Swap {
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                with = "crate::serialize::u256",
+                default = "crate::serialize::defaults::use_max"
+            )
+        )]
         use_max: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         pool_id: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         amount_0: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         amount_1: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         sell_asset: U256,
     },
 
     /// Collects all the fees generated from a positive invariant for.
     Claim {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         pool_id: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         fee_0: U256,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialize::u256"))]
         fee_1: U256,
     },
 
     /// This instructions is used to jump to a different instruction in the FVM’s state via FVM’s pointer.
     Jump,
 }
+
+/// The value of an instruction parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    Integer(U256),
+    Address(Address),
+}
+
+impl Opcode {
+    /// The specification of the instruction this opcode encodes.
+    pub fn instruction(&self) -> &'static Instruction {
+        match self {
+            Opcode::Unknown => &spec::UNKNOWN,
+            Opcode::Allocate { .. } => &spec::ALLOCATE,
+            Opcode::Deallocate { .. } => &spec::DEALLOCATE,
+            Opcode::CreatePair { .. } => &spec::CREATE_PAIR,
+            Opcode::CreatePool { .. } => &spec::CREATE_POOL,
+            Opcode::Swap { .. } => &spec::SWAP,
+            Opcode::Claim { .. } => &spec::CLAIM,
+            Opcode::Jump => &spec::JUMP,
+        }
+    }
+
+    /// Every argument of the opcode, paired with its parameter, in canonical order.
+    pub fn arguments(&self) -> Vec<(&'static Parameter, Argument)> {
+        use Argument::{Address, Integer};
+
+        let arguments = match self {
+            Opcode::Unknown | Opcode::Jump => vec![],
+            Opcode::Allocate {
+                use_max,
+                pool_id,
+                delta_liquidity,
+            }
+            | Opcode::Deallocate {
+                use_max,
+                pool_id,
+                delta_liquidity,
+            } => vec![
+                Integer(*use_max),
+                Integer(*pool_id),
+                Integer(*delta_liquidity),
+            ],
+            Opcode::CreatePair { token_0, token_1 } => vec![Address(*token_0), Address(*token_1)],
+            Opcode::CreatePool {
+                pair_id,
+                controller,
+                priority_fee,
+                fee,
+                vol,
+                dur,
+                jit,
+                max_price,
+                price,
+            } => vec![
+                Integer(*pair_id),
+                Address(*controller),
+                Integer(*priority_fee),
+                Integer(*fee),
+                Integer(*vol),
+                Integer(*dur),
+                Integer(*jit),
+                Integer(*max_price),
+                Integer(*price),
+            ],
+            Opcode::Swap {
+                use_max,
+                pool_id,
+                amount_0,
+                amount_1,
+                sell_asset,
+            } => vec![
+                Integer(*use_max),
+                Integer(*pool_id),
+                Integer(*amount_0),
+                Integer(*amount_1),
+                Integer(*sell_asset),
+            ],
+            Opcode::Claim {
+                pool_id,
+                fee_0,
+                fee_1,
+            } => vec![Integer(*pool_id), Integer(*fee_0), Integer(*fee_1)],
+        };
+
+        self.instruction()
+            .parameters
+            .iter()
+            .zip(arguments)
+            .collect()
+    }
+}
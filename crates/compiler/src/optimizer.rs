@@ -0,0 +1,439 @@
+//! # Optimizer
+//!
+//! Peephole optimizations over an expanded program, run before code generation.
+//! Consecutive `allocate`s, or `deallocate`s, of exact amounts to the same pool are merged into a single instruction,
+//! and `unknown` instructions, which encode to nothing, are removed.
+//! An optimized program leaves the same liquidity and balances as the original, and reverts where it does.
+//!
+//! With [`Options::net_liquidity`], an `allocate` is also merged with a `deallocate` from the same pool after it,
+//! and both are removed when they cancel out. The result may no longer revert where the `allocate` lacked the balance
+//! to pay for it, so this is opt in.
+
+use crate::{
+    assembler::{Body, Expression},
+    opcode::{Argument, Opcode, U256},
+    spec,
+    token::Spanned,
+};
+
+/// Optimizations to run in addition to the ones that never change what a program does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Merge an `allocate` with a `deallocate` from the same pool right after it, removing both if they cancel out.
+    pub net_liquidity: bool,
+}
+
+/// Optimize an expanded program.
+/// A merged instruction keeps the span of the first instruction it replaces.
+///
+/// ```
+/// use compiler::{
+///     assembler::{Body, Expression},
+///     builder::{Pool, Program},
+///     opcode::{Address, Opcode, U256},
+///     optimizer::{self, Options},
+///     simulator::State,
+///     token::{Span, Spanned},
+/// };
+///
+/// let (weth, usdc) = (Address::repeat_byte(1), Address::repeat_byte(2));
+/// let pool = Pool {
+///     pair_id: U256::zero(),
+///     controller: Address::zero(),
+///     priority_fee: U256::zero(),
+///     fee: U256::from(30),
+///     vol: U256::from(10000),
+///     dur: U256::from(365),
+///     jit: U256::zero(),
+///     max_price: U256::exp10(21) * 2,
+///     price: U256::exp10(21) * 3 / 2,
+/// };
+///
+/// let body: Body = Program::new()
+///     .create_pair(weth, usdc)
+///     .create_pool(pool)
+///     .allocate(false, 0, U256::exp10(18) * 5)
+///     .allocate(false, 0, U256::exp10(18) * 5)
+///     .deallocate(false, 0, U256::exp10(18) * 4)
+///     .build()
+///     .unwrap()
+///     .into_iter()
+///     .map(|opcode| Spanned::new(Expression::Opcode(opcode), Span::default()))
+///     .collect();
+///
+/// let opcodes = |body: Body| -> Vec<Opcode> {
+///     body.into_iter()
+///         .filter_map(|expression| match expression.node {
+///             Expression::Opcode(opcode) => Some(opcode),
+///             Expression::Invocation(_) => None,
+///         })
+///         .collect()
+/// };
+///
+/// let original = opcodes(body.clone());
+/// let optimized = opcodes(optimizer::optimize(body, Options::default()));
+/// assert_eq!(optimized.len(), 4);
+///
+/// // Both programs leave the same balances.
+/// let mut state = State::new();
+/// state.balances.insert(weth, 1e21);
+/// state.balances.insert(usdc, 1e24);
+///
+/// let (original, optimized) = (state.simulate(&original), state.simulate(&optimized));
+/// assert!(original.revert.is_none() && optimized.revert.is_none());
+///
+/// for (token, balance) in &original.state.balances {
+///     assert!(*balance < state.balances[token]);
+///     assert!((balance - optimized.state.balances[token]).abs() <= balance.abs() * 1e-12);
+/// }
+/// ```
+pub fn optimize(body: Body, options: Options) -> Body {
+    optimize_indexed(&body, options)
+        .into_iter()
+        .map(|(index, node)| Spanned::new(node, body[index].span))
+        .collect()
+}
+
+/// Optimize an expanded program, pairing each remaining expression with the index of the expression it replaces,
+/// which is the first of any it was merged from.
+pub fn optimize_indexed<'a>(body: &Body<'a>, options: Options) -> Vec<(usize, Expression<'a>)> {
+    let mut optimized: Vec<(usize, Expression)> = Vec::with_capacity(body.len());
+
+    for (index, expression) in body.iter().enumerate() {
+        let Expression::Opcode(opcode) = &expression.node else {
+            optimized.push((index, expression.node.clone()));
+            continue;
+        };
+
+        if let Opcode::Unknown = opcode {
+            continue;
+        }
+
+        let merged = match optimized.last() {
+            Some((_, Expression::Opcode(previous))) => merge(previous, opcode, options),
+            _ => None,
+        };
+
+        // Replacing or removing the previous instruction may let it merge with the next one in turn.
+        match merged {
+            Some(Some(opcode)) => optimized.last_mut().unwrap().1 = Expression::Opcode(opcode),
+            Some(None) => {
+                optimized.pop();
+            }
+            None => optimized.push((index, expression.node.clone())),
+        }
+    }
+
+    optimized
+}
+
+/// Merge two consecutive instructions.
+/// Returns `None` if they cannot be merged, and `Some(None)` if they cancel out.
+fn merge(first: &Opcode, second: &Opcode, options: Options) -> Option<Option<Opcode>> {
+    let (allocated, pool_id, first_amount) = liquidity(first)?;
+    let (allocating, second_pool_id, second_amount) = liquidity(second)?;
+
+    if pool_id != second_pool_id {
+        return None;
+    }
+
+    // An allocation after a deallocation could fail where the deallocation succeeded, so they are kept apart.
+    let (allocate, amount) = match (allocated, allocating) {
+        (true, true) => (true, first_amount.checked_add(second_amount)?),
+        (false, false) => (false, first_amount.checked_add(second_amount)?),
+        (true, false) if !options.net_liquidity => return None,
+        (true, false) if first_amount >= second_amount => (true, first_amount - second_amount),
+        (true, false) => (false, second_amount - first_amount),
+        (false, true) => return None,
+    };
+
+    if amount.is_zero() {
+        return Some(None);
+    }
+
+    // The merged amount must still be encodable.
+    let parameter = spec::parameter("deltaLiquidity").unwrap();
+    parameter.validate(&Argument::Integer(amount)).ok()?;
+
+    let (use_max, delta_liquidity) = (U256::zero(), amount);
+
+    Some(Some(if allocate {
+        Opcode::Allocate {
+            use_max,
+            pool_id,
+            delta_liquidity,
+        }
+    } else {
+        Opcode::Deallocate {
+            use_max,
+            pool_id,
+            delta_liquidity,
+        }
+    }))
+}
+
+/// Whether an instruction allocates or deallocates, its pool and its amount,
+/// if it is an `allocate` or `deallocate` of an exact amount.
+fn liquidity(opcode: &Opcode) -> Option<(bool, U256, U256)> {
+    match opcode {
+        Opcode::Allocate {
+            use_max,
+            pool_id,
+            delta_liquidity,
+        } if use_max.is_zero() => Some((true, *pool_id, *delta_liquidity)),
+        Opcode::Deallocate {
+            use_max,
+            pool_id,
+            delta_liquidity,
+        } if use_max.is_zero() => Some((false, *pool_id, *delta_liquidity)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::{Pool, Program},
+        opcode::Address,
+        simulator::State,
+        token::Span,
+    };
+
+    const NET: Options = Options {
+        net_liquidity: true,
+    };
+
+    const TOKEN_0: Address = Address::repeat_byte(1);
+    const TOKEN_1: Address = Address::repeat_byte(2);
+
+    /// Ids of the two pools [`setup`] creates.
+    const FIRST: u64 = 1 << 40 | 1;
+    const SECOND: u64 = 1 << 40 | 2;
+
+    /// A pair with two pools.
+    fn setup() -> Program {
+        let pool = Pool {
+            pair_id: U256::zero(),
+            controller: Address::zero(),
+            priority_fee: U256::zero(),
+            fee: U256::from(30),
+            vol: U256::from(10000),
+            dur: U256::from(365),
+            jit: U256::zero(),
+            max_price: U256::exp10(18) * 2,
+            price: U256::exp10(18),
+        };
+
+        Program::new()
+            .create_pair(TOKEN_0, TOKEN_1)
+            .create_pool(pool.clone())
+            .create_pool(pool)
+    }
+
+    fn optimized(program: &[Opcode], options: Options) -> Vec<Opcode> {
+        let body = program
+            .iter()
+            .map(|opcode| Spanned::new(Expression::Opcode(opcode.clone()), Span::default()))
+            .collect();
+
+        optimize(body, options)
+            .into_iter()
+            .map(|expression| match expression.node {
+                Expression::Opcode(opcode) => opcode,
+                Expression::Invocation(_) => unreachable!(),
+            })
+            .collect()
+    }
+
+    /// Optimize `program`, checking that the result leaves the same state as the original, from `state`.
+    fn check(state: &State, program: &[Opcode], options: Options) -> Vec<Opcode> {
+        let optimized = optimized(program, options);
+        let (before, after) = (state.simulate(program), state.simulate(&optimized));
+
+        assert_eq!(before.revert.is_some(), after.revert.is_some());
+
+        for (token, balance) in &before.state.balances {
+            let other = after.state.balances[token];
+            assert!(
+                (balance - other).abs() <= balance.abs() * 1e-12,
+                "{balance} != {other}"
+            );
+        }
+
+        for (pool_id, position) in &before.state.positions {
+            let other = after
+                .state
+                .positions
+                .get(pool_id)
+                .map_or(0.0, |position| position.liquidity);
+            assert!((position.liquidity - other).abs() <= position.liquidity * 1e-12);
+        }
+
+        optimized
+    }
+
+    fn funded() -> State {
+        let mut state = State::new();
+        state.balances.insert(TOKEN_0, 1e30);
+        state.balances.insert(TOKEN_1, 1e30);
+        state
+    }
+
+    /// A funded state, after [`setup`], with a position of 10 in the first pool.
+    fn positioned() -> State {
+        let program = setup().allocate(false, FIRST, 10).build().unwrap();
+        let simulation = funded().simulate(&program);
+
+        assert!(simulation.revert.is_none());
+        simulation.state
+    }
+
+    /// The instructions after the setup.
+    fn tail(opcodes: &[Opcode]) -> &[Opcode] {
+        &opcodes[3..]
+    }
+
+    fn allocate(use_max: bool, pool_id: u64, amount: u64) -> Opcode {
+        Program::new()
+            .allocate(use_max, pool_id, amount)
+            .build()
+            .unwrap()
+            .remove(0)
+    }
+
+    fn deallocate(use_max: bool, pool_id: u64, amount: u64) -> Opcode {
+        Program::new()
+            .deallocate(use_max, pool_id, amount)
+            .build()
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test]
+    fn merges_allocations() {
+        let program = setup()
+            .allocate(false, FIRST, 5)
+            .allocate(false, FIRST, 7)
+            .deallocate(false, FIRST, 2)
+            .deallocate(false, FIRST, 3)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            tail(&check(&funded(), &program, Options::default())),
+            [allocate(false, FIRST, 12), deallocate(false, FIRST, 5)]
+        );
+    }
+
+    #[test]
+    fn cancels_out() {
+        let program = setup()
+            .allocate(false, FIRST, 5)
+            .deallocate(false, FIRST, 5)
+            .build()
+            .unwrap();
+
+        assert!(tail(&check(&funded(), &program, NET)).is_empty());
+        assert_eq!(
+            tail(&check(&funded(), &program, Options::default())),
+            tail(&program)
+        );
+    }
+
+    #[test]
+    fn nets_liquidity() {
+        // A deallocate larger than the allocate before it becomes a deallocate of the difference.
+        let state = positioned();
+        let program = [allocate(false, FIRST, 5), deallocate(false, FIRST, 8)];
+
+        assert_eq!(check(&state, &program, NET), [deallocate(false, FIRST, 3)]);
+        assert_eq!(check(&state, &program, Options::default()), program);
+    }
+
+    #[test]
+    fn keeps_reverts() {
+        // Without balances, the allocate reverts, and the merged program has to as well.
+        let program = setup()
+            .allocate(false, FIRST, 5)
+            .deallocate(false, FIRST, 5)
+            .build()
+            .unwrap();
+
+        let kept = check(&State::new(), &program, Options::default());
+        assert!(State::new().simulate(&kept).revert.is_some());
+
+        // Netting liquidity is opt in because it does not.
+        let netted = optimized(&program, NET);
+        assert!(State::new().simulate(&netted).revert.is_none());
+    }
+
+    #[test]
+    fn removes_unknown() {
+        let program = setup()
+            .push(Opcode::Unknown)
+            .allocate(false, FIRST, 5)
+            .push(Opcode::Unknown)
+            .allocate(false, FIRST, 5)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            tail(&check(&funded(), &program, Options::default())),
+            [allocate(false, FIRST, 10)]
+        );
+    }
+
+    #[test]
+    fn keeps_use_max() {
+        let program = setup()
+            .allocate(false, FIRST, 5)
+            .allocate(true, FIRST, 0)
+            .deallocate(false, FIRST, 5)
+            .deallocate(true, FIRST, 0)
+            .deallocate(false, FIRST, 0)
+            .build()
+            .unwrap();
+
+        assert_eq!(tail(&check(&funded(), &program, NET)), tail(&program));
+    }
+
+    #[test]
+    fn keeps_pools_apart() {
+        let program = setup()
+            .allocate(false, FIRST, 5)
+            .allocate(false, SECOND, 5)
+            .deallocate(false, FIRST, 5)
+            .build()
+            .unwrap();
+
+        assert_eq!(tail(&check(&funded(), &program, NET)), tail(&program));
+    }
+
+    #[test]
+    fn keeps_deallocate_before_allocate() {
+        let program = [deallocate(false, FIRST, 5), allocate(false, FIRST, 5)];
+
+        assert_eq!(check(&positioned(), &program, NET), program);
+    }
+
+    #[test]
+    fn keeps_unencodable_sums() {
+        // The sum is 2^128, which is not a 128 bit base times a power of ten.
+        let max = U256::from(u128::MAX);
+        let program = setup()
+            .allocate(false, FIRST, max)
+            .allocate(false, FIRST, 1)
+            .build()
+            .unwrap();
+
+        assert!(spec::parameter("deltaLiquidity")
+            .unwrap()
+            .validate(&Argument::Integer(max + 1))
+            .is_err());
+        assert_eq!(
+            tail(&optimized(&program, Options::default())),
+            tail(&program)
+        );
+    }
+}
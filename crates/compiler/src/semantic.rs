@@ -0,0 +1,200 @@
+//! # Semantic
+//!
+//! Checks the pairs and pools an expanded program refers to.
+//! Ids are assigned on chain, so a program can only refer to a pair or pool it creates through the id `0`,
+//! which the FVM resolves to the last one created in the same transaction.
+//! Any other id must be declared to exist with `extern pair <id>` or `extern pool <id>`.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    assembler::Expression,
+    error::Warning,
+    opcode::{Opcode, U256},
+    token::{Span, Spanned},
+};
+
+/// Pairs and pools declared to exist before a program runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Externs {
+    pub pairs: BTreeSet<U256>,
+    pub pools: BTreeSet<U256>,
+}
+
+/// Check every pair and pool id in an expanded program, returning a warning for each reference
+/// that cannot be resolved.
+pub fn analyze(expressions: &[Spanned<Expression>], externs: &Externs) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    let (mut pairs, mut pools) = (false, false);
+
+    for expression in expressions {
+        let Expression::Opcode(opcode) = &expression.node else {
+            continue;
+        };
+
+        let span = expression.span;
+
+        match opcode {
+            Opcode::CreatePair { .. } => pairs = true,
+            Opcode::CreatePool { pair_id, .. } => {
+                warnings.extend(check("pair", *pair_id, pairs, &externs.pairs, span));
+                pools = true;
+            }
+            Opcode::Allocate { pool_id, .. }
+            | Opcode::Deallocate { pool_id, .. }
+            | Opcode::Swap { pool_id, .. }
+            | Opcode::Claim { pool_id, .. } => {
+                warnings.extend(check("pool", *pool_id, pools, &externs.pools, span));
+            }
+            Opcode::Unknown | Opcode::Jump => {}
+        }
+    }
+
+    warnings
+}
+
+/// Check a reference to a pair or pool, given whether one was created earlier in the program.
+fn check(
+    kind: &str,
+    id: U256,
+    created: bool,
+    declared: &BTreeSet<U256>,
+    span: Span,
+) -> Option<Warning> {
+    if id.is_zero() && !created {
+        return Some(Warning::new(
+            format!(
+                "{kind} id 0 refers to a {kind} created earlier in the program, but there is none"
+            ),
+            span,
+        ));
+    }
+
+    if !id.is_zero() && !declared.contains(&id) {
+        return Some(Warning::new(
+            format!(
+                "unknown {kind} {id}, declare it with `extern {kind} {id}` if it exists on chain"
+            ),
+            span,
+        ));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, token::Token};
+
+    const CREATE_PAIR: &str = "createPair: token0: 0x0000000000000000000000000000000000000001 token1: 0x0000000000000000000000000000000000000002";
+
+    fn pool(pair_id: u64) -> String {
+        format!(
+            "createPool: pairId: {pair_id} controller: 0x0000000000000000000000000000000000000000 \
+             priorityFee: 0 fee: 30 vol: 1000 dur: 365 jit: 0 maxPrice: 2 price: 1"
+        )
+    }
+
+    fn swap(pool_id: u64) -> String {
+        format!("swap: useMax: 0 poolId: {pool_id} amount0: 1 amount1: 0 sellAsset: 1")
+    }
+
+    /// The warnings of the `main` macro of `source`, with the source they point at.
+    fn warnings(source: &str) -> Vec<(String, &str)> {
+        let assembler = Assembler::new(Token::lex(source));
+        let macros = assembler.parse_macros().unwrap();
+        let body = Assembler::expand(&macros, "main").unwrap();
+
+        analyze(&body, &assembler.externs())
+            .into_iter()
+            .map(|warning| {
+                let text = &source[warning.span.start..warning.span.end];
+                (warning.message, text.split(':').next().unwrap())
+            })
+            .collect()
+    }
+
+    fn program(externs: &str, body: &[&str]) -> String {
+        format!("{externs}\nmacro main {{\n{}\n}}", body.join("\n"))
+    }
+
+    #[test]
+    fn last_created() {
+        let source = program("", &[CREATE_PAIR, &pool(0), &swap(0)]);
+
+        assert!(warnings(&source).is_empty());
+    }
+
+    #[test]
+    fn last_created_without_create() {
+        let source = program("", &[&pool(0), &swap(0)]);
+
+        // The pool counts as created, so only the missing pair is reported.
+        assert_eq!(
+            warnings(&source),
+            [(
+                "pair id 0 refers to a pair created earlier in the program, but there is none"
+                    .to_string(),
+                "createPool"
+            )]
+        );
+    }
+
+    #[test]
+    fn created_after_use() {
+        let source = program("", &[&swap(0), CREATE_PAIR, &pool(0)]);
+
+        assert_eq!(
+            warnings(&source),
+            [(
+                "pool id 0 refers to a pool created earlier in the program, but there is none"
+                    .to_string(),
+                "swap"
+            )]
+        );
+    }
+
+    #[test]
+    fn declared() {
+        let source = program("extern pair 7\nextern pool 34", &[&pool(7), &swap(34)]);
+
+        assert!(warnings(&source).is_empty());
+    }
+
+    #[test]
+    fn undeclared() {
+        // A pair id is not a pool id, and vice versa.
+        let source = program("extern pool 7\nextern pair 34", &[&pool(7), &swap(34)]);
+
+        assert_eq!(
+            warnings(&source),
+            [
+                (
+                    "unknown pair 7, declare it with `extern pair 7` if it exists on chain"
+                        .to_string(),
+                    "createPool"
+                ),
+                (
+                    "unknown pool 34, declare it with `extern pool 34` if it exists on chain"
+                        .to_string(),
+                    "swap"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_pair_after_create() {
+        // Creating a pair only makes id 0 resolve, not other ids.
+        let source = program("", &[CREATE_PAIR, &pool(1)]);
+
+        assert_eq!(
+            warnings(&source),
+            [(
+                "unknown pair 1, declare it with `extern pair 1` if it exists on chain".to_string(),
+                "createPool"
+            )]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! # Serialize
+//!
+//! Serde support for the AST and opcodes, enabled by the `serde` feature.
+//! Integers are written as decimal strings and addresses as checksummed hex, so serialized programs read like source.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::token::{Span, Spanned};
+
+/// Spans only make sense against the source they were parsed from, so only the node is serialized.
+impl<T: Serialize> Serialize for Spanned<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.node.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Spanned<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(|node| Spanned::new(node, Span::default()))
+    }
+}
+
+/// [`U256`](crate::opcode::U256) as a decimal string.
+/// Deserialization also accepts `0x` prefixed hex strings and plain JSON integers.
+pub mod u256 {
+    use std::fmt;
+
+    use serde::{
+        de::{Error, Visitor},
+        Deserializer, Serializer,
+    };
+
+    use crate::opcode::U256;
+
+    /// How to write an integer that does not fit in 64 bits, which JSON and YAML numbers cannot hold exactly.
+    pub const LARGE_INTEGERS: &str =
+        "write integers that do not fit in 64 bits as strings, e.g. \"100000000000000000000\"";
+
+    struct U256Visitor;
+
+    impl<'de> Visitor<'de> for U256Visitor {
+        type Value = U256;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "an unsigned integer, or a decimal or `0x` prefixed hex string"
+            )
+        }
+
+        fn visit_u64<E: Error>(self, value: u64) -> Result<U256, E> {
+            Ok(U256::from(value))
+        }
+
+        fn visit_u128<E: Error>(self, value: u128) -> Result<U256, E> {
+            Ok(U256::from(value))
+        }
+
+        fn visit_i64<E: Error>(self, value: i64) -> Result<U256, E> {
+            Err(E::custom(format!("`{value}` is negative")))
+        }
+
+        // JSON integers that do not fit in 64 bits are read as floats, which cannot hold them exactly.
+        fn visit_f64<E: Error>(self, value: f64) -> Result<U256, E> {
+            Err(E::custom(format!(
+                "`{value}` is not an integer that fits in 64 bits; {LARGE_INTEGERS}"
+            )))
+        }
+
+        fn visit_str<E: Error>(self, value: &str) -> Result<U256, E> {
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => U256::from_str_radix(hex, 16).ok(),
+                None => U256::from_dec_str(value).ok(),
+            };
+
+            parsed.ok_or_else(|| E::custom(format!("`{value}` is not a valid unsigned integer")))
+        }
+    }
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        deserializer.deserialize_any(U256Visitor)
+    }
+}
+
+/// Values of omitted parameters, read from the [`spec`](crate::spec), so that documents may omit the same parameters as source.
+pub mod defaults {
+    use crate::{opcode::U256, spec};
+
+    fn default(name: &str) -> U256 {
+        spec::parameter(name)
+            .and_then(|parameter| parameter.default)
+            .and_then(|default| U256::from_dec_str(default).ok())
+            .unwrap_or_else(|| panic!("`{name}` has no integer default"))
+    }
+
+    pub fn use_max() -> U256 {
+        default("useMax")
+    }
+
+    pub fn priority_fee() -> U256 {
+        default("priorityFee")
+    }
+}
+
+/// [`Address`](crate::opcode::Address) as an EIP-55 checksummed hex string.
+pub mod address {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::opcode::Address;
+
+    pub fn serialize<S: Serializer>(value: &Address, serializer: S) -> Result<S::Ok, S::Error> {
+        let address = ethers::types::Address::from(value.0);
+        serializer.serialize_str(&ethers::utils::to_checksum(&address, None))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        let value = String::deserialize(deserializer)?;
+
+        value
+            .parse::<Address>()
+            .map_err(|_| D::Error::custom(format!("`{value}` is not a valid address")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::{
+        assembler::{Assembler, Expression, Macro},
+        opcode::{Address, Opcode, U256},
+        token::Token,
+    };
+
+    fn round_trip(opcode: &Opcode) -> Value {
+        let value = serde_json::to_value(opcode).unwrap();
+        assert_eq!(
+            &serde_json::from_value::<Opcode>(value.clone()).unwrap(),
+            opcode
+        );
+
+        value
+    }
+
+    #[test]
+    fn checksummed_addresses() {
+        let token_0 = "0xdab0a5d15ff6b75e69256c29609d2fc3ebbfeb04"
+            .parse()
+            .unwrap();
+        let opcode = Opcode::CreatePair {
+            token_0,
+            token_1: Address::zero(),
+        };
+
+        assert_eq!(
+            round_trip(&opcode),
+            json!({
+                "op": "createPair",
+                "token0": "0xdAB0A5d15ff6B75E69256c29609d2Fc3eBbFEb04",
+                "token1": "0x0000000000000000000000000000000000000000",
+            })
+        );
+
+        let error = serde_json::from_value::<Opcode>(json!({
+            "op": "createPair",
+            "token0": "0xdab0",
+            "token1": "0x0000000000000000000000000000000000000000",
+        }))
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("`0xdab0` is not a valid address"));
+    }
+
+    #[test]
+    fn decimal_integers() {
+        let opcode = Opcode::Allocate {
+            use_max: U256::one(),
+            pool_id: U256::from(34),
+            delta_liquidity: U256::exp10(30),
+        };
+
+        assert_eq!(
+            round_trip(&opcode),
+            json!({
+                "op": "allocate",
+                "useMax": "1",
+                "poolId": "34",
+                "deltaLiquidity": "1000000000000000000000000000000",
+            })
+        );
+
+        // Hex strings and plain integers are read as well.
+        assert_eq!(
+            serde_json::from_value::<Opcode>(json!({
+                "op": "allocate",
+                "useMax": 1,
+                "poolId": "0x22",
+                "deltaLiquidity": "1000000000000000000000000000000",
+            }))
+            .unwrap(),
+            opcode
+        );
+
+        for invalid in [json!(-1), json!(1e30), json!("ten")] {
+            assert!(serde_json::from_value::<Opcode>(json!({
+                "op": "allocate",
+                "poolId": invalid,
+                "deltaLiquidity": "1",
+            }))
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(
+            serde_json::from_value::<Opcode>(json!({
+                "op": "swap",
+                "poolId": "34",
+                "amount0": "10",
+                "amount1": "20",
+                "sellAsset": "1",
+            }))
+            .unwrap(),
+            Opcode::Swap {
+                use_max: super::defaults::use_max(),
+                pool_id: U256::from(34),
+                amount_0: U256::from(10),
+                amount_1: U256::from(20),
+                sell_asset: U256::one(),
+            }
+        );
+
+        let pool = serde_json::from_value::<Opcode>(json!({
+            "op": "createPool",
+            "pairId": "1",
+            "controller": "0x0000000000000000000000000000000000000000",
+            "fee": "30",
+            "vol": "10000",
+            "dur": "365",
+            "jit": "4",
+            "maxPrice": "2000",
+            "price": "1000",
+        }))
+        .unwrap();
+
+        assert!(
+            matches!(pool, Opcode::CreatePool { priority_fee, .. } if priority_fee == super::defaults::priority_fee())
+        );
+        assert_eq!(super::defaults::use_max(), U256::zero());
+        assert_eq!(super::defaults::priority_fee(), U256::zero());
+    }
+
+    #[test]
+    fn expressions_and_macros() {
+        let source = "pub macro main { provide claim: poolId: 34 fee0: 5 fee1: 6 }";
+        let macros = Assembler::new(Token::lex(source)).parse_macros().unwrap();
+        let main = &macros["main"];
+
+        // Spans are not serialized, since they only make sense against the source.
+        let value = serde_json::to_value(main).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "name": "main",
+                "entry": true,
+                "body": [
+                    { "invocation": "provide" },
+                    { "opcode": { "op": "claim", "poolId": "34", "fee0": "5", "fee1": "6" } },
+                ],
+            })
+        );
+
+        let json = value.to_string();
+        let deserialized = serde_json::from_str::<Macro>(&json).unwrap();
+        assert_eq!(deserialized.name, main.name);
+        assert_eq!(deserialized.entry, main.entry);
+        assert_eq!(
+            deserialized
+                .body
+                .into_iter()
+                .map(|expression| expression.node)
+                .collect::<Vec<_>>(),
+            main.body
+                .iter()
+                .map(|expression| expression.node.clone())
+                .collect::<Vec<Expression>>()
+        );
+    }
+}
@@ -0,0 +1,1138 @@
+//! # Simulator
+//!
+//! Executes opcodes against an in-memory model of the FVM, to see what a program does before it is sent.
+//! Pools follow the RMM-01 trading function of the yellow paper, with reserves per unit of liquidity
+//! `x = 1 - Φ(d1)` and `y = K Φ(d1 - σ√τ) + k`, where `K` is the strike (`maxPrice`),
+//! `σ` the volatility (`vol`, in basis points) and `τ` the duration (`dur`, in days).
+//! Amounts are modelled as `f64`, in the raw units they are encoded in; prices are WAD fixed point numbers.
+//!
+//! Floats only represent integers up to [`EXACT`] exactly, so larger amounts, such as most token amounts
+//! with 18 decimals, are rounded to about 16 significant digits. Balances and reserves are approximations
+//! of what the FVM computes, and a check that is decided by the last few units of an amount may not agree
+//! with it. [`Simulation::is_exact`] tells whether every amount of a simulation was exact.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    disassembler,
+    opcode::{Address, Opcode, U256},
+};
+
+/// Scale of fixed point prices.
+const WAD: f64 = 1e18;
+
+/// Scale of fees and volatilities, which are given in basis points.
+const BASIS_POINTS: f64 = 1e4;
+
+/// The largest amount, `2^53`, up to which every integer is represented exactly.
+pub const EXACT: f64 = 9_007_199_254_740_992.0;
+
+/// A pair of tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pair {
+    pub token_0: Address,
+    pub token_1: Address,
+}
+
+/// A pool of a pair, trading along an RMM-01 curve.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pool {
+    pub pair_id: u32,
+    pub controller: Address,
+    /// Swap fee, in basis points.
+    pub fee: f64,
+    /// Swap fee paid by the controller, in basis points.
+    pub priority_fee: f64,
+    /// Strike price of the curve, given as `maxPrice`.
+    pub strike: f64,
+    /// Implied volatility, as a fraction.
+    pub volatility: f64,
+    /// Time to maturity, in years.
+    pub duration: f64,
+    /// Total liquidity of every position.
+    pub liquidity: f64,
+    /// Reserves of `token0` per unit of liquidity.
+    pub x: f64,
+    /// Reserves of `token1` per unit of liquidity.
+    pub y: f64,
+    /// Fees of `token0` earned per unit of liquidity since the pool was created.
+    pub fee_growth_0: f64,
+    /// Fees of `token1` earned per unit of liquidity since the pool was created.
+    pub fee_growth_1: f64,
+}
+
+/// Parameters of a new pool, in the units `createPool` takes them in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Parameters {
+    pub controller: Address,
+    /// Swap fee paid by the controller, in basis points.
+    pub priority_fee: f64,
+    /// Swap fee, in basis points.
+    pub fee: f64,
+    /// Implied volatility, in basis points.
+    pub vol: f64,
+    /// Time to maturity, in days.
+    pub dur: f64,
+    /// Strike price, as a WAD.
+    pub max_price: f64,
+    /// Initial spot price, as a WAD.
+    pub price: f64,
+}
+
+/// A position of the sender in a pool.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Position {
+    pub liquidity: f64,
+    /// Fee growth of the pool when fees were last credited to the position.
+    pub checkpoint_0: f64,
+    pub checkpoint_1: f64,
+    /// Fees credited to the position that have not been claimed.
+    pub owed_0: f64,
+    pub owed_1: f64,
+}
+
+/// The state of the FVM and of the account sending a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct State {
+    /// Every pair, by id.
+    pub pairs: BTreeMap<u32, Pair>,
+    /// Every pool, by id.
+    pub pools: BTreeMap<u64, Pool>,
+    /// Token balances of the sender, which are rounded above [`EXACT`].
+    pub balances: BTreeMap<Address, f64>,
+    /// Positions of the sender, by pool id.
+    pub positions: BTreeMap<u64, Position>,
+    /// The last pair created, which a pair id of `0` refers to.
+    pub last_pair: Option<u32>,
+    /// The last pool created, which a pool id of `0` refers to.
+    pub last_pool: Option<u64>,
+}
+
+/// A change an instruction made to the state. Amounts are rounded above [`EXACT`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delta {
+    /// A pair was created.
+    Pair { pair_id: u32 },
+    /// A pool was created.
+    Pool { pool_id: u64 },
+    /// The balance of the sender changed.
+    Balance { token: Address, amount: f64 },
+    /// The liquidity of the sender's position changed.
+    Liquidity { pool_id: u64, amount: f64 },
+    /// The reserves of a pool changed.
+    Reserve {
+        pool_id: u64,
+        token: Address,
+        amount: f64,
+    },
+    /// The spot price of a pool moved.
+    Price { pool_id: u64, from: f64, to: f64 },
+}
+
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delta::Pair { pair_id } => write!(f, "created pair {pair_id}"),
+            Delta::Pool { pool_id } => write!(f, "created pool {pool_id}"),
+            Delta::Balance { token, amount } => write!(f, "balance of {token:?} {amount:+}"),
+            Delta::Liquidity { pool_id, amount } => {
+                write!(f, "liquidity in pool {pool_id} {amount:+}")
+            }
+            Delta::Reserve {
+                pool_id,
+                token,
+                amount,
+            } => write!(f, "reserve of {token:?} in pool {pool_id} {amount:+}"),
+            Delta::Price { pool_id, from, to } => {
+                write!(f, "price of pool {pool_id} {from} -> {to}")
+            }
+        }
+    }
+}
+
+/// An executed instruction, and the changes it made.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub opcode: Opcode,
+    pub deltas: Vec<Delta>,
+}
+
+/// An instruction that reverted, which reverts the whole program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revert {
+    /// Index of the instruction.
+    pub index: usize,
+    pub reason: String,
+}
+
+/// The result of simulating a program.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    /// Every instruction executed, up to and including the one that reverted.
+    pub steps: Vec<Step>,
+    pub revert: Option<Revert>,
+    /// The state after the program, which is the initial state if it reverted.
+    pub state: State,
+}
+
+impl Simulation {
+    /// Whether every balance and every amount moved was below [`EXACT`], so that none were rounded.
+    /// Amounts computed along the curve are approximations either way.
+    pub fn is_exact(&self) -> bool {
+        let exact = |amount: f64| amount.abs() <= EXACT;
+
+        self.state.balances.values().all(|balance| exact(*balance))
+            && self
+                .steps
+                .iter()
+                .flat_map(|step| &step.deltas)
+                .all(|delta| match delta {
+                    Delta::Balance { amount, .. }
+                    | Delta::Liquidity { amount, .. }
+                    | Delta::Reserve { amount, .. } => exact(*amount),
+                    Delta::Pair { .. } | Delta::Pool { .. } | Delta::Price { .. } => true,
+                })
+    }
+}
+
+impl Pool {
+    /// A pool without liquidity, whose reserves are set for its initial price.
+    pub fn new(pair_id: u32, parameters: Parameters) -> Result<Self, String> {
+        let pool = Pool {
+            pair_id,
+            controller: parameters.controller,
+            fee: parameters.fee,
+            priority_fee: parameters.priority_fee,
+            strike: parameters.max_price / WAD,
+            volatility: parameters.vol / BASIS_POINTS,
+            duration: parameters.dur / 365.0,
+            liquidity: 0.0,
+            x: 0.0,
+            y: 0.0,
+            fee_growth_0: 0.0,
+            fee_growth_1: 0.0,
+        };
+
+        if pool.fee > BASIS_POINTS || pool.priority_fee > pool.fee {
+            return Err(format!(
+                "fee of {} and priority fee of {} are out of bounds",
+                pool.fee, pool.priority_fee
+            ));
+        }
+
+        if pool.volatility <= 0.0 || pool.duration <= 0.0 {
+            return Err("volatility and duration must be positive".to_string());
+        }
+
+        if pool.strike <= 0.0 || parameters.price <= 0.0 {
+            return Err("price and max price must be positive".to_string());
+        }
+
+        let x = pool.x_at(parameters.price / WAD);
+
+        Ok(Pool {
+            x,
+            y: pool.y_of(x),
+            ..pool
+        })
+    }
+
+    /// `σ√τ`, the width of the curve.
+    fn width(&self) -> f64 {
+        self.volatility * self.duration.sqrt()
+    }
+
+    /// Reserves of `token0` per unit of liquidity at a spot price.
+    fn x_at(&self, price: f64) -> f64 {
+        let width = self.width();
+        let d1 = ((price / self.strike).ln() + width * width / 2.0) / width;
+
+        1.0 - cdf(d1)
+    }
+
+    /// Reserves of `token1` per unit of liquidity on the curve, for reserves of `token0`.
+    fn y_of(&self, x: f64) -> f64 {
+        self.strike * cdf(inverse_cdf(1.0 - x) - self.width())
+    }
+
+    /// Reserves of `token0` per unit of liquidity on the curve, for reserves of `token1`.
+    fn x_of(&self, y: f64) -> f64 {
+        1.0 - cdf(inverse_cdf(y / self.strike) + self.width())
+    }
+
+    /// The invariant `k`, which is positive once fees have been paid into the pool.
+    fn invariant(&self) -> f64 {
+        self.y - self.y_of(self.x)
+    }
+
+    /// The spot price, `K exp(Φ⁻¹(1 - x) σ√τ - σ²τ / 2)`.
+    pub fn price(&self) -> f64 {
+        let width = self.width();
+
+        self.strike * (inverse_cdf(1.0 - self.x) * width - width * width / 2.0).exp()
+    }
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Simulate a program, stopping at the first instruction that reverts.
+    pub fn simulate(&self, opcodes: &[Opcode]) -> Simulation {
+        let mut state = self.clone();
+        let mut steps = Vec::new();
+
+        for (index, opcode) in opcodes.iter().enumerate() {
+            match state.execute(opcode) {
+                Ok(deltas) => steps.push(Step {
+                    opcode: opcode.clone(),
+                    deltas,
+                }),
+                Err(reason) => {
+                    steps.push(Step {
+                        opcode: opcode.clone(),
+                        deltas: Vec::new(),
+                    });
+
+                    return Simulation {
+                        steps,
+                        revert: Some(Revert { index, reason }),
+                        state: self.clone(),
+                    };
+                }
+            }
+        }
+
+        Simulation {
+            steps,
+            revert: None,
+            state,
+        }
+    }
+
+    /// Simulate a payload produced by [`Codegen::generate`](crate::codegen::Codegen::generate).
+    pub fn simulate_bytecode(&self, payload: &[u8]) -> Result<Simulation, String> {
+        let opcodes = disassembler::disassemble(payload)?
+            .into_iter()
+            .map(|decoded| decoded.opcode)
+            .collect::<Vec<_>>();
+
+        Ok(self.simulate(&opcodes))
+    }
+
+    /// Execute a single instruction, returning the changes it made.
+    /// If it reverts, the state may be partially updated.
+    pub fn execute(&mut self, opcode: &Opcode) -> Result<Vec<Delta>, String> {
+        match opcode {
+            Opcode::Unknown | Opcode::Jump => Ok(Vec::new()),
+            Opcode::CreatePair { token_0, token_1 } => self.create_pair(*token_0, *token_1),
+            Opcode::CreatePool {
+                pair_id,
+                controller,
+                priority_fee,
+                fee,
+                vol,
+                dur,
+                jit: _,
+                max_price,
+                price,
+            } => {
+                let pair_id = match integer(*pair_id, "pairId", 24)? as u32 {
+                    0 => self
+                        .last_pair
+                        .ok_or("pair id 0 refers to the last pair created, but none was")?,
+                    pair_id => pair_id,
+                };
+
+                if !self.pairs.contains_key(&pair_id) {
+                    return Err(format!("unknown pair {pair_id}"));
+                }
+
+                let pool = Pool::new(
+                    pair_id,
+                    Parameters {
+                        controller: *controller,
+                        priority_fee: float(*priority_fee),
+                        fee: float(*fee),
+                        vol: float(*vol),
+                        dur: float(*dur),
+                        max_price: float(*max_price),
+                        price: float(*price),
+                    },
+                )?;
+
+                // Pool ids are the pair id, whether the pool has a controller, and a nonce of the pair's pools.
+                let nonce = self
+                    .pools
+                    .values()
+                    .filter(|pool| pool.pair_id == pair_id)
+                    .count() as u64
+                    + 1;
+                let pool_id =
+                    u64::from(pair_id) << 40 | u64::from(!controller.is_zero()) << 32 | nonce;
+
+                self.pools.insert(pool_id, pool);
+                self.last_pool = Some(pool_id);
+
+                Ok(vec![Delta::Pool { pool_id }])
+            }
+            Opcode::Allocate {
+                use_max,
+                pool_id,
+                delta_liquidity,
+            } => {
+                let pool_id = self.pool_id(*pool_id)?;
+                let (pair, pool) = self.pool(pool_id)?;
+
+                let liquidity = if use_max.is_zero() {
+                    float(*delta_liquidity)
+                } else {
+                    // The most liquidity the sender's balances can pay for.
+                    let balance_0 = self.balance(pair.token_0) / pool.x;
+                    let balance_1 = self.balance(pair.token_1) / pool.y;
+                    balance_0.min(balance_1)
+                };
+
+                let (amount_0, amount_1) = (liquidity * pool.x, liquidity * pool.y);
+
+                self.credit(pool_id);
+                self.pay(pair.token_0, amount_0)?;
+                self.pay(pair.token_1, amount_1)?;
+
+                self.pools.get_mut(&pool_id).unwrap().liquidity += liquidity;
+                self.positions.get_mut(&pool_id).unwrap().liquidity += liquidity;
+
+                Ok(vec![
+                    Delta::Liquidity {
+                        pool_id,
+                        amount: liquidity,
+                    },
+                    Delta::Reserve {
+                        pool_id,
+                        token: pair.token_0,
+                        amount: amount_0,
+                    },
+                    Delta::Reserve {
+                        pool_id,
+                        token: pair.token_1,
+                        amount: amount_1,
+                    },
+                    Delta::Balance {
+                        token: pair.token_0,
+                        amount: -amount_0,
+                    },
+                    Delta::Balance {
+                        token: pair.token_1,
+                        amount: -amount_1,
+                    },
+                ])
+            }
+            Opcode::Deallocate {
+                use_max,
+                pool_id,
+                delta_liquidity,
+            } => {
+                let pool_id = self.pool_id(*pool_id)?;
+                let (pair, pool) = self.pool(pool_id)?;
+                let position = self.credit(pool_id).liquidity;
+
+                let liquidity = if use_max.is_zero() {
+                    float(*delta_liquidity)
+                } else {
+                    position
+                };
+
+                if liquidity > position {
+                    return Err(format!(
+                        "cannot remove {liquidity} liquidity from pool {pool_id}, the position holds {position}"
+                    ));
+                }
+
+                let (amount_0, amount_1) = (liquidity * pool.x, liquidity * pool.y);
+
+                self.pools.get_mut(&pool_id).unwrap().liquidity -= liquidity;
+                self.positions.get_mut(&pool_id).unwrap().liquidity -= liquidity;
+                *self.balances.entry(pair.token_0).or_default() += amount_0;
+                *self.balances.entry(pair.token_1).or_default() += amount_1;
+
+                Ok(vec![
+                    Delta::Liquidity {
+                        pool_id,
+                        amount: -liquidity,
+                    },
+                    Delta::Reserve {
+                        pool_id,
+                        token: pair.token_0,
+                        amount: -amount_0,
+                    },
+                    Delta::Reserve {
+                        pool_id,
+                        token: pair.token_1,
+                        amount: -amount_1,
+                    },
+                    Delta::Balance {
+                        token: pair.token_0,
+                        amount: amount_0,
+                    },
+                    Delta::Balance {
+                        token: pair.token_1,
+                        amount: amount_1,
+                    },
+                ])
+            }
+            Opcode::Swap {
+                use_max,
+                pool_id,
+                amount_0,
+                amount_1,
+                sell_asset,
+            } => {
+                let pool_id = self.pool_id(*pool_id)?;
+                let (pair, pool) = self.pool(pool_id)?;
+                let sell_asset = !sell_asset.is_zero();
+
+                // `amount0` is the input, and `amount1` the output, of whichever token is sold.
+                let (token_in, token_out) = match sell_asset {
+                    true => (pair.token_0, pair.token_1),
+                    false => (pair.token_1, pair.token_0),
+                };
+
+                let input = if use_max.is_zero() {
+                    float(*amount_0)
+                } else {
+                    self.balance(token_in)
+                };
+                let output = float(*amount_1);
+
+                if pool.liquidity == 0.0 {
+                    return Err(format!("pool {pool_id} has no liquidity"));
+                }
+
+                let fee = input * pool.fee / BASIS_POINTS;
+                let invariant = pool.invariant();
+                let per_liquidity = (input - fee) / pool.liquidity;
+
+                // Reserves per unit of liquidity after the input, and the most that can be taken out.
+                let (x, y, available) = if sell_asset {
+                    let x = pool.x + per_liquidity;
+                    let y = pool.y_of(x) + invariant;
+                    (x, y, (pool.y - y) * pool.liquidity)
+                } else {
+                    let y = pool.y + per_liquidity;
+                    let x = pool.x_of(y - invariant);
+                    (x, y, (pool.x - x) * pool.liquidity)
+                };
+
+                if !(x > 0.0 && x < 1.0 && y > 0.0 && y < pool.strike + invariant) {
+                    return Err(format!(
+                        "swapping {input} would move pool {pool_id} outside of its price bounds"
+                    ));
+                }
+
+                if output > available {
+                    return Err(format!(
+                        "swap asks for {output}, but pool {pool_id} can only pay {available} for {input}"
+                    ));
+                }
+
+                let from = pool.price();
+                self.pay(token_in, input)?;
+                *self.balances.entry(token_out).or_default() += output;
+
+                // The surplus of the output stays in the pool, increasing its invariant.
+                let pool = self.pools.get_mut(&pool_id).unwrap();
+                let surplus = (available - output) / pool.liquidity;
+
+                if sell_asset {
+                    pool.x = x;
+                    pool.y = y + surplus;
+                    pool.fee_growth_0 += fee / pool.liquidity;
+                } else {
+                    pool.x = x + surplus;
+                    pool.y = y;
+                    pool.fee_growth_1 += fee / pool.liquidity;
+                }
+
+                let to = pool.price();
+
+                Ok(vec![
+                    Delta::Balance {
+                        token: token_in,
+                        amount: -input,
+                    },
+                    Delta::Balance {
+                        token: token_out,
+                        amount: output,
+                    },
+                    Delta::Reserve {
+                        pool_id,
+                        token: token_in,
+                        amount: input - fee,
+                    },
+                    Delta::Reserve {
+                        pool_id,
+                        token: token_out,
+                        amount: -output,
+                    },
+                    Delta::Price { pool_id, from, to },
+                ])
+            }
+            Opcode::Claim {
+                pool_id,
+                fee_0,
+                fee_1,
+            } => {
+                let pool_id = self.pool_id(*pool_id)?;
+                let (pair, _) = self.pool(pool_id)?;
+                let position = self.credit(pool_id);
+
+                // Claims pay out what is owed, up to the amounts requested.
+                let amount_0 = float(*fee_0).min(position.owed_0);
+                let amount_1 = float(*fee_1).min(position.owed_1);
+                position.owed_0 -= amount_0;
+                position.owed_1 -= amount_1;
+
+                *self.balances.entry(pair.token_0).or_default() += amount_0;
+                *self.balances.entry(pair.token_1).or_default() += amount_1;
+
+                Ok(vec![
+                    Delta::Balance {
+                        token: pair.token_0,
+                        amount: amount_0,
+                    },
+                    Delta::Balance {
+                        token: pair.token_1,
+                        amount: amount_1,
+                    },
+                ])
+            }
+        }
+    }
+
+    fn create_pair(&mut self, token_0: Address, token_1: Address) -> Result<Vec<Delta>, String> {
+        if token_0 == token_1 {
+            return Err(format!("cannot create a pair of {token_0:?} with itself"));
+        }
+
+        if let Some((pair_id, _)) = self
+            .pairs
+            .iter()
+            .find(|(_, pair)| pair.token_0 == token_0 && pair.token_1 == token_1)
+        {
+            return Err(format!("pair {pair_id} already has these tokens"));
+        }
+
+        let pair_id = self.pairs.keys().last().map_or(1, |last| last + 1);
+
+        self.pairs.insert(pair_id, Pair { token_0, token_1 });
+        self.last_pair = Some(pair_id);
+
+        Ok(vec![Delta::Pair { pair_id }])
+    }
+
+    /// Resolve a pool id, where `0` refers to the last pool created.
+    fn pool_id(&self, pool_id: U256) -> Result<u64, String> {
+        match integer(pool_id, "poolId", 64)? {
+            0 => self.last_pool.ok_or_else(|| {
+                "pool id 0 refers to the last pool created, but none was".to_string()
+            }),
+            pool_id => Ok(pool_id),
+        }
+    }
+
+    /// A pool and its pair.
+    fn pool(&self, pool_id: u64) -> Result<(Pair, Pool), String> {
+        let pool = self
+            .pools
+            .get(&pool_id)
+            .ok_or_else(|| format!("unknown pool {pool_id}"))?;
+
+        let pair = self
+            .pairs
+            .get(&pool.pair_id)
+            .ok_or_else(|| format!("pool {pool_id} has an unknown pair {}", pool.pair_id))?;
+
+        Ok((*pair, pool.clone()))
+    }
+
+    fn balance(&self, token: Address) -> f64 {
+        self.balances.get(&token).copied().unwrap_or_default()
+    }
+
+    /// Take `amount` of `token` from the sender.
+    fn pay(&mut self, token: Address, amount: f64) -> Result<(), String> {
+        let balance = self.balances.entry(token).or_default();
+
+        if amount > *balance {
+            return Err(format!(
+                "insufficient balance of {token:?}: {amount} is needed, but the sender holds {balance}"
+            ));
+        }
+
+        *balance -= amount;
+        Ok(())
+    }
+
+    /// Credit the fees a position earned since it was last credited, returning the position.
+    fn credit(&mut self, pool_id: u64) -> &mut Position {
+        let pool = &self.pools[&pool_id];
+        let position = self.positions.entry(pool_id).or_default();
+
+        position.owed_0 += position.liquidity * (pool.fee_growth_0 - position.checkpoint_0);
+        position.owed_1 += position.liquidity * (pool.fee_growth_1 - position.checkpoint_1);
+        position.checkpoint_0 = pool.fee_growth_0;
+        position.checkpoint_1 = pool.fee_growth_1;
+
+        position
+    }
+}
+
+/// Convert an integer parameter, which must fit in `bits` bits.
+fn integer(value: U256, name: &str, bits: usize) -> Result<u64, String> {
+    if value.bits() > bits {
+        return Err(format!("`{name}` of {value} does not fit in {bits} bits"));
+    }
+
+    Ok(value.low_u64())
+}
+
+/// Convert an amount to a float, rounding to the nearest representable value.
+fn float(value: U256) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |float, limb| float * 2f64.powi(64) + *limb as f64)
+}
+
+/// The standard normal cumulative distribution function.
+fn cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+/// The complementary error function, with a fractional error below 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+
+    let polynomial = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ]
+    .iter()
+    .rev()
+    .fold(0.0, |sum, coefficient| sum * t + coefficient);
+
+    let erfc = t * (-z * z + polynomial).exp();
+
+    if x >= 0.0 {
+        erfc
+    } else {
+        2.0 - erfc
+    }
+}
+
+/// The inverse of [`cdf`], using Acklam's rational approximation.
+fn inverse_cdf(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const LOW: f64 = 0.02425;
+
+    let polynomial = |coefficients: &[f64], x: f64| {
+        coefficients
+            .iter()
+            .fold(0.0, |sum, coefficient| sum * x + coefficient)
+    };
+
+    // The tails, where the approximation is in terms of `sqrt(-2 ln p)`.
+    let tail = |p: f64| {
+        let q = (-2.0 * p.ln()).sqrt();
+        polynomial(&C, q) / (polynomial(&D, q) * q + 1.0)
+    };
+
+    if p <= 0.0 {
+        f64::NEG_INFINITY
+    } else if p >= 1.0 {
+        f64::INFINITY
+    } else if p < LOW {
+        tail(p)
+    } else if p > 1.0 - LOW {
+        -tail(1.0 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        polynomial(&A, r) * q / (polynomial(&B, r) * r + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN_0: Address = Address::repeat_byte(1);
+    const TOKEN_1: Address = Address::repeat_byte(2);
+
+    /// Liquidity allocated by [`funded`], as a raw amount.
+    const LIQUIDITY: u128 = 1_000_000_000_000_000_000;
+
+    fn create_pair() -> Opcode {
+        Opcode::CreatePair {
+            token_0: TOKEN_0,
+            token_1: TOKEN_1,
+        }
+    }
+
+    fn create_pool(fee: u64, vol: u64, controller: Address) -> Opcode {
+        Opcode::CreatePool {
+            pair_id: U256::zero(),
+            controller,
+            priority_fee: U256::zero(),
+            fee: U256::from(fee),
+            vol: U256::from(vol),
+            dur: U256::from(365),
+            jit: U256::zero(),
+            max_price: U256::from(2) * U256::exp10(18),
+            price: U256::exp10(18),
+        }
+    }
+
+    fn allocate(pool_id: u64, liquidity: u128) -> Opcode {
+        Opcode::Allocate {
+            use_max: U256::zero(),
+            pool_id: U256::from(pool_id),
+            delta_liquidity: U256::from(liquidity),
+        }
+    }
+
+    fn deallocate(pool_id: u64, liquidity: u128) -> Opcode {
+        Opcode::Deallocate {
+            use_max: U256::zero(),
+            pool_id: U256::from(pool_id),
+            delta_liquidity: U256::from(liquidity),
+        }
+    }
+
+    fn swap(input: u128, output: u128, sell_asset: bool) -> Opcode {
+        Opcode::Swap {
+            use_max: U256::zero(),
+            pool_id: U256::zero(),
+            amount_0: U256::from(input),
+            amount_1: U256::from(output),
+            sell_asset: U256::from(u8::from(sell_asset)),
+        }
+    }
+
+    /// Run `opcodes`, which must not revert.
+    fn run(state: &State, opcodes: &[Opcode]) -> State {
+        let simulation = state.simulate(opcodes);
+        assert_eq!(simulation.revert, None);
+        simulation.state
+    }
+
+    /// The reason the instruction at `index` reverted.
+    fn revert(state: &State, opcodes: &[Opcode], index: usize) -> String {
+        let simulation = state.simulate(opcodes);
+        let revert = simulation.revert.expect("the program did not revert");
+
+        assert_eq!(revert.index, index);
+        assert_eq!(&simulation.state, state);
+        revert.reason
+    }
+
+    /// A sender with balances of both tokens, and a pool holding liquidity.
+    fn funded(fee: u64) -> (State, u64) {
+        let mut state = State::new();
+        state.balances.insert(TOKEN_0, 1e30);
+        state.balances.insert(TOKEN_1, 1e30);
+
+        let state = run(
+            &state,
+            &[
+                create_pair(),
+                create_pool(fee, 10_000, Address::zero()),
+                allocate(0, LIQUIDITY),
+            ],
+        );
+        let pool_id = state.last_pool.unwrap();
+
+        (state, pool_id)
+    }
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn inverse_cdf_inverts_cdf() {
+        for x in [-3.5, -2.0, -1.0, -0.25, 0.0, 0.5, 1.5, 2.5, 3.5] {
+            assert!(close(inverse_cdf(cdf(x)), x, 1e-5), "{x}");
+        }
+
+        assert!(close(cdf(0.0), 0.5, 1e-7));
+        assert_eq!(inverse_cdf(0.0), f64::NEG_INFINITY);
+        assert_eq!(inverse_cdf(1.0), f64::INFINITY);
+    }
+
+    #[test]
+    fn initial_price() {
+        let (state, pool_id) = funded(30);
+        let pool = &state.pools[&pool_id];
+
+        assert!(close(pool.price(), 1.0, 1e-5), "{}", pool.price());
+        assert!(close(pool.invariant(), 0.0, 1e-9));
+        assert_eq!(pool.liquidity, LIQUIDITY as f64);
+    }
+
+    #[test]
+    fn pool_ids() {
+        let state = run(
+            &State::new(),
+            &[
+                create_pair(),
+                create_pool(30, 1000, Address::zero()),
+                create_pool(30, 1000, Address::repeat_byte(9)),
+                Opcode::CreatePair {
+                    token_0: TOKEN_1,
+                    token_1: TOKEN_0,
+                },
+                create_pool(30, 1000, Address::zero()),
+            ],
+        );
+
+        assert_eq!(
+            state.pools.keys().copied().collect::<Vec<_>>(),
+            [1 << 40 | 1, 1 << 40 | 1 << 32 | 2, 2 << 40 | 1]
+        );
+        assert_eq!(state.pools[&(2 << 40 | 1)].pair_id, 2);
+        assert_eq!(state.last_pair, Some(2));
+        assert_eq!(state.last_pool, Some(2 << 40 | 1));
+    }
+
+    #[test]
+    fn allocate_and_deallocate() {
+        let (state, pool_id) = funded(30);
+        let pool = &state.pools[&pool_id];
+
+        assert!(close(
+            state.balance(TOKEN_0),
+            1e30 - pool.x * LIQUIDITY as f64,
+            1e-12
+        ));
+
+        let after = run(&state, &[deallocate(0, LIQUIDITY)]);
+
+        assert!(close(after.balance(TOKEN_0), 1e30, 1e-12));
+        assert!(close(after.balance(TOKEN_1), 1e30, 1e-12));
+        assert_eq!(after.pools[&pool_id].liquidity, 0.0);
+        assert_eq!(after.positions[&pool_id].liquidity, 0.0);
+    }
+
+    #[test]
+    fn swaps_keep_the_invariant() {
+        let (state, pool_id) = funded(0);
+        let before = state.pools[&pool_id].clone();
+
+        // Taking out everything the input pays for leaves the invariant where it was.
+        for sell_asset in [true, false] {
+            let mut probe = state.clone();
+            let deltas = probe.execute(&swap(1e16 as u128, 0, sell_asset)).unwrap();
+            let Delta::Reserve { amount, .. } = deltas[3] else {
+                panic!("expected the output reserve, got {}", deltas[3]);
+            };
+            assert_eq!(amount, 0.0);
+
+            let pool = &probe.pools[&pool_id];
+            assert!(pool.invariant() > before.invariant());
+
+            let available = if sell_asset {
+                (before.y - before.y_of(before.x + 1e16 / before.liquidity)) * before.liquidity
+            } else {
+                let y = before.y + 1e16 / before.liquidity;
+                (before.x - before.x_of(y)) * before.liquidity
+            };
+
+            let after = run(&state, &[swap(1e16 as u128, available as u128, sell_asset)]);
+            let pool = &after.pools[&pool_id];
+
+            // Up to the precision of `erfc`, which `x_of` and `y_of` do not invert exactly.
+            assert!(close(pool.invariant(), before.invariant(), 1e-6));
+            assert_eq!(sell_asset, pool.price() < before.price());
+        }
+    }
+
+    #[test]
+    fn fees_raise_the_invariant() {
+        let (state, pool_id) = funded(100);
+        let before = state.pools[&pool_id].clone();
+        let after = run(&state, &[swap(1e16 as u128, 0, true)]);
+        let pool = &after.pools[&pool_id];
+
+        assert!(pool.invariant() > before.invariant());
+        assert!(close(pool.fee_growth_0 * pool.liquidity, 1e14, 1e-9));
+
+        // The fee is owed to the position, and claimed.
+        let claimed = run(
+            &after,
+            &[Opcode::Claim {
+                pool_id: U256::zero(),
+                fee_0: U256::MAX,
+                fee_1: U256::MAX,
+            }],
+        );
+
+        assert!(close(
+            claimed.balance(TOKEN_0),
+            after.balance(TOKEN_0) + 1e14,
+            1e-12
+        ));
+        assert_eq!(claimed.positions[&pool_id].owed_0, 0.0);
+    }
+
+    #[test]
+    fn unknown_pairs_and_pools() {
+        let state = State::new();
+
+        assert_eq!(
+            revert(&state, &[create_pool(30, 1000, Address::zero())], 0),
+            "pair id 0 refers to the last pair created, but none was"
+        );
+
+        let mut unknown_pair = create_pool(30, 1000, Address::zero());
+        if let Opcode::CreatePool { pair_id, .. } = &mut unknown_pair {
+            *pair_id = U256::from(5);
+        }
+
+        assert_eq!(
+            revert(&state, &[create_pair(), unknown_pair], 1),
+            "unknown pair 5"
+        );
+        assert_eq!(revert(&state, &[allocate(7, 1)], 0), "unknown pool 7");
+        assert_eq!(
+            revert(&state, &[allocate(0, 1)], 0),
+            "pool id 0 refers to the last pool created, but none was"
+        );
+        assert_eq!(
+            revert(&state, &[create_pair(), create_pair()], 1),
+            "pair 1 already has these tokens"
+        );
+    }
+
+    #[test]
+    fn insufficient_balances() {
+        let (state, pool_id) = funded(30);
+
+        let mut poor = state.clone();
+        poor.balances.clear();
+
+        assert!(revert(&poor, &[allocate(0, LIQUIDITY)], 0).starts_with("insufficient balance of"));
+        assert!(revert(&poor, &[swap(1, 0, true)], 0).starts_with("insufficient balance of"));
+        assert_eq!(
+            revert(&state, &[deallocate(0, 2 * LIQUIDITY)], 0),
+            format!(
+                "cannot remove {} liquidity from pool {pool_id}, the position holds {}",
+                (2 * LIQUIDITY) as f64,
+                LIQUIDITY as f64
+            )
+        );
+    }
+
+    #[test]
+    fn swaps_within_bounds() {
+        let (state, pool_id) = funded(30);
+
+        assert!(revert(&state, &[swap(1e16 as u128, 1e30 as u128, true)], 0)
+            .starts_with("swap asks for"));
+        assert!(revert(&state, &[swap(1e30 as u128, 0, true)], 0)
+            .ends_with("outside of its price bounds"));
+
+        let empty = run(&state, &[deallocate(0, LIQUIDITY)]);
+
+        assert_eq!(
+            revert(&empty, &[swap(1, 0, true)], 0),
+            format!("pool {pool_id} has no liquidity")
+        );
+    }
+
+    #[test]
+    fn invalid_pools() {
+        let state = run(&State::new(), &[create_pair()]);
+
+        let mut priority = create_pool(30, 1000, Address::zero());
+        if let Opcode::CreatePool { priority_fee, .. } = &mut priority {
+            *priority_fee = U256::from(31);
+        }
+
+        assert_eq!(
+            revert(&state, &[create_pool(10_001, 1000, Address::zero())], 0),
+            "fee of 10001 and priority fee of 0 are out of bounds"
+        );
+        assert_eq!(
+            revert(&state, &[priority], 0),
+            "fee of 30 and priority fee of 31 are out of bounds"
+        );
+        assert_eq!(
+            revert(&state, &[create_pool(30, 0, Address::zero())], 0),
+            "volatility and duration must be positive"
+        );
+    }
+
+    #[test]
+    fn reverts_discard_earlier_instructions() {
+        let (state, _) = funded(30);
+        let simulation =
+            state.simulate(&[allocate(0, LIQUIDITY), deallocate(0, 1), allocate(9, 1)]);
+
+        assert_eq!(simulation.steps.len(), 3);
+        assert_eq!(simulation.revert.unwrap().index, 2);
+        assert_eq!(simulation.state, state);
+    }
+
+    #[test]
+    fn exact_amounts() {
+        let mut state = State::new();
+        state.balances.insert(TOKEN_0, EXACT);
+        assert!(state.simulate(&[create_pair()]).is_exact());
+
+        // Above 2^53, odd amounts cannot be told apart from their neighbours.
+        assert_eq!(float(U256::from(1u64 << 53) + 1), EXACT);
+
+        let (state, _) = funded(30);
+        assert!(!state.simulate(&[allocate(0, LIQUIDITY)]).is_exact());
+    }
+}
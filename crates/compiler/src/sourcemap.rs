@@ -0,0 +1,338 @@
+//! # Source map
+//!
+//! Maps each instruction of a payload back to the source it was assembled from.
+//! A [`Mapping`] holds the byte range of an instruction within the payload built by
+//! [`Codegen::generate`], the location of the instruction in source, and the stack of macro invocations it was expanded through,
+//! so that an instruction that reverts on chain can be traced back to the line and the expansion that produced it.
+
+use std::{fmt, ops::Range};
+
+use crate::{codegen::Codegen, opcode::Opcode};
+
+/// A position in a source file, with a 1-indexed line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// A macro an instruction was expanded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Frame {
+    /// Name of the macro.
+    pub name: String,
+    /// Where the macro was invoked, or defined for the entry point.
+    pub location: Option<Location>,
+}
+
+/// Where an instruction came from in source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Origin {
+    /// Location of the instruction, if it was assembled from source.
+    pub location: Option<Location>,
+    /// Macros the instruction was expanded from, outermost first.
+    pub stack: Vec<Frame>,
+}
+
+/// The origin of an encoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "camelCase")
+)]
+pub struct Mapping {
+    /// Index of the instruction in the payload.
+    pub index: usize,
+    /// Byte range of the instruction within the payload, not including its length prefix.
+    pub range: Range<usize>,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub origin: Origin,
+}
+
+/// The origin of every instruction of a payload.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceMap {
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Map the payload of `codegen`, given the origin of each of its opcodes.
+    /// Opcodes that are not encoded, like `unknown`, have no mapping.
+    pub fn new(codegen: &Codegen, origins: Vec<Origin>) -> Self {
+        // The payload starts with `jump` and the instruction count.
+        let mut offset = 2;
+        let mut mappings = Vec::new();
+
+        for (opcode, origin) in codegen.opcodes().iter().zip(origins) {
+            let Some(length) = SourceMap::length(opcode) else {
+                continue;
+            };
+
+            // Each instruction is prefixed with its length.
+            offset += 1;
+
+            mappings.push(Mapping {
+                index: mappings.len(),
+                range: offset..offset + length,
+                origin,
+            });
+
+            offset += length;
+        }
+
+        Self { mappings }
+    }
+
+    /// The mapping of the instruction that byte `offset` of the payload belongs to.
+    pub fn at(&self, offset: usize) -> Option<&Mapping> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.range.start <= offset && offset < mapping.range.end)
+    }
+
+    /// Encoded length of an opcode, if it is encoded at all.
+    fn length(opcode: &Opcode) -> Option<usize> {
+        Codegen::from(vec![opcode.clone()])
+            .encode()
+            .pop()
+            .map(|encoded| encoded.len() / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use ethers::utils::hex;
+
+    use super::*;
+    use crate::{
+        assembler::{Assembler, Expression},
+        disassembler,
+        loader::Loader,
+        opcode::{Address, U256},
+    };
+
+    /// Assemble `main` from the files of `loader`, mapping each instruction to its origin as `folio build` does.
+    fn map(loader: &Loader) -> (Codegen, SourceMap) {
+        let macros = Assembler::new(loader.tokens()).parse_macros().unwrap();
+        let (body, stacks): (Vec<_>, Vec<_>) = Assembler::trace(&macros, "main")
+            .unwrap()
+            .into_iter()
+            .unzip();
+
+        let origins = body
+            .iter()
+            .zip(stacks)
+            .map(|(expression, stack)| Origin {
+                location: loader.location(expression.span),
+                stack: stack
+                    .into_iter()
+                    .map(|frame| Frame {
+                        name: frame.node.to_string(),
+                        location: loader.location(frame.span),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let codegen = Codegen::new(body.into_iter().map(|expression| expression.node).collect());
+        let source_map = SourceMap::new(&codegen, origins);
+
+        (codegen, source_map)
+    }
+
+    /// A loader of `source`, named `main`.
+    fn load(source: &str, search_paths: Vec<PathBuf>) -> Loader {
+        let mut loader = Loader::new(search_paths);
+        loader.load("main", None, source.to_string()).unwrap();
+
+        loader
+    }
+
+    fn location(file: &str, line: usize, column: usize) -> Option<Location> {
+        Some(Location {
+            file: file.to_string(),
+            line,
+            column,
+        })
+    }
+
+    #[test]
+    fn ranges() {
+        let codegen = Codegen::new(
+            [
+                Opcode::CreatePair {
+                    token_0: Address::repeat_byte(1),
+                    token_1: Address::repeat_byte(2),
+                },
+                Opcode::Unknown,
+                Opcode::Allocate {
+                    use_max: U256::zero(),
+                    pool_id: U256::from(34),
+                    delta_liquidity: U256::exp10(18),
+                },
+                Opcode::Claim {
+                    pool_id: U256::from(34),
+                    fee_0: U256::from(1),
+                    fee_1: U256::zero(),
+                },
+            ]
+            .into_iter()
+            .map(Expression::Opcode)
+            .collect(),
+        );
+        let source_map = SourceMap::new(&codegen, vec![Origin::default(); 4]);
+        let payload = codegen.payload().unwrap();
+        let encoded = codegen.encode();
+
+        // `unknown` is not encoded, so it has no mapping.
+        assert_eq!(source_map.mappings.len(), 3);
+        assert_eq!(encoded.len(), 3);
+
+        for (mapping, instruction) in source_map.mappings.iter().zip(&encoded) {
+            let bytes = &payload[mapping.range.clone()];
+
+            assert_eq!(hex::encode(bytes), *instruction);
+            assert_eq!(usize::from(payload[mapping.range.start - 1]), bytes.len());
+            assert_eq!(source_map.at(mapping.range.start), Some(mapping));
+            assert_eq!(source_map.at(mapping.range.end - 1), Some(mapping));
+        }
+
+        // The ranges cover the payload, apart from its header and the length prefixes.
+        assert_eq!(source_map.mappings.last().unwrap().range.end, payload.len());
+        assert_eq!(
+            source_map
+                .mappings
+                .iter()
+                .map(|mapping| mapping.index)
+                .collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        assert_eq!(source_map.at(0), None);
+        assert_eq!(source_map.at(2), None);
+        assert_eq!(source_map.at(payload.len()), None);
+    }
+
+    #[test]
+    fn imported_locations() {
+        let directory =
+            std::env::temp_dir().join(format!("folio-source-map-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("pools.fvm"),
+            "macro pools {\n\tclaim:\n\t\tpoolId: 34\n\t\tfee0: 1\n\t\tfee1: 2\n}",
+        )
+        .unwrap();
+
+        let loader = load(
+            "import \"pools.fvm\"\n\nmacro main {\n\tpools\n}",
+            vec![directory.clone()],
+        );
+        let (_, source_map) = map(&loader);
+        fs::remove_dir_all(&directory).unwrap();
+
+        let imported = directory.join("pools.fvm").display().to_string();
+        let origin = &source_map.mappings[0].origin;
+
+        assert_eq!(origin.location, location(&imported, 2, 2));
+        assert_eq!(
+            origin.stack,
+            [
+                Frame {
+                    name: "main".to_string(),
+                    location: location("main", 3, 7),
+                },
+                Frame {
+                    name: "pools".to_string(),
+                    location: location("main", 4, 2),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_expansions() {
+        let loader = load(
+            "macro main {\n\touter\n}\n\nmacro outer {\n\tinner\n\tclaim: poolId: 1 fee0: 0 fee1: 0\n}\n\nmacro inner {\n\tclaim: poolId: 2 fee0: 0 fee1: 0\n}",
+            Vec::new(),
+        );
+        let (_, source_map) = map(&loader);
+
+        let stacks = source_map
+            .mappings
+            .iter()
+            .map(|mapping| {
+                mapping
+                    .origin
+                    .stack
+                    .iter()
+                    .map(|frame| (frame.name.as_str(), frame.location.clone().unwrap().line))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // `inner` is expanded first, through both macros, then the rest of `outer`.
+        assert_eq!(
+            stacks,
+            [
+                vec![("main", 1), ("outer", 2), ("inner", 6)],
+                vec![("main", 1), ("outer", 2)],
+            ]
+        );
+        assert_eq!(
+            source_map.mappings[0].origin.location,
+            location("main", 11, 2)
+        );
+        assert_eq!(
+            source_map.mappings[1].origin.location,
+            location("main", 7, 2)
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        let loader = load(
+            "macro main {\n\tclaim: poolId: 1 fee0: 0 fee1: 0\n\n\tswap: poolId: 2 amount0: 1 amount1: 2 sellAsset: 1\n}",
+            Vec::new(),
+        );
+        let (codegen, source_map) = map(&loader);
+        let payload = codegen.payload().unwrap();
+
+        let annotated = disassembler::annotate(&payload, &source_map).unwrap();
+
+        assert_eq!(annotated.len(), 2);
+
+        for ((decoded, mapping), (opcode, line)) in
+            annotated.iter().zip(codegen.opcodes().iter().zip([2, 4]))
+        {
+            let mapping = mapping.unwrap();
+
+            assert_eq!(decoded.opcode, *opcode);
+            assert_eq!(decoded.offset, mapping.range.start);
+            assert_eq!(mapping.origin.location, location("main", line, 2));
+        }
+
+        // A source map of another payload leaves instructions past its end unmapped.
+        let partial = SourceMap {
+            mappings: source_map.mappings[..1].to_vec(),
+        };
+        let annotated = disassembler::annotate(&payload, &partial).unwrap();
+
+        assert_eq!(annotated[0].1, Some(&source_map.mappings[0]));
+        assert_eq!(annotated[1].1, None);
+    }
+}
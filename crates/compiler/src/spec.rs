@@ -0,0 +1,228 @@
+//! # Spec
+//!
+//! The machine-readable specification of every FVM instruction folio can emit.
+//! This table is the single source of truth for opcode nibbles, parameter names, types, encoded widths and defaults:
+//! the assembler and code generator read it, and `folio spec --json` exports it for other tooling.
+
+use crate::{
+    codegen::Codegen,
+    opcode::{Argument, U256},
+};
+
+/// Type of a parameter value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "camelCase")
+)]
+pub enum Kind {
+    /// An unsigned integer, encoded big-endian in `bits` bits.
+    Integer,
+    /// A 20 byte address.
+    Address,
+    /// An amount, encoded as a one byte power of ten followed by a 16 byte base.
+    Amount,
+    /// A boolean, `0` or `1`.
+    Flag,
+}
+
+/// A parameter of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "camelCase")
+)]
+pub struct Parameter {
+    /// Name of the parameter, as written in source.
+    pub name: &'static str,
+    /// Type of the parameter value.
+    pub kind: Kind,
+    /// Width of the encoded field in bits.
+    /// Zero for parameters that select the opcode rather than being encoded as a field.
+    pub bits: usize,
+    /// Value used when the parameter is omitted. Parameters without a default are required.
+    pub default: Option<&'static str>,
+}
+
+impl Parameter {
+    const fn new(name: &'static str, kind: Kind, bits: usize) -> Self {
+        Self {
+            name,
+            kind,
+            bits,
+            default: None,
+        }
+    }
+
+    const fn default(self, default: &'static str) -> Self {
+        Self {
+            default: Some(default),
+            ..self
+        }
+    }
+
+    /// Check that `argument` has the right type, and fits in the encoded field.
+    pub fn validate(&self, argument: &Argument) -> Result<(), String> {
+        let value = match (self.kind, argument) {
+            (Kind::Address, Argument::Address(_)) => return Ok(()),
+            (Kind::Address, Argument::Integer(_)) => {
+                return Err(format!("`{}` must be an address", self.name))
+            }
+            (_, Argument::Address(_)) => return Err(format!("`{}` must be an integer", self.name)),
+            (_, Argument::Integer(value)) => *value,
+        };
+
+        match self.kind {
+            Kind::Flag if value > U256::one() => Err(format!("`{}` must be 0 or 1", self.name)),
+            Kind::Integer if value.bits() > self.bits => Err(format!(
+                "`{}` must fit in {} bits, but {value} does not",
+                self.name, self.bits
+            )),
+            Kind::Amount if Codegen::from_amount(value).1.bits() > 128 => Err(format!(
+                "`{}` must be a base of at most 128 bits times a power of ten, but {value} is not",
+                self.name
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An FVM instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "camelCase")
+)]
+pub struct Instruction {
+    /// Name of the instruction, as written in source.
+    pub name: &'static str,
+    /// Opcode, stored in the lower nibble of the first encoded byte, or the whole byte for `jump`.
+    pub opcode: u8,
+    /// Description, taken from the FVM yellow paper.
+    pub description: &'static str,
+    /// Parameters, in canonical order.
+    pub parameters: &'static [Parameter],
+}
+
+impl Instruction {
+    /// Look up a parameter of this instruction by name.
+    pub fn parameter(&self, name: &str) -> Option<&'static Parameter> {
+        self.parameters
+            .iter()
+            .find(|parameter| parameter.name == name)
+    }
+}
+
+const USE_MAX: Parameter = Parameter::new("useMax", Kind::Flag, 4).default("0");
+const POOL_ID: Parameter = Parameter::new("poolId", Kind::Integer, 64);
+const DELTA_LIQUIDITY: Parameter = Parameter::new("deltaLiquidity", Kind::Amount, 136);
+
+pub const UNKNOWN: Instruction = Instruction {
+    name: "unknown",
+    opcode: 0x00,
+    description: "This is the default opcode. It is used to represent an unknown opcode, and is used to initialize the FVM’s state.",
+    parameters: &[],
+};
+
+pub const ALLOCATE: Instruction = Instruction {
+    name: "allocate",
+    opcode: 0x01,
+    description: "This instruction is used to add liquidity to a pool. It maintains invariant pricing for each pool that is interacted with.",
+    parameters: &[USE_MAX, POOL_ID, DELTA_LIQUIDITY],
+};
+
+pub const DEALLOCATE: Instruction = Instruction {
+    name: "deallocate",
+    opcode: 0x03,
+    description: "This instruction is used to remove liquidity from a pool. It maintains invariant pricing for each pool that is interacted with.",
+    parameters: &[USE_MAX, POOL_ID, DELTA_LIQUIDITY],
+};
+
+pub const CLAIM: Instruction = Instruction {
+    name: "claim",
+    opcode: 0x04,
+    description: "Collects all the fees generated from a positive invariant for.",
+    parameters: &[
+        POOL_ID,
+        Parameter::new("fee0", Kind::Amount, 136),
+        Parameter::new("fee1", Kind::Amount, 136),
+    ],
+};
+
+/// Swaps encode as `0x05` when selling the quote token, and `0x06` when `sellAsset` is set.
+pub const SWAP: Instruction = Instruction {
+    name: "swap",
+    opcode: 0x05,
+    description: "This instruction is used to swap between the tokens. It maintains the invariant of the trading curve.",
+    parameters: &[
+        USE_MAX,
+        POOL_ID,
+        Parameter::new("amount0", Kind::Amount, 136),
+        Parameter::new("amount1", Kind::Amount, 136),
+        Parameter::new("sellAsset", Kind::Flag, 0),
+    ],
+};
+
+pub const CREATE_POOL: Instruction = Instruction {
+    name: "createPool",
+    opcode: 0x0B,
+    description: "This instruction is used to create a new pool. Initially, pools are not deployed with any capital, but are deployed with parameters for the CFMM as well as an initial price.",
+    parameters: &[
+        Parameter::new("pairId", Kind::Integer, 24),
+        Parameter::new("controller", Kind::Address, 160),
+        Parameter::new("priorityFee", Kind::Integer, 16).default("0"),
+        Parameter::new("fee", Kind::Integer, 16),
+        Parameter::new("vol", Kind::Integer, 16),
+        Parameter::new("dur", Kind::Integer, 16),
+        Parameter::new("jit", Kind::Integer, 16),
+        Parameter::new("maxPrice", Kind::Amount, 136),
+        Parameter::new("price", Kind::Amount, 136),
+    ],
+};
+
+pub const CREATE_PAIR: Instruction = Instruction {
+    name: "createPair",
+    opcode: 0x0C,
+    description: "This instruction is used to initialize a new pair of assets for which pools can be created.",
+    parameters: &[
+        Parameter::new("token0", Kind::Address, 160),
+        Parameter::new("token1", Kind::Address, 160),
+    ],
+};
+
+/// Jumps are emitted by [`Codegen::generate`](crate::codegen::Codegen::generate) to process a list of instructions.
+pub const JUMP: Instruction = Instruction {
+    name: "jump",
+    opcode: 0xAA,
+    description: "This instructions is used to jump to a different instruction in the FVM’s state via FVM’s pointer.",
+    parameters: &[],
+};
+
+/// Every instruction, in opcode order.
+pub static INSTRUCTIONS: [Instruction; 8] = [
+    UNKNOWN,
+    ALLOCATE,
+    DEALLOCATE,
+    CLAIM,
+    SWAP,
+    CREATE_POOL,
+    CREATE_PAIR,
+    JUMP,
+];
+
+/// Look up an instruction by name.
+pub fn instruction(name: &str) -> Option<&'static Instruction> {
+    INSTRUCTIONS
+        .iter()
+        .find(|instruction| instruction.name == name)
+}
+
+/// Look up a parameter of any instruction by name.
+pub fn parameter(name: &str) -> Option<&'static Parameter> {
+    INSTRUCTIONS
+        .iter()
+        .find_map(|instruction| instruction.parameter(name))
+}
@@ -1,10 +1,12 @@
-use logos::Logos;
+//! # Token
+//!
+//! This module exposes a lexer, and a token type.
+//! It is instantiated with a raw source string, and converts it into a vector of Tokens.
+//! This is the first stage of the folio compiler.
+
+use std::fmt;
 
-/// # Token
-///
-/// This module exposes a lexer, and a token type.
-/// It is instantiated with a raw source string, and converts it into a vector of Tokens.
-/// This is the first stage of the folio compiler.
+use logos::Logos;
 
 /// Represents a token type.
 /// All opcode descriptions are taken from the FVM Yellowpaper.
@@ -37,6 +39,18 @@ pub enum TokenType {
     #[token("macro")]
     Macro,
 
+    #[token("import")]
+    Import,
+
+    /// Declares a pair or pool that exists before a program runs.
+    #[token("extern")]
+    Extern,
+
+    /// Marks a macro as an entry point.
+    #[token("pub")]
+    #[token("entry")]
+    Pub,
+
     #[token("{")]
     OpenBrace,
 
@@ -106,34 +120,160 @@ pub enum TokenType {
     #[regex(r"0[xX][a-fA-F0-9]+")]
     AddressLiteral,
 
-    #[regex("[a-zA-Z_]+")]
+    #[regex("[a-zA-Z_][a-zA-Z0-9_]*")]
     Identifier,
 
+    #[regex(r#""[^"\n]*""#)]
+    StringLiteral,
+
     #[regex("[+-]?([0-9]*[.])?[0-9]+")]
     Literal,
 
+    /// A line comment, only kept by [`Token::lex_with_comments`].
+    #[regex(r"//[^\n]*")]
+    Comment,
+
     /// Unrecognized or erroneous token.
     #[error]
     #[regex(r"[ \t\n\f]+", logos::skip)]
     Error,
 }
 
+impl TokenType {
+    /// Whether the token is the key of an instruction parameter.
+    pub fn is_parameter(&self) -> bool {
+        matches!(
+            self,
+            TokenType::PoolId
+                | TokenType::Fee0
+                | TokenType::Fee1
+                | TokenType::UseMax
+                | TokenType::DeltaLiquidity
+                | TokenType::Amount0
+                | TokenType::Amount1
+                | TokenType::Token0
+                | TokenType::Token1
+                | TokenType::PairId
+                | TokenType::Controller
+                | TokenType::PriorityFee
+                | TokenType::Fee
+                | TokenType::Vol
+                | TokenType::Dur
+                | TokenType::Jit
+                | TokenType::MaxPrice
+                | TokenType::Price
+                | TokenType::SellAsset
+        )
+    }
+}
+
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            TokenType::Unknown => "`unknown`",
+            TokenType::Allocate => "`allocate`",
+            TokenType::Deallocate => "`deallocate`",
+            TokenType::Claim => "`claim`",
+            TokenType::Swap => "`swap`",
+            TokenType::CreatePool => "`createPool`",
+            TokenType::CreatePair => "`createPair`",
+            TokenType::Jump => "`jump`",
+            TokenType::Macro => "`macro`",
+            TokenType::Import => "`import`",
+            TokenType::Extern => "`extern`",
+            TokenType::Pub => "`pub`",
+            TokenType::OpenBrace => "`{`",
+            TokenType::CloseBrace => "`}`",
+            TokenType::Colon => "`:`",
+            TokenType::PoolId => "`poolId`",
+            TokenType::Fee0 => "`fee0`",
+            TokenType::Fee1 => "`fee1`",
+            TokenType::UseMax => "`useMax`",
+            TokenType::DeltaLiquidity => "`deltaLiquidity`",
+            TokenType::Amount0 => "`amount0`",
+            TokenType::Amount1 => "`amount1`",
+            TokenType::Token0 => "`token0`",
+            TokenType::Token1 => "`token1`",
+            TokenType::PairId => "`pairId`",
+            TokenType::Controller => "`controller`",
+            TokenType::PriorityFee => "`priorityFee`",
+            TokenType::Fee => "`fee`",
+            TokenType::Vol => "`vol`",
+            TokenType::Dur => "`dur`",
+            TokenType::Jit => "`jit`",
+            TokenType::MaxPrice => "`maxPrice`",
+            TokenType::Price => "`price`",
+            TokenType::SellAsset => "`sellAsset`",
+            TokenType::AddressLiteral => "an address",
+            TokenType::Identifier => "an identifier",
+            TokenType::StringLiteral => "a string",
+            TokenType::Literal => "a literal",
+            TokenType::Comment => "a comment",
+            TokenType::Error => "an unrecognized token",
+        };
+
+        write!(f, "{description}")
+    }
+}
+
+/// A byte range within a source string.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Instantiate a span.
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// A value paired with the span of source it was parsed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    /// Instantiate a spanned value.
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
 /// Represents a token.
-/// Should include a span later on as well.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Token<'a> {
     pub ttype: TokenType,
     pub slice: &'a str,
+    pub span: Span,
 }
 
 impl<'a> Token<'a> {
     /// Instantiate a token.
-    pub fn new(ttype: TokenType, slice: &'a str) -> Self {
-        Self { ttype, slice }
+    pub fn new(ttype: TokenType, slice: &'a str, span: Span) -> Self {
+        Self { ttype, slice, span }
     }
 
     /// Given a source chunk, lex it and provide a vector of tokens.
-    pub fn lex(raw: &'a str) -> Vec<Token> {
+    /// Unrecognized input is kept as [`TokenType::Error`] tokens, for the parser to report.
+    pub fn lex(raw: &'a str) -> Vec<Token<'a>> {
+        let mut tokens = Token::lex_with_comments(raw);
+        tokens.retain(|token| token.ttype != TokenType::Comment);
+
+        tokens
+    }
+
+    /// Lex a source chunk, keeping comments as [`TokenType::Comment`] tokens.
+    pub fn lex_with_comments(raw: &'a str) -> Vec<Token<'a>> {
         let mut tokens = Vec::new();
         let mut lex = TokenType::lexer(raw);
 
@@ -142,9 +282,12 @@ impl<'a> Token<'a> {
             if z.is_none() {
                 break;
             }
-            if !(z.unwrap() == TokenType::Error) {
-                tokens.push(Self::new(z.unwrap(), lex.slice()))
-            };
+            let span = lex.span();
+            tokens.push(Self::new(
+                z.unwrap(),
+                lex.slice(),
+                Span::new(span.start, span.end),
+            ));
         }
 
         tokens
@@ -0,0 +1,231 @@
+//! # Transaction
+//!
+//! Wraps a payload into the calldata of a call to the FVM contract.
+//! The contract processes a payload either through `multiprocess(bytes)`, which takes it abi-encoded after the function selector,
+//! or through its fallback function, which takes the bare payload as calldata.
+//! A call can be left unsigned, for a wallet to fill in and sign, or signed offline as an EIP-1559 transaction.
+
+use std::{fmt, str::FromStr};
+
+use ethers::{
+    abi::{self, Token},
+    signers::{LocalWallet, Signer},
+    types::{
+        self, transaction::eip2718::TypedTransaction, Bytes, Eip1559TransactionRequest,
+        TransactionRequest,
+    },
+    utils,
+};
+
+use crate::opcode::Address;
+
+/// Signature of the function of the FVM contract that processes a payload.
+pub const MULTIPROCESS: &str = "multiprocess(bytes)";
+
+/// The fields of an EIP-1559 transaction that its sender chooses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parameters {
+    pub chain_id: u64,
+    pub nonce: types::U256,
+    /// Gas limit.
+    pub gas: types::U256,
+    /// Most wei paid per unit of gas, including the priority fee.
+    pub max_fee_per_gas: types::U256,
+    /// Most wei paid per unit of gas to the block producer.
+    pub max_priority_fee_per_gas: types::U256,
+}
+
+/// The function of the FVM contract a payload is sent to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Entrypoint {
+    /// The fallback function, called with the bare payload.
+    #[default]
+    Fallback,
+    /// `multiprocess(bytes)`, called with the abi-encoded payload.
+    Multiprocess,
+}
+
+impl FromStr for Entrypoint {
+    type Err = String;
+
+    fn from_str(entrypoint: &str) -> Result<Self, Self::Err> {
+        match entrypoint {
+            "fallback" => Ok(Entrypoint::Fallback),
+            "multiprocess" => Ok(Entrypoint::Multiprocess),
+            entrypoint => Err(format!(
+                "unknown entrypoint `{entrypoint}`, expected `fallback` or `multiprocess`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Entrypoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entrypoint::Fallback => write!(f, "fallback"),
+            Entrypoint::Multiprocess => write!(f, "multiprocess"),
+        }
+    }
+}
+
+/// The calldata of a call processing `payload` through `entrypoint`.
+pub fn calldata(payload: &[u8], entrypoint: Entrypoint) -> Vec<u8> {
+    match entrypoint {
+        Entrypoint::Fallback => payload.to_vec(),
+        Entrypoint::Multiprocess => {
+            let mut calldata = utils::id(MULTIPROCESS).to_vec();
+            calldata.extend(abi::encode(&[Token::Bytes(payload.to_vec())]));
+            calldata
+        }
+    }
+}
+
+/// An unsigned transaction to the FVM contract at `to`, processing `payload` through `entrypoint`.
+/// The sender, gas and nonce are left for the wallet that signs it to fill in.
+pub fn request(payload: &[u8], entrypoint: Entrypoint, to: Address) -> TransactionRequest {
+    TransactionRequest::new()
+        .to(types::Address::from_slice(to.as_bytes()))
+        .data(calldata(payload, entrypoint))
+}
+
+/// Sign a transaction to the FVM contract at `to`, processing `payload` through `entrypoint`,
+/// returning it RLP-encoded, ready to be broadcast. Signing needs no network access.
+pub fn sign(
+    payload: &[u8],
+    entrypoint: Entrypoint,
+    to: Address,
+    parameters: &Parameters,
+    wallet: &LocalWallet,
+) -> Result<Bytes, String> {
+    let transaction: TypedTransaction = Eip1559TransactionRequest::new()
+        .from(wallet.address())
+        .to(types::Address::from_slice(to.as_bytes()))
+        .data(calldata(payload, entrypoint))
+        .chain_id(parameters.chain_id)
+        .nonce(parameters.nonce)
+        .gas(parameters.gas)
+        .max_fee_per_gas(parameters.max_fee_per_gas)
+        .max_priority_fee_per_gas(parameters.max_priority_fee_per_gas)
+        .into();
+
+    let signature = wallet
+        .clone()
+        .with_chain_id(parameters.chain_id)
+        .sign_transaction_sync(&transaction)
+        .map_err(|error| format!("could not sign the transaction: {error}"))?;
+
+    Ok(transaction.rlp_signed(&signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback() {
+        let payload = [0xaa, 0x01, 0x01, 0x00];
+
+        assert_eq!(calldata(&payload, Entrypoint::Fallback), payload);
+        assert!(calldata(&[], Entrypoint::Fallback).is_empty());
+    }
+
+    #[test]
+    fn multiprocess() {
+        for length in [0, 1, 31, 32, 33, 100] {
+            let payload = (0..length).map(|byte| byte as u8 + 1).collect::<Vec<_>>();
+            let calldata = calldata(&payload, Entrypoint::Multiprocess);
+
+            // The selector, the offset of the bytes, their length, then the bytes padded to a whole word.
+            let (selector, rest) = calldata.split_at(4);
+            let (offset, rest) = rest.split_at(32);
+            let (length, rest) = rest.split_at(32);
+            let (bytes, padding) = rest.split_at(payload.len());
+
+            assert_eq!(selector, &utils::keccak256(MULTIPROCESS)[..4]);
+            assert_eq!(types::U256::from_big_endian(offset), types::U256::from(32));
+            assert_eq!(
+                types::U256::from_big_endian(length),
+                types::U256::from(payload.len())
+            );
+            assert_eq!(bytes, payload);
+            assert_eq!(rest.len() % 32, 0);
+            assert!(padding.len() < 32 && padding.iter().all(|byte| *byte == 0));
+        }
+    }
+
+    #[test]
+    fn entrypoints() {
+        for entrypoint in [Entrypoint::Fallback, Entrypoint::Multiprocess] {
+            assert_eq!(entrypoint.to_string().parse(), Ok(entrypoint));
+        }
+
+        assert!("receive".parse::<Entrypoint>().is_err());
+    }
+
+    #[test]
+    fn requests() {
+        let to = Address::repeat_byte(0xfe);
+        let request = request(&[0xaa, 0x00], Entrypoint::Multiprocess, to);
+
+        assert_eq!(request.to, Some(types::Address::repeat_byte(0xfe).into()));
+        assert_eq!(
+            request.data.unwrap().to_vec(),
+            calldata(&[0xaa, 0x00], Entrypoint::Multiprocess)
+        );
+        assert_eq!(request.from, None);
+        assert_eq!(request.nonce, None);
+    }
+
+    #[test]
+    fn signs() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let parameters = Parameters {
+            chain_id: 5,
+            nonce: types::U256::from(7),
+            gas: types::U256::from(300_000),
+            max_fee_per_gas: types::U256::from(30_000_000_000u64),
+            max_priority_fee_per_gas: types::U256::from(1_000_000_000),
+        };
+        let payload = [0xaa, 0x00];
+        let to = Address::repeat_byte(0xfe);
+
+        let signed = sign(&payload, Entrypoint::Multiprocess, to, &parameters, &wallet).unwrap();
+
+        // An EIP-1559 transaction is its type, `0x02`, followed by its RLP encoding.
+        assert_eq!(signed[0], 0x02);
+
+        let (transaction, signature) =
+            TypedTransaction::decode_signed(&utils::rlp::Rlp::new(&signed)).unwrap();
+
+        assert_eq!(
+            signature.recover(transaction.sighash()).unwrap(),
+            wallet.address()
+        );
+        assert_eq!(transaction.chain_id(), Some(5.into()));
+        assert_eq!(transaction.nonce(), Some(&parameters.nonce));
+        assert_eq!(transaction.gas(), Some(&parameters.gas));
+        assert_eq!(
+            transaction.to_addr(),
+            Some(&types::Address::repeat_byte(0xfe))
+        );
+        assert_eq!(
+            transaction.data().unwrap().to_vec(),
+            calldata(&payload, Entrypoint::Multiprocess)
+        );
+
+        let TypedTransaction::Eip1559(transaction) = transaction else {
+            panic!("expected an EIP-1559 transaction");
+        };
+
+        assert_eq!(
+            transaction.max_fee_per_gas,
+            Some(parameters.max_fee_per_gas)
+        );
+        assert_eq!(
+            transaction.max_priority_fee_per_gas,
+            Some(parameters.max_priority_fee_per_gas)
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! End-to-end tests against a local anvil node, run with `cargo test --features anvil -- --ignored`.
+//! They require anvil to be installed and `FOLIO_FVM_ARTIFACT` to name the FVM contract's artifact.
+
+#![cfg(feature = "anvil")]
+
+use compiler::{
+    anvil::Harness,
+    builder::{Pool, Program},
+    codegen::Codegen,
+    opcode::{self, Address},
+    simulator::State,
+    transaction::Entrypoint,
+};
+use ethers::{
+    abi::{self, Token},
+    types::{self, U256},
+    utils,
+};
+
+fn address(token: types::Address) -> Address {
+    Address::from_slice(token.as_bytes())
+}
+
+/// The id the FVM assigned to the pair of `token_0` and `token_1`.
+fn pair_id(harness: &Harness, token_0: types::Address, token_1: types::Address) -> U256 {
+    let mut calldata = utils::id("getPairId(address,address)").to_vec();
+    calldata.extend(abi::encode(&[
+        Token::Address(token_0),
+        Token::Address(token_1),
+    ]));
+
+    U256::from_big_endian(&harness.call(calldata).unwrap())
+}
+
+/// Create a pair of two tokens through `entrypoint`, and check the FVM assigned it the first pair id.
+fn create_pair(entrypoint: Entrypoint) {
+    let harness = Harness::spawn().unwrap();
+    let (token_0, token_1) = (harness.token().unwrap(), harness.token().unwrap());
+
+    let opcodes = Program::new()
+        .create_pair(address(token_0), address(token_1))
+        .build()
+        .unwrap();
+    let payload = Codegen::from(opcodes).payload().unwrap();
+
+    harness.process(&payload, entrypoint).unwrap();
+
+    assert_eq!(pair_id(&harness, token_0, token_1), U256::one());
+}
+
+#[test]
+#[ignore = "requires anvil and `FOLIO_FVM_ARTIFACT`"]
+fn create_pair_through_fallback() {
+    create_pair(Entrypoint::Fallback);
+}
+
+#[test]
+#[ignore = "requires anvil and `FOLIO_FVM_ARTIFACT`"]
+fn create_pair_through_multiprocess() {
+    create_pair(Entrypoint::Multiprocess);
+}
+
+/// Create a pair and a pool, provide liquidity and swap against it, all in one payload,
+/// referring to the new pair and pool by the id `0`.
+#[test]
+#[ignore = "requires anvil and `FOLIO_FVM_ARTIFACT`"]
+fn allocate_and_swap() {
+    let harness = Harness::spawn().unwrap();
+    let (token_0, token_1) = (harness.token().unwrap(), harness.token().unwrap());
+    let wad = opcode::U256::exp10(18);
+
+    let opcodes = Program::new()
+        .create_pair(address(token_0), address(token_1))
+        .create_pool(Pool {
+            pair_id: opcode::U256::zero(),
+            controller: Address::zero(),
+            priority_fee: opcode::U256::zero(),
+            fee: opcode::U256::from(30),
+            vol: opcode::U256::from(10_000),
+            dur: opcode::U256::from(365),
+            jit: opcode::U256::zero(),
+            max_price: wad * 2,
+            price: wad,
+        })
+        .allocate(false, 0, wad)
+        .swap(false, 0, wad / 1000, 1, true)
+        .build()
+        .unwrap();
+
+    // The program is one the simulator expects to succeed, given enough of both tokens.
+    let mut state = State::new();
+    state.balances.insert(address(token_0), 1e30);
+    state.balances.insert(address(token_1), 1e30);
+    assert_eq!(state.simulate(&opcodes).revert, None);
+
+    let payload = Codegen::from(opcodes).payload().unwrap();
+    harness.process(&payload, Entrypoint::Multiprocess).unwrap();
+
+    assert_eq!(pair_id(&harness, token_0, token_1), U256::one());
+}
@@ -0,0 +1,324 @@
+//! # Macros
+//!
+//! Procedural macros for embedding folio programs in Rust.
+//! [`fvm!`] lexes, parses and encodes its input with the same stages as the folio compiler,
+//! so a program that builds with `folio build` builds identically inside Rust source.
+
+use std::collections::HashMap;
+
+use compiler::{
+    assembler::{Assembler, Expression},
+    codegen::Codegen,
+    opcode::{Argument, Opcode},
+    spec::{self, Kind},
+    token::{self, Token},
+};
+use proc_macro::TokenStream;
+use proc_macro2::{Delimiter, Span, TokenStream as TokenStream2, TokenTree};
+use quote::{quote, quote_spanned, ToTokens};
+
+/// Compile a folio program into its encoded payload.
+///
+/// The input is either a set of macro definitions including `main`, or a bare list of
+/// instructions, which is treated as the body of `main`.
+/// Rust values can be interpolated into any parameter with `#ident` or `#(expression)`.
+/// Programs without interpolations are encoded at compile time; otherwise the opcodes are
+/// built at compile time and encoded at runtime.
+///
+/// Expands to a [`String`], and requires the `compiler` crate to be a dependency of the caller.
+/// Literal parameters are validated at compile time, and interpolated ones when the program is encoded.
+///
+/// # Panics
+///
+/// If an interpolated value does not fit in the parameter it is given for.
+///
+/// ```
+/// use macros::fvm;
+///
+/// let pool_id = 34u64;
+/// let calldata = fvm! { swap: useMax: 0 poolId: #pool_id amount0: 10 amount1: 20 sellAsset: 1 };
+///
+/// assert_eq!(
+///     calldata,
+///     fvm! { swap: useMax: 0 poolId: 34 amount0: 10 amount1: 20 sellAsset: 1 }
+/// );
+/// ```
+#[proc_macro]
+pub fn fvm(input: TokenStream) -> TokenStream {
+    match expand(input.into()) {
+        Ok(expanded) => expanded,
+        Err((message, span)) => quote_spanned! {span=> ::core::compile_error!(#message) },
+    }
+    .into()
+}
+
+fn expand(input: TokenStream2) -> Result<TokenStream2, (String, Span)> {
+    let mut source = Source::default();
+    let wrap = !input
+        .clone()
+        .into_iter()
+        .any(|tree| matches!(&tree, TokenTree::Ident(ident) if ident == "macro"));
+
+    if wrap {
+        source.push("macro main {", Span::call_site());
+    }
+
+    source.extend(input)?;
+
+    if wrap {
+        source.push("}", Span::call_site());
+    }
+
+    let tokens = Token::lex(&source.text);
+    let assembler = Assembler::new(tokens.clone()).with_placeholders(&source.placeholders);
+    let body = assembler
+        .parse_macros()
+        .and_then(|macros| Assembler::expand(&macros, "main"))
+        .map_err(|error| (error.message, source.rust_span(error.span)))?;
+
+    // Interpolations do not change the number or length of instructions, so the payload is checked as parsed.
+    let codegen = Codegen::new(
+        body.iter()
+            .map(|expression| expression.node.clone())
+            .collect(),
+    );
+    let payload =
+        Codegen::generate(codegen.encode()).map_err(|message| (message, Span::call_site()))?;
+
+    if source.interpolations.is_empty() {
+        return Ok(quote! { ::std::string::String::from(#payload) });
+    }
+
+    let opcodes = body.iter().map(|expression| match &expression.node {
+        Expression::Opcode(opcode) => {
+            source.quote_opcode(opcode, &source.interpolated(&tokens, expression.span))
+        }
+        Expression::Invocation(_) => unreachable!("invocations are expanded by the assembler"),
+    });
+
+    // Interpolated values were skipped by the assembler, so every argument is validated once they are known.
+    Ok(quote! {{
+        let opcodes = ::std::vec![#(#opcodes),*];
+
+        for opcode in &opcodes {
+            for (parameter, argument) in opcode.arguments() {
+                if let ::core::result::Result::Err(message) = parameter.validate(&argument) {
+                    ::core::panic!("invalid interpolation in `{}`: {}", opcode.instruction().name, message);
+                }
+            }
+        }
+
+        let codegen = ::compiler::codegen::Codegen::from(opcodes);
+        match ::compiler::codegen::Codegen::generate(codegen.encode()) {
+            ::core::result::Result::Ok(payload) => payload,
+            ::core::result::Result::Err(message) => ::core::panic!("{}", message),
+        }
+    }})
+}
+
+/// Folio source rebuilt from a Rust token stream.
+#[derive(Default)]
+struct Source {
+    /// Source text handed to the lexer.
+    text: String,
+    /// Each span of `text`, paired with the Rust span it was built from.
+    spans: Vec<(token::Span, Span)>,
+    /// Interpolated Rust expressions, in order of appearance.
+    interpolations: Vec<TokenStream2>,
+    /// Spans of `text` holding the placeholder of each interpolation, in order of appearance.
+    placeholders: Vec<token::Span>,
+}
+
+impl Source {
+    fn push(&mut self, text: &str, span: Span) {
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.spans
+            .push((token::Span::new(start, self.text.len()), span));
+        self.text.push(' ');
+    }
+
+    fn extend(&mut self, stream: TokenStream2) -> Result<(), (String, Span)> {
+        let mut trees = stream.into_iter();
+
+        while let Some(tree) = trees.next() {
+            match tree {
+                TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                    self.push("{", group.span_open());
+                    self.extend(group.stream())?;
+                    self.push("}", group.span_close());
+                }
+                TokenTree::Punct(punct) if punct.as_char() == '#' => {
+                    let expression = match trees.next() {
+                        Some(TokenTree::Ident(ident)) => ident.into_token_stream(),
+                        Some(TokenTree::Group(group))
+                            if group.delimiter() == Delimiter::Parenthesis =>
+                        {
+                            group.stream()
+                        }
+                        _ => {
+                            return Err((
+                                "expected an identifier or parenthesized expression after `#`"
+                                    .to_string(),
+                                punct.span(),
+                            ))
+                        }
+                    };
+
+                    self.interpolations.push(expression);
+
+                    let placeholder = if self
+                        .previous_key()
+                        .and_then(spec::parameter)
+                        .is_some_and(|parameter| parameter.kind == Kind::Address)
+                    {
+                        PLACEHOLDER_ADDRESS
+                    } else {
+                        "0"
+                    };
+
+                    self.push(placeholder, punct.span());
+                    self.placeholders.push(self.spans.last().unwrap().0);
+                }
+                other => self.push(&other.to_string(), other.span()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The parameter key preceding the trailing colon, if any.
+    fn previous_key(&self) -> Option<&str> {
+        let mut words = self.text.split_whitespace().rev();
+
+        match (words.next(), words.next()) {
+            (Some(":"), key) => key,
+            _ => None,
+        }
+    }
+
+    /// The Rust span a span of the rebuilt source originated from.
+    fn rust_span(&self, span: token::Span) -> Span {
+        self.spans
+            .iter()
+            .find(|(range, _)| range.end > span.start)
+            .map(|(_, span)| *span)
+            .unwrap_or_else(Span::call_site)
+    }
+
+    /// The interpolation given for each parameter of the instruction at `span`, keyed by parameter name.
+    /// Placeholders are matched by position, so a literal equal to a placeholder is never mistaken for one.
+    fn interpolated<'a>(&self, tokens: &[Token<'a>], span: token::Span) -> HashMap<&'a str, usize> {
+        tokens
+            .windows(3)
+            .filter(|window| span.start <= window[2].span.start && window[2].span.end <= span.end)
+            .filter_map(|window| {
+                let index = self
+                    .placeholders
+                    .iter()
+                    .position(|placeholder| *placeholder == window[2].span)?;
+
+                Some((window[0].slice, index))
+            })
+            .collect()
+    }
+
+    /// Each argument of `opcode`, as an expression of its type: the interpolated Rust expression if any, or the literal.
+    fn quote_arguments(
+        &self,
+        opcode: &Opcode,
+        interpolated: &HashMap<&str, usize>,
+    ) -> Vec<TokenStream2> {
+        opcode
+            .arguments()
+            .into_iter()
+            .map(|(parameter, argument)| {
+                match (interpolated.get(parameter.name), argument) {
+                    (Some(index), Argument::Address(_)) => {
+                        let expression = &self.interpolations[*index];
+                        quote! { ::core::convert::Into::<::compiler::opcode::Address>::into(#expression) }
+                    }
+                    (Some(index), Argument::Integer(_)) => {
+                        let expression = &self.interpolations[*index];
+                        quote! { ::core::convert::Into::<::compiler::opcode::U256>::into(#expression) }
+                    }
+                    (None, Argument::Address(address)) => {
+                        let bytes = address.0;
+                        quote! { ::compiler::opcode::Address::from([#(#bytes),*]) }
+                    }
+                    (None, Argument::Integer(value)) => {
+                        let limbs = value.0;
+                        quote! { ::compiler::opcode::U256([#(#limbs),*]) }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn quote_opcode(&self, opcode: &Opcode, interpolated: &HashMap<&str, usize>) -> TokenStream2 {
+        match (
+            opcode,
+            self.quote_arguments(opcode, interpolated).as_slice(),
+        ) {
+            (Opcode::Unknown, _) => quote! { ::compiler::opcode::Opcode::Unknown },
+            (Opcode::Allocate { .. }, [use_max, pool_id, delta_liquidity]) => quote! {
+                ::compiler::opcode::Opcode::Allocate {
+                    use_max: #use_max,
+                    pool_id: #pool_id,
+                    delta_liquidity: #delta_liquidity,
+                }
+            },
+            (Opcode::Deallocate { .. }, [use_max, pool_id, delta_liquidity]) => quote! {
+                ::compiler::opcode::Opcode::Deallocate {
+                    use_max: #use_max,
+                    pool_id: #pool_id,
+                    delta_liquidity: #delta_liquidity,
+                }
+            },
+            (Opcode::CreatePair { .. }, [token_0, token_1]) => quote! {
+                ::compiler::opcode::Opcode::CreatePair {
+                    token_0: #token_0,
+                    token_1: #token_1,
+                }
+            },
+            (
+                Opcode::CreatePool { .. },
+                [pair_id, controller, priority_fee, fee, vol, dur, jit, max_price, price],
+            ) => quote! {
+                ::compiler::opcode::Opcode::CreatePool {
+                    pair_id: #pair_id,
+                    controller: #controller,
+                    priority_fee: #priority_fee,
+                    fee: #fee,
+                    vol: #vol,
+                    dur: #dur,
+                    jit: #jit,
+                    max_price: #max_price,
+                    price: #price,
+                }
+            },
+            (Opcode::Swap { .. }, [use_max, pool_id, amount_0, amount_1, sell_asset]) => quote! {
+                ::compiler::opcode::Opcode::Swap {
+                    use_max: #use_max,
+                    pool_id: #pool_id,
+                    amount_0: #amount_0,
+                    amount_1: #amount_1,
+                    sell_asset: #sell_asset,
+                }
+            },
+            (Opcode::Claim { .. }, [pool_id, fee_0, fee_1]) => quote! {
+                ::compiler::opcode::Opcode::Claim {
+                    pool_id: #pool_id,
+                    fee_0: #fee_0,
+                    fee_1: #fee_1,
+                }
+            },
+            (Opcode::Jump, _) => quote! { ::compiler::opcode::Opcode::Jump },
+            _ => unreachable!("every opcode has one argument per parameter"),
+        }
+    }
+}
+
+/// Placeholder standing in for an interpolated address while parsing.
+/// Interpolations are found by the position of their placeholder, never by its value.
+const PLACEHOLDER_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
@@ -0,0 +1,129 @@
+use compiler::{assembler::Assembler, codegen::Codegen, token::Token};
+use macros::fvm;
+
+/// The payload `folio build` produces for `source`.
+fn build(source: &str) -> String {
+    let expressions = Assembler::parse(Token::lex(source)).unwrap();
+    Codegen::generate(Codegen::new(expressions).encode()).unwrap()
+}
+
+#[test]
+fn bare_instructions() {
+    assert_eq!(
+        fvm! { swap: poolId: 34 amount0: 10 amount1: 20 sellAsset: 1 useMax: 0 },
+        build("macro main { swap: useMax: 0 poolId: 34 amount0: 10 amount1: 20 sellAsset: 1 }")
+    );
+}
+
+#[test]
+fn macro_definitions() {
+    let calldata = fvm! {
+        macro provide {
+            allocate: useMax: 0 poolId: 34 deltaLiquidity: 675
+        }
+
+        macro main {
+            createPair: token0: 0x0000000000000000000000000000000000000001 token1: 0x0000000000000000000000000000000000000002
+            provide
+            claim: poolId: 34 fee0: 5 fee1: 25000
+        }
+    };
+
+    assert_eq!(
+        calldata,
+        build(
+            "macro provide { allocate: useMax: 0 poolId: 34 deltaLiquidity: 675 }
+            macro main {
+                createPair: token0: 0x0000000000000000000000000000000000000001 token1: 0x0000000000000000000000000000000000000002
+                provide
+                claim: poolId: 34 fee0: 5 fee1: 25000
+            }"
+        )
+    );
+}
+
+#[test]
+fn interpolated_integers() {
+    let pool_id = u64::MAX;
+    let amounts = (10u64, 20u64);
+
+    assert_eq!(
+        fvm! { swap: useMax: 0 poolId: #pool_id amount0: #(amounts.0) amount1: #(amounts.1) sellAsset: 1 },
+        build(&format!(
+            "macro main {{ swap: useMax: 0 poolId: {pool_id} amount0: 10 amount1: 20 sellAsset: 1 }}"
+        ))
+    );
+}
+
+#[test]
+fn interpolated_addresses() {
+    let token = compiler::opcode::Address::repeat_byte(0xab);
+
+    assert_eq!(
+        fvm! { createPair: token0: #token token1: 0x0000000000000000000000000000000000000001 },
+        build(&format!(
+            "macro main {{ createPair: token0: {token:?} token1: 0x0000000000000000000000000000000000000001 }}"
+        ))
+    );
+}
+
+#[test]
+fn literals_equal_to_placeholders() {
+    // Placeholders are found by position, so literals next to an interpolation keep their own values.
+    let token = compiler::opcode::Address::repeat_byte(0xab);
+    let pool_id = 34u64;
+
+    assert_eq!(
+        fvm! {
+            createPair: token0: #token token1: 0xffffffffffffffffffffffffffffffffffffffff
+            createPair: token0: 0x0000000000000000000000000000000000000000 token1: #token
+            swap: useMax: 0 poolId: #pool_id amount0: 0 amount1: 0 sellAsset: 1
+        },
+        build(&format!(
+            "macro main {{
+                createPair: token0: {token:?} token1: 0xffffffffffffffffffffffffffffffffffffffff
+                createPair: token0: 0x0000000000000000000000000000000000000000 token1: {token:?}
+                swap: useMax: 0 poolId: 34 amount0: 0 amount1: 0 sellAsset: 1
+            }}"
+        ))
+    );
+}
+
+#[test]
+fn interpolations_in_macros() {
+    let amount = 675u64;
+
+    assert_eq!(
+        fvm! {
+            macro provide {
+                allocate: useMax: 0 poolId: 34 deltaLiquidity: #amount
+            }
+
+            macro main {
+                provide
+                deallocate: useMax: 0 poolId: 34 deltaLiquidity: 0
+                provide
+            }
+        },
+        build(
+            "macro main {
+                allocate: useMax: 0 poolId: 34 deltaLiquidity: 675
+                deallocate: useMax: 0 poolId: 34 deltaLiquidity: 0
+                allocate: useMax: 0 poolId: 34 deltaLiquidity: 675
+            }"
+        )
+    );
+}
+
+#[test]
+#[should_panic(expected = "`poolId` must fit in 64 bits")]
+fn interpolated_value_out_of_range() {
+    let pool_id = u128::MAX;
+
+    fvm! { swap: poolId: #pool_id amount0: 10 amount1: 20 sellAsset: 1 };
+}
+
+#[test]
+fn compile_errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,5 @@
+use macros::fvm;
+
+fn main() {
+    fvm! { swap: poolId: 34 deltaLiquidity: 10 amount1: 20 sellAsset: 1 };
+}
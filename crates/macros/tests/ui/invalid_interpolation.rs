@@ -0,0 +1,5 @@
+use macros::fvm;
+
+fn main() {
+    fvm! { allocate: poolId: #[34] deltaLiquidity: 675 };
+}
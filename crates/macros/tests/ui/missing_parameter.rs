@@ -0,0 +1,5 @@
+use macros::fvm;
+
+fn main() {
+    fvm! { claim: poolId: 34 fee0: 5 };
+}
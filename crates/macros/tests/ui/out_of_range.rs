@@ -0,0 +1,9 @@
+use macros::fvm;
+
+fn main() {
+    fvm! {
+        macro main {
+            allocate: poolId: 18446744073709551616 deltaLiquidity: 675
+        }
+    };
+}